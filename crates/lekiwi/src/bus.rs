@@ -1,8 +1,10 @@
 use tokio::sync::broadcast;
 
 use crate::messages::{
-    CameraFrame, Diagnostics, EstopCommand, LogControl, LogStatus, Odometry, PowerState,
-    ServoStateArray, SkillCommand, VelocityCommand,
+    CameraFrame, CameraStats, Diagnostics, EstopCommand, EstopReset, EstopState, Imu, LogControl,
+    LogStatus, Odometry, PoseReset, PowerState, SafetyEvent, ServoBusHealth, ServoStateArray,
+    SetPoseCommand, SkillCancel, SkillCommand, SkillStatus, SpeedScaleCommand, VelocityCommand,
+    WheelTargetArray,
 };
 
 const CHANNEL_SIZE: usize = 64;
@@ -11,43 +13,79 @@ const CHANNEL_SIZE: usize = 64;
 pub struct Bus {
     pub cmd_in: broadcast::Sender<VelocityCommand>,
     pub cmd_out: broadcast::Sender<VelocityCommand>,
+    pub wheel_targets: broadcast::Sender<WheelTargetArray>,
     pub cmd_skill: broadcast::Sender<SkillCommand>,
+    pub cmd_skill_cancel: broadcast::Sender<SkillCancel>,
+    pub skill_status: broadcast::Sender<SkillStatus>,
     pub cmd_estop: broadcast::Sender<EstopCommand>,
+    pub cmd_estop_reset: broadcast::Sender<EstopReset>,
+    pub estop_state: broadcast::Sender<EstopState>,
+    pub cmd_speed_scale: broadcast::Sender<SpeedScaleCommand>,
+    pub cmd_set_pose: broadcast::Sender<SetPoseCommand>,
     pub log_control: broadcast::Sender<LogControl>,
     pub log_status: broadcast::Sender<LogStatus>,
     pub odometry: broadcast::Sender<Odometry>,
+    pub pose_reset: broadcast::Sender<PoseReset>,
     pub servos: broadcast::Sender<ServoStateArray>,
+    pub servo_health: broadcast::Sender<ServoBusHealth>,
     pub power: broadcast::Sender<PowerState>,
     pub diagnostics: broadcast::Sender<Diagnostics>,
+    pub safety_event: broadcast::Sender<SafetyEvent>,
+    pub imu: broadcast::Sender<Imu>,
     pub camera: broadcast::Sender<CameraFrame>,
+    pub camera_stats: broadcast::Sender<CameraStats>,
 }
 
 impl Bus {
     pub fn new() -> Self {
         let (cmd_in, _) = broadcast::channel(CHANNEL_SIZE);
         let (cmd_out, _) = broadcast::channel(CHANNEL_SIZE);
+        let (wheel_targets, _) = broadcast::channel(CHANNEL_SIZE);
         let (cmd_skill, _) = broadcast::channel(CHANNEL_SIZE);
+        let (cmd_skill_cancel, _) = broadcast::channel(CHANNEL_SIZE);
+        let (skill_status, _) = broadcast::channel(CHANNEL_SIZE);
         let (cmd_estop, _) = broadcast::channel(CHANNEL_SIZE);
+        let (cmd_estop_reset, _) = broadcast::channel(CHANNEL_SIZE);
+        let (estop_state, _) = broadcast::channel(CHANNEL_SIZE);
+        let (cmd_speed_scale, _) = broadcast::channel(CHANNEL_SIZE);
+        let (cmd_set_pose, _) = broadcast::channel(CHANNEL_SIZE);
         let (log_control, _) = broadcast::channel(CHANNEL_SIZE);
         let (log_status, _) = broadcast::channel(CHANNEL_SIZE);
         let (odometry, _) = broadcast::channel(CHANNEL_SIZE);
+        let (pose_reset, _) = broadcast::channel(CHANNEL_SIZE);
         let (servos, _) = broadcast::channel(CHANNEL_SIZE);
+        let (servo_health, _) = broadcast::channel(CHANNEL_SIZE);
         let (power, _) = broadcast::channel(CHANNEL_SIZE);
         let (diagnostics, _) = broadcast::channel(CHANNEL_SIZE);
+        let (safety_event, _) = broadcast::channel(CHANNEL_SIZE);
+        let (imu, _) = broadcast::channel(CHANNEL_SIZE);
         let (camera, _) = broadcast::channel(CHANNEL_SIZE);
+        let (camera_stats, _) = broadcast::channel(CHANNEL_SIZE);
 
         Self {
             cmd_in,
             cmd_out,
+            wheel_targets,
             cmd_skill,
+            cmd_skill_cancel,
+            skill_status,
             cmd_estop,
+            cmd_estop_reset,
+            estop_state,
+            cmd_speed_scale,
+            cmd_set_pose,
             log_control,
             log_status,
             odometry,
+            pose_reset,
             servos,
+            servo_health,
             power,
             diagnostics,
+            safety_event,
+            imu,
             camera,
+            camera_stats,
         }
     }
 }
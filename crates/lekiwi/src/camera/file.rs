@@ -0,0 +1,206 @@
+use std::fs;
+use std::time::{Duration, Instant};
+
+use anyhow::{ensure, Context, Result};
+
+use crate::camera::{CameraSource, CapturedFrame};
+use crate::config::CameraConfig;
+use crate::utils::now_nanos;
+
+/// Replays a recorded MJPEG stream, e.g. `ffmpeg -i in.mp4 -c:v mjpeg -f
+/// mjpeg out.mjpeg`, at the configured rate. Lets the camera pipeline run on
+/// a machine without the robot's cameras.
+pub struct FileCamera {
+    path: String,
+    data: Vec<u8>,
+    /// Byte range of each JPEG in `data`.
+    frames: Vec<(usize, usize)>,
+    next: usize,
+    period: Duration,
+    due: Instant,
+    width: u32,
+    height: u32,
+}
+
+impl FileCamera {
+    pub fn open(config: &CameraConfig) -> Result<Self> {
+        ensure!(
+            config.format == "mjpeg",
+            "file backend only replays mjpeg, not '{}'",
+            config.format
+        );
+        let data = fs::read(&config.device)
+            .with_context(|| format!("unable to read {}", config.device))?;
+        let frames = split_jpegs(&data);
+        ensure!(!frames.is_empty(), "no JPEG frames in {}", config.device);
+
+        Ok(Self {
+            path: config.device.clone(),
+            data,
+            frames,
+            next: 0,
+            period: Duration::from_secs_f64(1.0 / config.fps.max(1) as f64),
+            due: Instant::now(),
+            width: config.width,
+            height: config.height,
+        })
+    }
+}
+
+impl CameraSource for FileCamera {
+    fn describe(&self) -> String {
+        format!("{} ({} frames)", self.path, self.frames.len())
+    }
+
+    fn next_frame(&mut self, timeout: Duration) -> Result<Option<CapturedFrame>> {
+        let wait = self.due.saturating_duration_since(Instant::now());
+        if wait > timeout {
+            std::thread::sleep(timeout);
+            return Ok(None);
+        }
+        std::thread::sleep(wait);
+        // Keep the cadence, but do not try to catch up after a stall.
+        self.due = (self.due + self.period).max(Instant::now());
+
+        let (start, end) = self.frames[self.next];
+        self.next = (self.next + 1) % self.frames.len();
+        Ok(Some(CapturedFrame {
+            timestamp_ns: now_nanos(),
+            sequence: None,
+            width: self.width,
+            height: self.height,
+            encoding: "jpeg".to_string(),
+            data: self.data[start..end].to_vec(),
+        }))
+    }
+}
+
+/// Splits concatenated JPEGs where an end-of-image marker is directly
+/// followed by a start-of-image marker.
+fn split_jpegs(data: &[u8]) -> Vec<(usize, usize)> {
+    const SOI: [u8; 3] = [0xFF, 0xD8, 0xFF];
+    const EOI: [u8; 2] = [0xFF, 0xD9];
+
+    let starts: Vec<usize> = (0..data.len().saturating_sub(2))
+        .filter(|&i| data[i..i + 3] == SOI && (i == 0 || i >= 2 && data[i - 2..i] == EOI))
+        .collect();
+    starts
+        .iter()
+        .enumerate()
+        .map(|(i, &start)| (start, starts.get(i + 1).copied().unwrap_or(data.len())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+
+    fn jpeg(fill: u8, len: usize) -> Vec<u8> {
+        let mut data = vec![0xFF, 0xD8, 0xFF, 0xE0];
+        data.extend(std::iter::repeat_n(fill, len));
+        data.extend([0xFF, 0xD9]);
+        data
+    }
+
+    fn config(path: &Path, fps: u32, format: &str) -> CameraConfig {
+        serde_yaml::from_str(&format!(
+            "name: test
+device: {}
+width: 320
+height: 240
+fps: {fps}
+format: {format}
+auto_exposure: true
+auto_focus: true
+flip_horizontal: false
+flip_vertical: false
+backend: file",
+            path.display()
+        ))
+        .unwrap()
+    }
+
+    /// Writes `data` to a file unique to the calling test.
+    fn recording(test: &str, data: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "lekiwi-file-camera-{}-{test}.mjpeg",
+            std::process::id()
+        ));
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn splits_concatenated_jpegs() {
+        let frames = [jpeg(1, 10), jpeg(2, 3), jpeg(3, 0)];
+        let data = frames.concat();
+        let expected = vec![(0, 16), (16, 25), (25, 31)];
+        assert_eq!(split_jpegs(&data), expected);
+    }
+
+    #[test]
+    fn start_marker_inside_a_frame_is_not_a_split() {
+        let mut first = jpeg(1, 4);
+        first.splice(5..5, [0xFF, 0xD8, 0xFF]);
+        let data = [first.clone(), jpeg(2, 4)].concat();
+        assert_eq!(
+            split_jpegs(&data),
+            vec![(0, first.len()), (first.len(), data.len())]
+        );
+    }
+
+    #[test]
+    fn no_frames_in_garbage() {
+        assert!(split_jpegs(&[0x00, 0xFF, 0xD9, 0x12]).is_empty());
+        assert!(split_jpegs(&[]).is_empty());
+    }
+
+    #[test]
+    fn open_rejects_bad_input() {
+        let empty = recording("empty", &[1, 2, 3, 4]);
+        assert!(FileCamera::open(&config(&empty, 10, "mjpeg")).is_err());
+        let valid = recording("format", &jpeg(1, 4));
+        assert!(FileCamera::open(&config(&valid, 10, "yuyv")).is_err());
+        let missing = std::env::temp_dir().join("lekiwi-file-camera-missing.mjpeg");
+        assert!(FileCamera::open(&config(&missing, 10, "mjpeg")).is_err());
+        fs::remove_file(empty).unwrap();
+        fs::remove_file(valid).unwrap();
+    }
+
+    #[test]
+    fn replays_frames_in_order_and_loops() {
+        let frames = [jpeg(1, 8), jpeg(2, 4)];
+        let path = recording("loop", &frames.concat());
+        let mut camera = FileCamera::open(&config(&path, 1000, "mjpeg")).unwrap();
+        assert_eq!(camera.describe(), format!("{} (2 frames)", path.display()));
+
+        for expected in [&frames[0], &frames[1], &frames[0]] {
+            let frame = camera
+                .next_frame(Duration::from_millis(100))
+                .unwrap()
+                .unwrap();
+            assert_eq!(&frame.data, expected);
+            assert_eq!((frame.width, frame.height), (320, 240));
+            assert_eq!(frame.encoding, "jpeg");
+            assert_eq!(frame.sequence, None);
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn paces_frames_at_the_configured_rate() {
+        let path = recording("pace", &jpeg(1, 4));
+        let mut camera = FileCamera::open(&config(&path, 5, "mjpeg")).unwrap();
+
+        let timeout = Duration::from_millis(10);
+        assert!(camera.next_frame(timeout).unwrap().is_some());
+        assert!(camera.next_frame(timeout).unwrap().is_none());
+
+        let start = Instant::now();
+        assert!(camera.next_frame(Duration::from_secs(1)).unwrap().is_some());
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,54 @@
+pub mod file;
+pub mod stats;
+#[cfg(target_pointer_width = "64")]
+pub mod v4l2;
+
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::config::{CameraBackend, CameraConfig};
+
+/// One frame as delivered by a capture backend.
+#[derive(Debug, Clone)]
+pub struct CapturedFrame {
+    /// UNIX time the frame was captured, from the driver buffer when the
+    /// backend has one.
+    pub timestamp_ns: u64,
+    /// Driver frame counter, when the backend has one; gaps are drops.
+    pub sequence: Option<u32>,
+    pub width: u32,
+    pub height: u32,
+    /// CompressedImage format, e.g. `jpeg` for MJPEG passthrough.
+    pub encoding: String,
+    pub data: Vec<u8>,
+}
+
+/// Blocking frame source. Each camera's capture thread owns one.
+pub trait CameraSource: Send {
+    fn describe(&self) -> String;
+
+    /// Waits up to `timeout` for the next frame; `Ok(None)` if none arrived.
+    fn next_frame(&mut self, timeout: Duration) -> Result<Option<CapturedFrame>>;
+}
+
+pub fn open(config: &CameraConfig) -> Result<Box<dyn CameraSource>> {
+    match config.backend {
+        CameraBackend::V4l2 => open_v4l2(config),
+        CameraBackend::File => Ok(Box::new(file::FileCamera::open(config)?)),
+    }
+}
+
+#[cfg(target_pointer_width = "64")]
+fn open_v4l2(config: &CameraConfig) -> Result<Box<dyn CameraSource>> {
+    Ok(Box::new(v4l2::V4l2Camera::open(config)?))
+}
+
+/// The V4L2 structs are only laid out for 64-bit Linux.
+#[cfg(not(target_pointer_width = "64"))]
+fn open_v4l2(config: &CameraConfig) -> Result<Box<dyn CameraSource>> {
+    anyhow::bail!(
+        "camera '{}': the v4l2 backend is unsupported on 32-bit targets; use backend: file",
+        config.name
+    )
+}
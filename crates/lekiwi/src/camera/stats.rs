@@ -0,0 +1,199 @@
+use std::time::{Duration, Instant};
+
+use crate::camera::CapturedFrame;
+use crate::messages::CameraStats;
+use crate::utils::now_nanos;
+
+/// A frame this much later than the nominal period counts as late.
+const LATE_FACTOR: f64 = 1.5;
+
+/// Frame counters for one camera, kept across reconnects.
+pub struct FrameStats {
+    name: String,
+    fps_target: u32,
+    period_ns: u64,
+    frames: u64,
+    dropped: u64,
+    late: u64,
+    reconnects: u64,
+    /// Capture time and sequence of the previous frame in this stream.
+    previous: Option<(u64, Option<u32>)>,
+    /// Last frame, or when the device was opened if none arrived since.
+    last_activity: Option<Instant>,
+    window_start: Instant,
+    window_frames: u32,
+}
+
+impl FrameStats {
+    pub fn new(name: &str, fps_target: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            fps_target,
+            period_ns: 1_000_000_000 / fps_target.max(1) as u64,
+            frames: 0,
+            dropped: 0,
+            late: 0,
+            reconnects: 0,
+            previous: None,
+            last_activity: None,
+            window_start: Instant::now(),
+            window_frames: 0,
+        }
+    }
+
+    /// A device was opened; sequence numbers restart with the new stream.
+    pub fn opened(&mut self) {
+        if self.last_activity.is_some() {
+            self.reconnects += 1;
+        }
+        self.previous = None;
+        self.last_activity = Some(Instant::now());
+    }
+
+    pub fn record(&mut self, frame: &CapturedFrame) {
+        self.frames += 1;
+        self.window_frames += 1;
+        self.last_activity = Some(Instant::now());
+
+        if let Some((timestamp_ns, sequence)) = self.previous {
+            let skipped = match (sequence, frame.sequence) {
+                (Some(prev), Some(seq)) => seq.wrapping_sub(prev).saturating_sub(1) as u64,
+                _ => 0,
+            };
+            let gap_ns = frame.timestamp_ns.saturating_sub(timestamp_ns) as f64;
+            if skipped > 0 {
+                self.dropped += skipped;
+            } else if gap_ns > self.period_ns as f64 * LATE_FACTOR {
+                self.late += 1;
+            }
+        }
+        self.previous = Some((frame.timestamp_ns, frame.sequence));
+    }
+
+    /// Time since the last frame or since the device was opened.
+    pub fn idle_for(&self) -> Option<Duration> {
+        self.last_activity.map(|at| at.elapsed())
+    }
+
+    /// Builds the report and starts a new fps window.
+    pub fn report(&mut self, connected: bool, last_error: Option<String>) -> CameraStats {
+        let elapsed = self.window_start.elapsed().as_secs_f32();
+        let fps_measured = if elapsed > 0.0 {
+            self.window_frames as f32 / elapsed
+        } else {
+            0.0
+        };
+        self.window_start = Instant::now();
+        self.window_frames = 0;
+
+        CameraStats {
+            timestamp_ns: now_nanos(),
+            camera_name: self.name.clone(),
+            connected,
+            fps_target: self.fps_target,
+            fps_measured,
+            frames: self.frames,
+            dropped_frames: self.dropped,
+            late_frames: self.late,
+            reconnects: self.reconnects,
+            last_frame_age_s: self.idle_for().map(|age| age.as_secs_f64()),
+            last_error,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS_NS: u64 = 1_000_000;
+
+    fn frame(timestamp_ms: u64, sequence: Option<u32>) -> CapturedFrame {
+        CapturedFrame {
+            timestamp_ns: 1_700_000_000_000 * MS_NS + timestamp_ms * MS_NS,
+            sequence,
+            width: 320,
+            height: 240,
+            encoding: "jpeg".to_string(),
+            data: Vec::new(),
+        }
+    }
+
+    fn stats_after(frames: &[CapturedFrame]) -> CameraStats {
+        let mut stats = FrameStats::new("front", 10);
+        stats.opened();
+        for frame in frames {
+            stats.record(frame);
+        }
+        stats.report(true, None)
+    }
+
+    #[test]
+    fn sequence_gaps_count_as_dropped() {
+        let report = stats_after(&[
+            frame(0, Some(1)),
+            frame(100, Some(2)),
+            frame(400, Some(5)),
+            frame(500, Some(6)),
+        ]);
+        assert_eq!(report.frames, 4);
+        assert_eq!(report.dropped_frames, 2);
+        // The gap is already counted as drops, not also as a late frame.
+        assert_eq!(report.late_frames, 0);
+    }
+
+    #[test]
+    fn sequence_wraparound_is_not_a_gap() {
+        let report = stats_after(&[
+            frame(0, Some(u32::MAX - 1)),
+            frame(100, Some(u32::MAX)),
+            frame(200, Some(0)),
+            frame(400, Some(2)),
+        ]);
+        assert_eq!(report.dropped_frames, 1);
+    }
+
+    #[test]
+    fn frames_beyond_one_and_a_half_periods_are_late() {
+        let report = stats_after(&[
+            frame(0, None),
+            frame(140, None),
+            frame(300, None),
+            frame(400, None),
+            frame(700, None),
+        ]);
+        assert_eq!(report.late_frames, 2);
+        assert_eq!(report.dropped_frames, 0);
+    }
+
+    #[test]
+    fn a_reopened_stream_starts_a_new_sequence() {
+        let mut stats = FrameStats::new("front", 10);
+        stats.opened();
+        stats.record(&frame(0, Some(100)));
+        stats.opened();
+        stats.record(&frame(5_000, Some(0)));
+
+        let report = stats.report(true, None);
+        assert_eq!(report.reconnects, 1);
+        assert_eq!((report.dropped_frames, report.late_frames), (0, 0));
+    }
+
+    #[test]
+    fn measured_fps_covers_the_report_window() {
+        let mut stats = FrameStats::new("front", 10);
+        stats.opened();
+        for i in 0..5 {
+            stats.record(&frame(i * 100, Some(i as u32)));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+
+        let report = stats.report(true, None);
+        assert!(report.fps_measured > 5.0 && report.fps_measured <= 50.0);
+        assert!(report.last_frame_age_s.unwrap() >= 0.1);
+
+        let report = stats.report(true, None);
+        assert_eq!(report.fps_measured, 0.0);
+        assert_eq!(report.frames, 5);
+    }
+}
@@ -0,0 +1,553 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::time::Duration;
+
+use anyhow::{bail, ensure, Context, Result};
+
+use crate::camera::{CameraSource, CapturedFrame};
+use crate::config::CameraConfig;
+use crate::utils::now_nanos;
+
+const BUFFER_COUNT: u32 = 4;
+
+/// Single-planar V4L2 capture with mmap streaming, which covers the UVC
+/// cameras on the robot. MJPEG frames are handed on exactly as the camera
+/// encoded them.
+pub struct V4l2Camera {
+    device: File,
+    path: String,
+    buffers: Vec<Mapping>,
+    width: u32,
+    height: u32,
+    encoding: &'static str,
+}
+
+struct Mapping {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// The mappings belong to this camera alone and are only touched through
+// `&mut self`.
+unsafe impl Send for V4l2Camera {}
+
+impl V4l2Camera {
+    pub fn open(config: &CameraConfig) -> Result<Self> {
+        // Frames go out as foxglove.CompressedImage, so only compressed
+        // formats are captured.
+        let (pixel_format, encoding) = match config.format.as_str() {
+            "mjpeg" => (sys::PIX_FMT_MJPEG, "jpeg"),
+            other => bail!("unsupported camera format '{other}' (expected mjpeg)"),
+        };
+        let device = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&config.device)
+            .with_context(|| format!("unable to open {}", config.device))?;
+        let fd = device.as_raw_fd();
+
+        let mut caps: sys::Capability = zeroed();
+        ioctl(fd, sys::VIDIOC_QUERYCAP, &mut caps).context("VIDIOC_QUERYCAP failed")?;
+        let device_caps = if caps.capabilities & sys::CAP_DEVICE_CAPS != 0 {
+            caps.device_caps
+        } else {
+            caps.capabilities
+        };
+        ensure!(
+            device_caps & sys::CAP_VIDEO_CAPTURE != 0 && device_caps & sys::CAP_STREAMING != 0,
+            "{} is not a streaming video capture device",
+            config.device
+        );
+
+        let mut format: sys::Format = zeroed();
+        format.type_ = sys::BUF_TYPE_VIDEO_CAPTURE;
+        format.pix = sys::PixFormat {
+            width: config.width,
+            height: config.height,
+            pixelformat: pixel_format,
+            ..zeroed()
+        };
+        ioctl(fd, sys::VIDIOC_S_FMT, &mut format).context("VIDIOC_S_FMT failed")?;
+        let pix = format.pix;
+        ensure!(
+            pix.pixelformat == pixel_format,
+            "{} does not offer {}",
+            config.device,
+            config.format
+        );
+        if (pix.width, pix.height) != (config.width, config.height) {
+            tracing::warn!(
+                "Camera {}: driver chose {}x{} instead of {}x{}",
+                config.name,
+                pix.width,
+                pix.height,
+                config.width,
+                config.height
+            );
+        }
+
+        set_frame_rate(fd, config);
+        apply_controls(fd, config);
+
+        let mut camera = Self {
+            device,
+            path: config.device.clone(),
+            buffers: Vec::new(),
+            width: pix.width,
+            height: pix.height,
+            encoding,
+        };
+        camera.start_streaming()?;
+        Ok(camera)
+    }
+
+    fn start_streaming(&mut self) -> Result<()> {
+        let fd = self.device.as_raw_fd();
+        let mut request: sys::RequestBuffers = zeroed();
+        request.count = BUFFER_COUNT;
+        request.type_ = sys::BUF_TYPE_VIDEO_CAPTURE;
+        request.memory = sys::MEMORY_MMAP;
+        ioctl(fd, sys::VIDIOC_REQBUFS, &mut request).context("VIDIOC_REQBUFS failed")?;
+        ensure!(
+            request.count >= 2,
+            "driver granted only {} buffers",
+            request.count
+        );
+
+        for index in 0..request.count {
+            let mut buffer = capture_buffer();
+            buffer.index = index;
+            ioctl(fd, sys::VIDIOC_QUERYBUF, &mut buffer).context("VIDIOC_QUERYBUF failed")?;
+            let len = buffer.length as usize;
+            // SAFETY: maps the driver buffer at the offset it just reported;
+            // the mapping is released in Drop.
+            let ptr = unsafe {
+                libc::mmap(
+                    std::ptr::null_mut(),
+                    len,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_SHARED,
+                    fd,
+                    buffer.m.offset as libc::off_t,
+                )
+            };
+            if ptr == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error()).context("mmap of capture buffer failed");
+            }
+            self.buffers.push(Mapping { ptr, len });
+            ioctl(fd, sys::VIDIOC_QBUF, &mut buffer).context("VIDIOC_QBUF failed")?;
+        }
+
+        let mut kind = sys::BUF_TYPE_VIDEO_CAPTURE as libc::c_int;
+        ioctl(fd, sys::VIDIOC_STREAMON, &mut kind).context("VIDIOC_STREAMON failed")?;
+        Ok(())
+    }
+
+    /// Converts a driver timestamp to UNIX time. Drivers stamp buffers on
+    /// CLOCK_MONOTONIC at capture; anything else falls back to now.
+    fn capture_time(buffer: &sys::Buffer) -> u64 {
+        if buffer.flags & sys::BUF_FLAG_TIMESTAMP_MASK != sys::BUF_FLAG_TIMESTAMP_MONOTONIC {
+            return now_nanos();
+        }
+        let captured = buffer.timestamp.tv_sec as u64 * 1_000_000_000
+            + buffer.timestamp.tv_usec as u64 * 1_000;
+        let mut now = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // SAFETY: clock_gettime only writes the timespec it is given.
+        unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+        let monotonic_now = now.tv_sec as u64 * 1_000_000_000 + now.tv_nsec as u64;
+        now_nanos().saturating_sub(monotonic_now.saturating_sub(captured))
+    }
+}
+
+impl CameraSource for V4l2Camera {
+    fn describe(&self) -> String {
+        format!(
+            "{} ({}x{} {})",
+            self.path, self.width, self.height, self.encoding
+        )
+    }
+
+    fn next_frame(&mut self, timeout: Duration) -> Result<Option<CapturedFrame>> {
+        let fd = self.device.as_raw_fd();
+        let mut poll = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: polls the one descriptor this camera owns.
+        let ready = unsafe { libc::poll(&mut poll, 1, timeout.as_millis() as libc::c_int) };
+        if ready < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                return Ok(None);
+            }
+            return Err(err).context("poll failed");
+        }
+        if ready == 0 {
+            return Ok(None);
+        }
+        // An unplugged UVC camera reports POLLERR with nothing to dequeue.
+        if poll.revents & libc::POLLIN == 0 && poll.revents & (libc::POLLERR | libc::POLLHUP) != 0 {
+            bail!("{} reported an error; device lost", self.path);
+        }
+
+        let mut buffer = capture_buffer();
+        match ioctl(fd, sys::VIDIOC_DQBUF, &mut buffer) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+            Err(err) => {
+                return Err(err).with_context(|| format!("VIDIOC_DQBUF on {} failed", self.path))
+            }
+        }
+
+        let mapping = self
+            .buffers
+            .get(buffer.index as usize)
+            .context("driver returned an unknown buffer index")?;
+        let used = (buffer.bytesused as usize).min(mapping.len);
+        let corrupt = buffer.flags & sys::BUF_FLAG_ERROR != 0 || used == 0;
+        // SAFETY: the buffer is dequeued, so the driver is not writing to it
+        // until it is queued again below.
+        let data = unsafe { std::slice::from_raw_parts(mapping.ptr as *const u8, used) }.to_vec();
+        let timestamp_ns = Self::capture_time(&buffer);
+        ioctl(fd, sys::VIDIOC_QBUF, &mut buffer).context("VIDIOC_QBUF failed")?;
+
+        if corrupt {
+            tracing::debug!("Dropping corrupt frame from {}", self.path);
+            return Ok(None);
+        }
+        Ok(Some(CapturedFrame {
+            timestamp_ns,
+            sequence: Some(buffer.sequence),
+            width: self.width,
+            height: self.height,
+            encoding: self.encoding.to_string(),
+            data,
+        }))
+    }
+}
+
+impl Drop for V4l2Camera {
+    fn drop(&mut self) {
+        let mut kind = sys::BUF_TYPE_VIDEO_CAPTURE as libc::c_int;
+        let _ = ioctl(self.device.as_raw_fd(), sys::VIDIOC_STREAMOFF, &mut kind);
+        for mapping in &self.buffers {
+            // SAFETY: each mapping came from mmap in start_streaming and is
+            // unmapped exactly once.
+            unsafe { libc::munmap(mapping.ptr, mapping.len) };
+        }
+    }
+}
+
+fn set_frame_rate(fd: libc::c_int, config: &CameraConfig) {
+    let mut parm: sys::StreamParm = zeroed();
+    parm.type_ = sys::BUF_TYPE_VIDEO_CAPTURE;
+    parm.capture.timeperframe = sys::Fract {
+        numerator: 1,
+        denominator: config.fps.max(1),
+    };
+    match ioctl(fd, sys::VIDIOC_S_PARM, &mut parm) {
+        Ok(()) => {
+            let frame = parm.capture.timeperframe;
+            if frame.numerator > 0 && frame.denominator / frame.numerator != config.fps {
+                tracing::warn!(
+                    "Camera {}: driver runs at {}/{} fps instead of {}",
+                    config.name,
+                    frame.denominator,
+                    frame.numerator,
+                    config.fps
+                );
+            }
+        }
+        Err(err) => tracing::warn!(
+            "Camera {}: unable to set {} fps: {err}",
+            config.name,
+            config.fps
+        ),
+    }
+}
+
+/// Applies exposure, focus and flip settings. Not every camera has every
+/// control, so failures are logged and capture continues.
+fn apply_controls(fd: libc::c_int, config: &CameraConfig) {
+    let set = |id: u32, value: i32| {
+        let mut control = sys::Control { id, value };
+        ioctl(fd, sys::VIDIOC_S_CTRL, &mut control)
+    };
+    let warn = |control: &str, err: io::Error| {
+        tracing::warn!("Camera {}: {control} not applied: {err}", config.name);
+    };
+
+    if config.auto_exposure {
+        // UVC cameras usually offer aperture priority rather than full auto.
+        if let Err(err) = set(sys::CID_EXPOSURE_AUTO, sys::EXPOSURE_APERTURE_PRIORITY)
+            .or_else(|_| set(sys::CID_EXPOSURE_AUTO, sys::EXPOSURE_AUTO))
+        {
+            warn("auto exposure", err);
+        }
+    } else {
+        if let Err(err) = set(sys::CID_EXPOSURE_AUTO, sys::EXPOSURE_MANUAL) {
+            warn("manual exposure", err);
+        }
+        if let Some(exposure) = config.exposure_100us {
+            if let Err(err) = set(sys::CID_EXPOSURE_ABSOLUTE, exposure) {
+                warn("exposure_100us", err);
+            }
+        }
+    }
+
+    if let Err(err) = set(sys::CID_FOCUS_AUTO, config.auto_focus as i32) {
+        // Fixed-focus cameras have no focus controls at all.
+        if !config.auto_focus || config.focus.is_some() {
+            warn("auto_focus", err);
+        }
+    }
+    if let (false, Some(focus)) = (config.auto_focus, config.focus) {
+        if let Err(err) = set(sys::CID_FOCUS_ABSOLUTE, focus) {
+            warn("focus", err);
+        }
+    }
+
+    for (id, enabled, name) in [
+        (sys::CID_HFLIP, config.flip_horizontal, "flip_horizontal"),
+        (sys::CID_VFLIP, config.flip_vertical, "flip_vertical"),
+    ] {
+        if let Err(err) = set(id, enabled as i32) {
+            if enabled {
+                warn(name, err);
+            }
+        }
+    }
+}
+
+fn capture_buffer() -> sys::Buffer {
+    let mut buffer: sys::Buffer = zeroed();
+    buffer.type_ = sys::BUF_TYPE_VIDEO_CAPTURE;
+    buffer.memory = sys::MEMORY_MMAP;
+    buffer
+}
+
+fn zeroed<T: sys::Plain>() -> T {
+    // SAFETY: `Plain` is only implemented for the C structs below, for which
+    // all-zero bytes are a valid value.
+    unsafe { std::mem::zeroed() }
+}
+
+fn ioctl<T: sys::Plain>(fd: libc::c_int, request: libc::Ioctl, arg: &mut T) -> io::Result<()> {
+    loop {
+        // SAFETY: `request` is a V4L2 ioctl whose argument type is `T`.
+        let ret = unsafe { libc::ioctl(fd, request, arg as *mut T) };
+        if ret != -1 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+/// The subset of `linux/videodev2.h` used here, laid out as on 64-bit Linux;
+/// the module is only built for 64-bit targets.
+#[allow(dead_code)]
+mod sys {
+    use std::mem::size_of;
+
+    /// Plain C data that may be zero-initialised and passed to ioctl.
+    pub trait Plain {}
+    impl Plain for Capability {}
+    impl Plain for Format {}
+    impl Plain for PixFormat {}
+    impl Plain for RequestBuffers {}
+    impl Plain for Buffer {}
+    impl Plain for StreamParm {}
+    impl Plain for Control {}
+    impl Plain for libc::c_int {}
+
+    pub const BUF_TYPE_VIDEO_CAPTURE: u32 = 1;
+    pub const MEMORY_MMAP: u32 = 1;
+
+    pub const CAP_VIDEO_CAPTURE: u32 = 0x0000_0001;
+    pub const CAP_STREAMING: u32 = 0x0400_0000;
+    pub const CAP_DEVICE_CAPS: u32 = 0x8000_0000;
+
+    pub const PIX_FMT_MJPEG: u32 = fourcc(b"MJPG");
+
+    pub const BUF_FLAG_ERROR: u32 = 0x0040;
+    pub const BUF_FLAG_TIMESTAMP_MASK: u32 = 0xe000;
+    pub const BUF_FLAG_TIMESTAMP_MONOTONIC: u32 = 0x2000;
+
+    pub const CID_HFLIP: u32 = 0x0098_0914;
+    pub const CID_VFLIP: u32 = 0x0098_0915;
+    pub const CID_EXPOSURE_AUTO: u32 = 0x009a_0901;
+    pub const CID_EXPOSURE_ABSOLUTE: u32 = 0x009a_0902;
+    pub const CID_FOCUS_ABSOLUTE: u32 = 0x009a_090a;
+    pub const CID_FOCUS_AUTO: u32 = 0x009a_090c;
+
+    pub const EXPOSURE_AUTO: i32 = 0;
+    pub const EXPOSURE_MANUAL: i32 = 1;
+    pub const EXPOSURE_APERTURE_PRIORITY: i32 = 3;
+
+    pub const VIDIOC_QUERYCAP: libc::Ioctl = ior(0, size_of::<Capability>());
+    pub const VIDIOC_S_FMT: libc::Ioctl = iowr(5, size_of::<Format>());
+    pub const VIDIOC_REQBUFS: libc::Ioctl = iowr(8, size_of::<RequestBuffers>());
+    pub const VIDIOC_QUERYBUF: libc::Ioctl = iowr(9, size_of::<Buffer>());
+    pub const VIDIOC_QBUF: libc::Ioctl = iowr(15, size_of::<Buffer>());
+    pub const VIDIOC_DQBUF: libc::Ioctl = iowr(17, size_of::<Buffer>());
+    pub const VIDIOC_STREAMON: libc::Ioctl = iow(18, size_of::<libc::c_int>());
+    pub const VIDIOC_STREAMOFF: libc::Ioctl = iow(19, size_of::<libc::c_int>());
+    pub const VIDIOC_S_PARM: libc::Ioctl = iowr(22, size_of::<StreamParm>());
+    pub const VIDIOC_S_CTRL: libc::Ioctl = iowr(28, size_of::<Control>());
+
+    const IOC_WRITE: u32 = 1;
+    const IOC_READ: u32 = 2;
+
+    const fn ioc(dir: u32, nr: u32, size: usize) -> libc::Ioctl {
+        ((dir << 30) | ((size as u32) << 16) | ((b'V' as u32) << 8) | nr) as libc::Ioctl
+    }
+
+    const fn ior(nr: u32, size: usize) -> libc::Ioctl {
+        ioc(IOC_READ, nr, size)
+    }
+
+    const fn iow(nr: u32, size: usize) -> libc::Ioctl {
+        ioc(IOC_WRITE, nr, size)
+    }
+
+    const fn iowr(nr: u32, size: usize) -> libc::Ioctl {
+        ioc(IOC_READ | IOC_WRITE, nr, size)
+    }
+
+    const fn fourcc(code: &[u8; 4]) -> u32 {
+        u32::from_le_bytes(*code)
+    }
+
+    #[repr(C)]
+    pub struct Capability {
+        pub driver: [u8; 16],
+        pub card: [u8; 32],
+        pub bus_info: [u8; 32],
+        pub version: u32,
+        pub capabilities: u32,
+        pub device_caps: u32,
+        pub reserved: [u32; 3],
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct PixFormat {
+        pub width: u32,
+        pub height: u32,
+        pub pixelformat: u32,
+        pub field: u32,
+        pub bytesperline: u32,
+        pub sizeimage: u32,
+        pub colorspace: u32,
+        pub priv_: u32,
+        pub flags: u32,
+        pub ycbcr_enc: u32,
+        pub quantization: u32,
+        pub xfer_func: u32,
+    }
+
+    /// `struct v4l2_format` with the `fmt` union narrowed to `pix`. The union
+    /// holds pointers, so it starts 8-byte aligned.
+    #[repr(C)]
+    pub struct Format {
+        pub type_: u32,
+        _union_align: u32,
+        pub pix: PixFormat,
+        _union_rest: [u8; 200 - size_of::<PixFormat>()],
+    }
+
+    #[repr(C)]
+    pub struct RequestBuffers {
+        pub count: u32,
+        pub type_: u32,
+        pub memory: u32,
+        pub capabilities: u32,
+        pub flags: u8,
+        pub reserved: [u8; 3],
+    }
+
+    #[repr(C)]
+    pub struct Timecode {
+        pub type_: u32,
+        pub flags: u32,
+        pub frames: u8,
+        pub seconds: u8,
+        pub minutes: u8,
+        pub hours: u8,
+        pub userbits: [u8; 4],
+    }
+
+    #[repr(C)]
+    pub union BufferMemory {
+        pub offset: u32,
+        pub userptr: libc::c_ulong,
+        pub fd: i32,
+    }
+
+    #[repr(C)]
+    pub struct Buffer {
+        pub index: u32,
+        pub type_: u32,
+        pub bytesused: u32,
+        pub flags: u32,
+        pub field: u32,
+        pub timestamp: libc::timeval,
+        pub timecode: Timecode,
+        pub sequence: u32,
+        pub memory: u32,
+        pub m: BufferMemory,
+        pub length: u32,
+        pub reserved2: u32,
+        pub request_fd: i32,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct Fract {
+        pub numerator: u32,
+        pub denominator: u32,
+    }
+
+    #[repr(C)]
+    pub struct CaptureParm {
+        pub capability: u32,
+        pub capturemode: u32,
+        pub timeperframe: Fract,
+        pub extendedmode: u32,
+        pub readbuffers: u32,
+        pub reserved: [u32; 4],
+    }
+
+    /// `struct v4l2_streamparm` with the `parm` union narrowed to `capture`.
+    #[repr(C)]
+    pub struct StreamParm {
+        pub type_: u32,
+        pub capture: CaptureParm,
+        _union_rest: [u8; 200 - size_of::<CaptureParm>()],
+    }
+
+    #[repr(C)]
+    pub struct Control {
+        pub id: u32,
+        pub value: i32,
+    }
+
+    const _: () = {
+        assert!(size_of::<Capability>() == 104);
+        assert!(size_of::<Format>() == 208);
+        assert!(size_of::<RequestBuffers>() == 20);
+        assert!(size_of::<Buffer>() == 88);
+        assert!(size_of::<StreamParm>() == 204);
+        assert!(size_of::<Control>() == 8);
+    };
+}
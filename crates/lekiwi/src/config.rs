@@ -1,8 +1,9 @@
+use std::collections::HashSet;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use anyhow::{Context, Result};
-use serde::Deserialize;
+use anyhow::{ensure, Context, Result};
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct AppConfig {
@@ -14,7 +15,13 @@ pub struct AppConfig {
 impl AppConfig {
     pub fn load(robot_path: &Path, cameras_path: &Path, logging_path: &Path) -> Result<Self> {
         let robot = read_yaml(robot_path).context("failed to read robot config")?;
-        let cameras = read_yaml(cameras_path).context("failed to read cameras config")?;
+        let mut cameras: CamerasConfig =
+            read_yaml(cameras_path).context("failed to read cameras config")?;
+        let cameras_dir = cameras_path.parent().unwrap_or(Path::new("."));
+        cameras
+            .load_calibration_files(cameras_dir)
+            .and_then(|()| cameras.validate())
+            .with_context(|| format!("invalid {}", cameras_path.display()))?;
         let logging = read_yaml(logging_path).context("failed to read logging config")?;
         Ok(Self {
             robot,
@@ -25,8 +32,8 @@ impl AppConfig {
 }
 
 fn read_yaml<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<T> {
-    let contents = fs::read_to_string(path)
-        .with_context(|| format!("unable to read {}", path.display()))?;
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("unable to read {}", path.display()))?;
     let config = serde_yaml::from_str(&contents)
         .with_context(|| format!("unable to parse {}", path.display()))?;
     Ok(config)
@@ -34,6 +41,8 @@ fn read_yaml<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<T> {
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct RobotConfig {
+    /// Descriptive only; nothing reads it yet.
+    #[allow(dead_code)]
     pub robot: RobotInfo,
     pub battery: BatteryConfig,
     pub servo_bus: ServoBusConfig,
@@ -41,9 +50,22 @@ pub struct RobotConfig {
     pub limits: LimitsConfig,
     pub safety: SafetyConfig,
     pub frames: FramesConfig,
+    #[serde(default)]
+    pub estimator: EstimatorConfig,
+    #[serde(default)]
+    pub arbitration: ArbitrationConfig,
+    #[serde(default)]
+    pub geofence: Option<GeofenceConfig>,
+    #[serde(default)]
+    pub speed_modes: SpeedModesConfig,
+    #[serde(default)]
+    pub teleop: TeleopConfig,
+    #[serde(default)]
+    pub skills: SkillsConfig,
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[allow(dead_code)]
 pub struct RobotInfo {
     pub name: String,
     pub platform: String,
@@ -52,6 +74,8 @@ pub struct RobotInfo {
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct BatteryConfig {
+    /// Descriptive only; the cutoff uses the voltages.
+    #[allow(dead_code)]
     pub r#type: String,
     pub nominal_voltage_v: f32,
     pub low_voltage_v: f32,
@@ -62,21 +86,67 @@ pub struct ServoBusConfig {
     pub port: String,
     pub baud_rate: u32,
     pub protocol: String,
+    #[serde(default)]
+    pub transport: ServoTransportKind,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServoTransportKind {
+    #[default]
+    Serial,
+    Loopback,
+    PtySim,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct DriveConfig {
     pub wheel_radius_m: f32,
     pub wheel_distance_m: f32,
+    #[serde(default = "default_max_wheel_speed_rad_s")]
+    pub max_wheel_speed_rad_s: f32,
     pub wheel_mounts: Vec<WheelMount>,
 }
 
+/// STS3215 no-load speed at 12 V is roughly 3400 steps/s (~5.2 rad/s).
+fn default_max_wheel_speed_rad_s() -> f32 {
+    5.0
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct WheelMount {
     pub name: String,
     pub angle_deg: f32,
     pub servo_id: u8,
     pub direction: i8,
+    #[serde(default)]
+    pub stall: StallConfig,
+}
+
+/// Per-wheel stall and impact thresholds for behavior_router.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StallConfig {
+    /// |load| (fraction of full torque) that counts as pushing hard.
+    pub load_threshold: f32,
+    /// |commanded - measured| servo speed that counts as not tracking.
+    pub velocity_error_rad_s: f32,
+    /// Both conditions must hold this long before a stall is declared.
+    pub debounce_ms: u64,
+    /// Rise in |load| between two readings treated as an impact; `null`
+    /// disables impact detection.
+    pub impact_load_jump: Option<f32>,
+}
+
+impl Default for StallConfig {
+    fn default() -> Self {
+        Self {
+            load_threshold: 0.8,
+            velocity_error_rad_s: 1.0,
+            debounce_ms: 300,
+            impact_load_jump: Some(0.5),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -84,31 +154,286 @@ pub struct LimitsConfig {
     pub max_vx_m_s: f32,
     pub max_vy_m_s: f32,
     pub max_omega_rad_s: f32,
+    /// Planar speed norm; defaults to the larger of `max_vx_m_s` and
+    /// `max_vy_m_s` so diagonals are no faster than straight lines.
+    #[serde(default)]
+    pub max_speed_m_s: Option<f32>,
+    /// Planar acceleration norm.
     pub max_accel_m_s2: f32,
     pub max_alpha_rad_s2: f32,
+    /// Optional jerk limits for smoother starts and stops; e-stop and other
+    /// forced stops ignore them.
+    #[serde(default)]
+    pub max_jerk_m_s3: Option<f32>,
+    #[serde(default)]
+    pub max_jerk_rad_s3: Option<f32>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct SafetyConfig {
     pub command_timeout_ms: u64,
     pub estop_enabled: bool,
+    /// Sources allowed to clear a latched e-stop via /cmd/estop_reset, as
+    /// `name` or `name:detail`.
+    #[serde(default = "default_estop_reset_sources")]
+    pub estop_reset_sources: Vec<String>,
     pub servo_temp_limit_c: f32,
+    /// Speed is derated linearly over this many degrees below the limit,
+    /// reaching zero at `servo_temp_limit_c`.
+    #[serde(default = "default_servo_temp_derate_c")]
+    pub servo_temp_derate_c: f32,
     pub low_battery_stop: bool,
 }
 
+fn default_estop_reset_sources() -> Vec<String> {
+    vec!["foxglove".to_string()]
+}
+
+fn default_servo_temp_derate_c() -> f32 {
+    10.0
+}
+
+/// Velocity sources behavior_router arbitrates between. A command belongs to
+/// the source whose `name` equals its `source`, or prefixes it as
+/// `name:detail`; anything else lands in `default_source`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ArbitrationConfig {
+    pub sources: Vec<CommandSourceConfig>,
+    pub default_source: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CommandSourceConfig {
+    pub name: String,
+    /// Higher wins while its lease is live.
+    pub priority: i32,
+    /// How long one command holds the slot.
+    pub lease_ms: u64,
+}
+
+impl Default for ArbitrationConfig {
+    fn default() -> Self {
+        let source = |name: &str, priority, lease_ms| CommandSourceConfig {
+            name: name.to_string(),
+            priority,
+            lease_ms,
+        };
+        Self {
+            sources: vec![
+                source("foxglove", 100, 250),
+                source("skill", 50, 250),
+                source("script", 10, 500),
+            ],
+            default_source: "script".to_string(),
+        }
+    }
+}
+
+/// Liveness checks foxglove_server applies to each client's /cmd/velocity, so
+/// a frozen UI that keeps re-sending its last command cannot hold the base.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TeleopConfig {
+    /// Reject commands without a `seq` above the client's previous one.
+    pub require_seq: bool,
+    /// Reject commands whose `timestamp_ns` is older than this.
+    pub max_command_age_ms: Option<u64>,
+}
+
+impl Default for TeleopConfig {
+    fn default() -> Self {
+        Self {
+            require_seq: true,
+            max_command_age_ms: Some(500),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SkillsConfig {
+    /// Longest `timeout_s` a /cmd/skill request may ask for.
+    pub max_timeout_s: f32,
+}
+
+impl Default for SkillsConfig {
+    fn default() -> Self {
+        Self {
+            max_timeout_s: 300.0,
+        }
+    }
+}
+
+/// Named presets that scale `limits` at runtime, selected over
+/// /cmd/speed_scale. `default_mode` is active at startup.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SpeedModesConfig {
+    pub modes: Vec<SpeedModeConfig>,
+    pub default_mode: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SpeedModeConfig {
+    pub name: String,
+    /// Multiplies the velocity caps (vx, vy, planar speed, omega).
+    pub speed_scale: f32,
+    /// Multiplies the acceleration and jerk caps.
+    #[serde(default = "default_accel_scale")]
+    pub accel_scale: f32,
+}
+
+fn default_accel_scale() -> f32 {
+    1.0
+}
+
+impl Default for SpeedModesConfig {
+    fn default() -> Self {
+        let mode = |name: &str, speed_scale, accel_scale| SpeedModeConfig {
+            name: name.to_string(),
+            speed_scale,
+            accel_scale,
+        };
+        Self {
+            modes: vec![
+                mode("normal", 1.0, 1.0),
+                mode("demo", 0.5, 0.5),
+                mode("crawl", 0.2, 0.5),
+            ],
+            default_mode: "normal".to_string(),
+        }
+    }
+}
+
+/// Optional fence in the odom frame. behavior_router brakes outward motion so
+/// the base stops at the boundary, and always allows motion back inside.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "shape", rename_all = "snake_case")]
+pub enum GeofenceConfig {
+    Circle { center_m: [f64; 2], radius_m: f64 },
+    Polygon { vertices_m: Vec<[f64; 2]> },
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct FramesConfig {
     pub base_link: String,
     pub odom: String,
+    /// Reserved for localization; nothing publishes in it yet.
+    #[allow(dead_code)]
     pub map: String,
 }
 
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EstimatorConfig {
+    #[serde(default)]
+    pub mode: EstimatorMode,
+    #[serde(default)]
+    pub ekf: EkfConfig,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EstimatorMode {
+    /// Integrate wheel encoder deltas through the forward kinematics.
+    #[default]
+    Encoders,
+    /// Integrate the commanded wheel targets (open loop).
+    Commanded,
+    /// Fuse encoder twist with `/sensors/imu` yaw rate and acceleration.
+    Ekf,
+}
+
+/// Noise terms for the EKF, all as one-sigma values. The IMU is assumed to be
+/// mounted level and aligned with `base_link`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct EkfConfig {
+    /// Unmodelled linear acceleration driving vx/vy between updates (m/s^2).
+    pub accel_process_noise: f32,
+    /// Unmodelled angular acceleration driving omega between updates (rad/s^2).
+    pub alpha_process_noise: f32,
+    /// Encoder-derived vx/vy (m/s).
+    pub wheel_velocity_noise: f32,
+    /// Encoder-derived omega (rad/s); keep well above `gyro_noise` because
+    /// omni rollers slip most while spinning in place.
+    pub wheel_omega_noise: f32,
+    /// IMU yaw rate (rad/s).
+    pub gyro_noise: f32,
+    /// IMU linear acceleration, used as the prediction input (m/s^2).
+    pub accel_noise: f32,
+    /// IMU samples older than this are ignored.
+    pub imu_timeout_ms: u64,
+}
+
+impl Default for EkfConfig {
+    fn default() -> Self {
+        Self {
+            accel_process_noise: 1.0,
+            alpha_process_noise: 4.0,
+            wheel_velocity_noise: 0.05,
+            wheel_omega_noise: 0.3,
+            gyro_noise: 0.02,
+            accel_noise: 0.3,
+            imu_timeout_ms: 200,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct CamerasConfig {
     pub cameras: Vec<CameraConfig>,
+    /// Also publish each frame as the old JSON message with base64 data on
+    /// `<camera topic>/json`, for consumers that predate CompressedImage.
+    #[serde(default)]
+    pub json_frames: bool,
+}
+
+impl CamerasConfig {
+    /// Reads each `calibration_file` into `calibration`. Relative paths are
+    /// resolved against the directory of cameras.yaml.
+    fn load_calibration_files(&mut self, dir: &Path) -> Result<()> {
+        for camera in &mut self.cameras {
+            let Some(file) = &camera.calibration_file else {
+                continue;
+            };
+            ensure!(
+                camera.calibration.is_none(),
+                "camera '{}' sets both calibration and calibration_file",
+                camera.name
+            );
+            let calibration = read_yaml(&dir.join(file))
+                .with_context(|| format!("failed to read calibration for '{}'", camera.name))?;
+            camera.calibration = Some(calibration);
+        }
+        Ok(())
+    }
+
+    /// Camera names become topic and frame names, so they must be unique and
+    /// safe to splice into both.
+    fn validate(&self) -> Result<()> {
+        let mut seen = HashSet::new();
+        for camera in &self.cameras {
+            let name = &camera.name;
+            ensure!(
+                !name.is_empty()
+                    && name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'),
+                "camera name '{name}' must be non-empty and use only letters, digits, '_' or '-'"
+            );
+            ensure!(seen.insert(name), "camera name '{name}' is listed twice");
+            if let Some(calibration) = &camera.calibration {
+                calibration
+                    .validate(camera)
+                    .with_context(|| format!("invalid calibration for '{name}'"))?;
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
+// Only the v4l2 backend, which is 64-bit only, reads the capture controls.
+#[cfg_attr(not(target_pointer_width = "64"), allow(dead_code))]
 pub struct CameraConfig {
     pub name: String,
     pub device: String,
@@ -120,6 +445,162 @@ pub struct CameraConfig {
     pub auto_focus: bool,
     pub flip_horizontal: bool,
     pub flip_vertical: bool,
+    /// Manual exposure in 100 us units, applied when `auto_exposure` is off.
+    #[serde(default)]
+    pub exposure_100us: Option<i32>,
+    /// Manual focus position, applied when `auto_focus` is off.
+    #[serde(default)]
+    pub focus: Option<i32>,
+    #[serde(default)]
+    pub backend: CameraBackend,
+    #[serde(default)]
+    pub extrinsics: CameraExtrinsics,
+    #[serde(default)]
+    pub calibration: Option<CameraIntrinsics>,
+    /// Sidecar YAML with the same fields as `calibration`, e.g. the output of
+    /// a calibration run kept next to cameras.yaml.
+    #[serde(default)]
+    pub calibration_file: Option<PathBuf>,
+}
+
+impl CameraConfig {
+    /// Frame the camera's images are expressed in; transforms publishes it.
+    pub fn frame_id(&self) -> String {
+        format!("camera_{}", self.name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CameraBackend {
+    /// Capture from the V4L2 device at `device`.
+    #[default]
+    V4l2,
+    /// Replay a recorded MJPEG stream (concatenated JPEGs) from `device` at
+    /// `fps`, looping at the end.
+    File,
+}
+
+/// Intrinsics for images of `width` x `height`, as published on
+/// `/sensors/camera/<name>/calibration`. Matrices are row-major.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CameraIntrinsics {
+    pub width: u32,
+    pub height: u32,
+    #[serde(default)]
+    pub distortion_model: DistortionModel,
+    /// [fx, 0, cx, 0, fy, cy, 0, 0, 1]
+    pub k: [f64; 9],
+    /// Distortion coefficients in the order the model defines.
+    #[serde(default)]
+    pub d: Vec<f64>,
+    /// Rectification rotation; identity for a monocular camera.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub r: Option<[f64; 9]>,
+    /// Projection matrix; defaults to `k` with a zero fourth column.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub p: Option<[f64; 12]>,
+}
+
+impl CameraIntrinsics {
+    fn validate(&self, camera: &CameraConfig) -> Result<()> {
+        ensure!(
+            self.width == camera.width && self.height == camera.height,
+            "calibrated for {}x{} but the camera captures {}x{}",
+            self.width,
+            self.height,
+            camera.width,
+            camera.height
+        );
+        ensure!(
+            self.k[0] > 0.0 && self.k[4] > 0.0,
+            "k must have positive fx and fy"
+        );
+        let expected = self.distortion_model.coefficient_count();
+        ensure!(
+            self.d.len() == expected,
+            "{} takes {expected} distortion coefficients, got {}",
+            self.distortion_model.as_str(),
+            self.d.len()
+        );
+        Ok(())
+    }
+
+    pub fn rectification(&self) -> [f64; 9] {
+        self.r
+            .unwrap_or([1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0])
+    }
+
+    pub fn projection(&self) -> [f64; 12] {
+        self.p.unwrap_or_else(|| {
+            let k = &self.k;
+            [
+                k[0], k[1], k[2], 0.0, k[3], k[4], k[5], 0.0, k[6], k[7], k[8], 0.0,
+            ]
+        })
+    }
+}
+
+/// Distortion models understood by foxglove.CameraCalibration.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DistortionModel {
+    /// OpenCV pinhole: k1, k2, p1, p2, k3.
+    #[default]
+    PlumbBob,
+    /// OpenCV pinhole: k1, k2, p1, p2, k3, k4, k5, k6.
+    RationalPolynomial,
+    /// OpenCV fisheye: k1, k2, k3, k4.
+    KannalaBrandt,
+}
+
+impl DistortionModel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DistortionModel::PlumbBob => "plumb_bob",
+            DistortionModel::RationalPolynomial => "rational_polynomial",
+            DistortionModel::KannalaBrandt => "kannala_brandt",
+        }
+    }
+
+    fn coefficient_count(self) -> usize {
+        match self {
+            DistortionModel::PlumbBob => 5,
+            DistortionModel::RationalPolynomial => 8,
+            DistortionModel::KannalaBrandt => 4,
+        }
+    }
+}
+
+/// Pose of the camera's optical frame (z forward, x right, y down) in
+/// `parent_frame`. Rotation is roll/pitch/yaw about fixed x, y, z axes.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CameraExtrinsics {
+    #[serde(default = "default_extrinsics_parent")]
+    pub parent_frame: String,
+    #[serde(default)]
+    pub translation_m: [f64; 3],
+    #[serde(default = "default_optical_rpy_deg")]
+    pub rotation_rpy_deg: [f64; 3],
+}
+
+impl Default for CameraExtrinsics {
+    fn default() -> Self {
+        Self {
+            parent_frame: default_extrinsics_parent(),
+            translation_m: [0.0; 3],
+            rotation_rpy_deg: default_optical_rpy_deg(),
+        }
+    }
+}
+
+fn default_extrinsics_parent() -> String {
+    "base_link".to_string()
+}
+
+/// Optical frame looking along +x of the parent.
+fn default_optical_rpy_deg() -> [f64; 3] {
+    [-90.0, 0.0, -90.0]
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -131,6 +612,8 @@ pub struct LoggingConfig {
 pub struct LoggingSettings {
     pub directory: String,
     pub include_cameras: bool,
+    /// Reserved for MCAP file rotation; each recording is one file for now.
+    #[allow(dead_code)]
     pub rotate_on_size_mb: u64,
     pub default_topics: Vec<String>,
 }
@@ -0,0 +1,400 @@
+use crate::config::EkfConfig;
+use crate::services::kinematics::Twist;
+use crate::utils::normalize_angle;
+
+const N: usize = 6;
+const X: usize = 0;
+const Y: usize = 1;
+const THETA: usize = 2;
+const VX: usize = 3;
+const VY: usize = 4;
+const OMEGA: usize = 5;
+
+/// Velocity covariance before the first measurement; the pose starts exact.
+const INITIAL_VELOCITY_VAR: f64 = 1.0;
+
+type Matrix = [[f64; N]; N];
+
+/// Planar EKF over `[x, y, theta, vx, vy, omega]`, with the pose in odom and
+/// the velocities in `base_link`. The model is constant velocity, or constant
+/// acceleration when an IMU sample is available to drive it. Wheel odometry
+/// observes the whole twist and the gyro observes omega; with the gyro trusted
+/// more than the wheels, roller slip during spins no longer leaks into heading.
+#[derive(Debug, Clone)]
+pub struct PoseEkf {
+    state: [f64; N],
+    covariance: Matrix,
+    config: EkfConfig,
+}
+
+impl PoseEkf {
+    pub fn new(config: EkfConfig) -> Self {
+        let mut covariance = [[0.0; N]; N];
+        for i in [VX, VY, OMEGA] {
+            covariance[i][i] = INITIAL_VELOCITY_VAR;
+        }
+        Self {
+            state: [0.0; N],
+            covariance,
+            config,
+        }
+    }
+
+    /// Propagates the state by `dt`. `accel` is the body-frame (x, y) IMU
+    /// acceleration, or `None` to hold the velocity constant.
+    pub fn predict(&mut self, dt: f64, accel: Option<[f64; 2]>) {
+        let [x, y, theta, vx, vy, omega] = self.state;
+        let (sin_t, cos_t) = theta.sin_cos();
+
+        let mut next = self.state;
+        next[X] = x + (vx * cos_t - vy * sin_t) * dt;
+        next[Y] = y + (vx * sin_t + vy * cos_t) * dt;
+        next[THETA] = normalize_angle(theta + omega * dt);
+
+        let mut jacobian = identity();
+        jacobian[X][THETA] = -(vx * sin_t + vy * cos_t) * dt;
+        jacobian[X][VX] = cos_t * dt;
+        jacobian[X][VY] = -sin_t * dt;
+        jacobian[Y][THETA] = (vx * cos_t - vy * sin_t) * dt;
+        jacobian[Y][VX] = sin_t * dt;
+        jacobian[Y][VY] = cos_t * dt;
+        jacobian[THETA][OMEGA] = dt;
+
+        let accel_sigma = if let Some([ax, ay]) = accel {
+            // Body-frame velocity also rotates with the base: dv/dt = a - omega x v.
+            next[VX] = vx + (ax + omega * vy) * dt;
+            next[VY] = vy + (ay - omega * vx) * dt;
+            jacobian[VX][VY] = omega * dt;
+            jacobian[VX][OMEGA] = vy * dt;
+            jacobian[VY][VX] = -omega * dt;
+            jacobian[VY][OMEGA] = -vx * dt;
+            self.config.accel_noise as f64
+        } else {
+            self.config.accel_process_noise as f64
+        };
+
+        let mut covariance = multiply(
+            &multiply(&jacobian, &self.covariance),
+            &transpose(&jacobian),
+        );
+        let linear_var = (accel_sigma * dt).powi(2);
+        let angular_var = (self.config.alpha_process_noise as f64 * dt).powi(2);
+        covariance[VX][VX] += linear_var;
+        covariance[VY][VY] += linear_var;
+        covariance[OMEGA][OMEGA] += angular_var;
+
+        self.state = next;
+        self.covariance = covariance;
+    }
+
+    /// Fuses the body twist from the wheel encoders.
+    pub fn update_wheels(&mut self, twist: &Twist) {
+        let linear_var = (self.config.wheel_velocity_noise as f64).powi(2);
+        let angular_var = (self.config.wheel_omega_noise as f64).powi(2);
+        self.update(
+            [twist.vx_m_s, twist.vy_m_s, twist.omega_rad_s],
+            [VX, VY, OMEGA],
+            [linear_var, linear_var, angular_var],
+        );
+    }
+
+    /// Fuses the IMU yaw rate.
+    pub fn update_gyro(&mut self, omega_rad_s: f64) {
+        let var = (self.config.gyro_noise as f64).powi(2);
+        self.update([omega_rad_s], [OMEGA], [var]);
+    }
+
+    /// Drops the velocity estimate to zero, for when the wheel encoders go
+    /// silent and the pose must not keep integrating the last twist.
+    pub fn zero_velocity(&mut self) {
+        for i in [VX, VY, OMEGA] {
+            self.state[i] = 0.0;
+        }
+    }
+
+    /// Moves the pose to a known value and drops its uncertainty, along with
+    /// any correlation to the velocities, which are kept.
+    pub fn reset_pose(&mut self, x: f64, y: f64, theta: f64) {
+        self.state[X] = x;
+        self.state[Y] = y;
+        self.state[THETA] = normalize_angle(theta);
+        for i in [X, Y, THETA] {
+            for j in 0..N {
+                self.covariance[i][j] = 0.0;
+                self.covariance[j][i] = 0.0;
+            }
+        }
+    }
+
+    pub fn pose(&self) -> (f64, f64, f64) {
+        (self.state[X], self.state[Y], self.state[THETA])
+    }
+
+    pub fn twist(&self) -> Twist {
+        Twist {
+            vx_m_s: self.state[VX],
+            vy_m_s: self.state[VY],
+            omega_rad_s: self.state[OMEGA],
+        }
+    }
+
+    /// Row-major covariance of (x, y, theta).
+    pub fn pose_covariance(&self) -> [f64; 9] {
+        std::array::from_fn(|i| self.covariance[i / 3][i % 3])
+    }
+
+    /// Kalman update for a measurement that observes state entries directly,
+    /// so H is a row selection and S is a sub-block of P plus R.
+    fn update<const M: usize>(&mut self, z: [f64; M], indices: [usize; M], variances: [f64; M]) {
+        let mut innovation_cov = [[0.0; M]; M];
+        for (r, row) in innovation_cov.iter_mut().enumerate() {
+            for (c, value) in row.iter_mut().enumerate() {
+                *value = self.covariance[indices[r]][indices[c]];
+            }
+            row[r] += variances[r];
+        }
+        let Some(inverse) = invert(innovation_cov) else {
+            tracing::warn!("EKF innovation covariance is singular; skipping update");
+            return;
+        };
+
+        // K = P H^T S^-1, where P H^T is just the selected columns of P.
+        let gain: [[f64; M]; N] = std::array::from_fn(|i| {
+            std::array::from_fn(|k| {
+                (0..M)
+                    .map(|j| self.covariance[i][indices[j]] * inverse[j][k])
+                    .sum()
+            })
+        });
+
+        let residual: [f64; M] = std::array::from_fn(|k| z[k] - self.state[indices[k]]);
+        for (i, gain_row) in gain.iter().enumerate() {
+            self.state[i] += gain_row
+                .iter()
+                .zip(&residual)
+                .map(|(g, r)| g * r)
+                .sum::<f64>();
+        }
+        self.state[THETA] = normalize_angle(self.state[THETA]);
+
+        // P = (I - K H) P, then re-symmetrize against rounding.
+        let previous = self.covariance;
+        self.covariance = std::array::from_fn(|r| {
+            std::array::from_fn(|c| {
+                let correction: f64 = gain[r]
+                    .iter()
+                    .zip(&indices)
+                    .map(|(g, &j)| g * previous[j][c])
+                    .sum();
+                previous[r][c] - correction
+            })
+        });
+        for r in 0..N {
+            for c in r + 1..N {
+                let mean = 0.5 * (self.covariance[r][c] + self.covariance[c][r]);
+                self.covariance[r][c] = mean;
+                self.covariance[c][r] = mean;
+            }
+        }
+    }
+}
+
+fn identity() -> Matrix {
+    std::array::from_fn(|r| std::array::from_fn(|c| if r == c { 1.0 } else { 0.0 }))
+}
+
+fn transpose(m: &Matrix) -> Matrix {
+    std::array::from_fn(|r| std::array::from_fn(|c| m[c][r]))
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    std::array::from_fn(|r| std::array::from_fn(|c| (0..N).map(|k| a[r][k] * b[k][c]).sum()))
+}
+
+/// Gauss-Jordan inverse with partial pivoting for the small innovation blocks.
+fn invert<const M: usize>(mut m: [[f64; M]; M]) -> Option<[[f64; M]; M]> {
+    let mut inverse: [[f64; M]; M] =
+        std::array::from_fn(|r| std::array::from_fn(|c| if r == c { 1.0 } else { 0.0 }));
+    for col in 0..M {
+        let pivot = (col..M).max_by(|a, b| m[*a][col].abs().total_cmp(&m[*b][col].abs()))?;
+        if m[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        m.swap(col, pivot);
+        inverse.swap(col, pivot);
+
+        let scale = 1.0 / m[col][col];
+        for c in 0..M {
+            m[col][c] *= scale;
+            inverse[col][c] *= scale;
+        }
+        for r in 0..M {
+            if r == col {
+                continue;
+            }
+            let factor = m[r][col];
+            for c in 0..M {
+                m[r][c] -= factor * m[col][c];
+                inverse[r][c] -= factor * inverse[col][c];
+            }
+        }
+    }
+    Some(inverse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn twist(vx_m_s: f64, vy_m_s: f64, omega_rad_s: f64) -> Twist {
+        Twist {
+            vx_m_s,
+            vy_m_s,
+            omega_rad_s,
+        }
+    }
+
+    /// Symmetric, with a Cholesky factorization (positive definite).
+    fn assert_covariance_valid(ekf: &PoseEkf) {
+        let p = &ekf.covariance;
+        assert!(
+            p.iter()
+                .flatten()
+                .zip(transpose(p).iter().flatten())
+                .all(|(a, b)| (a - b).abs() < 1e-12),
+            "asymmetric covariance"
+        );
+        let mut l = [[0.0f64; N]; N];
+        for r in 0..N {
+            for c in 0..=r {
+                let sum: f64 = (0..c).map(|k| l[r][k] * l[c][k]).sum();
+                if r == c {
+                    let diagonal = p[r][r] - sum;
+                    assert!(diagonal > 0.0, "not positive definite at {r}");
+                    l[r][c] = diagonal.sqrt();
+                } else {
+                    l[r][c] = (p[r][c] - sum) / l[c][c];
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn predict_integrates_the_twist_in_odom() {
+        let mut ekf = PoseEkf::new(EkfConfig::default());
+        ekf.state[VX] = 0.5;
+        for _ in 0..100 {
+            ekf.predict(0.01, None);
+        }
+        let (x, y, theta) = ekf.pose();
+        assert!((x - 0.5).abs() < 1e-9 && y.abs() < 1e-9 && theta.abs() < 1e-9);
+
+        ekf.reset_pose(0.0, 0.0, std::f64::consts::FRAC_PI_2);
+        for _ in 0..100 {
+            ekf.predict(0.01, None);
+        }
+        let (x, y, _) = ekf.pose();
+        assert!(x.abs() < 1e-9 && (y - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn predict_applies_imu_acceleration() {
+        let mut ekf = PoseEkf::new(EkfConfig::default());
+        for _ in 0..100 {
+            ekf.predict(0.01, Some([1.0, 0.0]));
+        }
+        assert!((ekf.twist().vx_m_s - 1.0).abs() < 1e-9);
+        assert!((ekf.pose().0 - 0.495).abs() < 1e-9);
+    }
+
+    #[test]
+    fn predict_grows_pose_uncertainty() {
+        let mut ekf = PoseEkf::new(EkfConfig::default());
+        let before = ekf.pose_covariance();
+        ekf.predict(0.1, None);
+        let after = ekf.pose_covariance();
+        assert!(after[0] > before[0] && after[4] > before[4] && after[8] > before[8]);
+    }
+
+    #[test]
+    fn wheel_update_converges_on_the_measured_twist() {
+        let mut ekf = PoseEkf::new(EkfConfig::default());
+        let measured = twist(0.3, -0.1, 0.5);
+        for _ in 0..20 {
+            ekf.predict(0.02, None);
+            ekf.update_wheels(&measured);
+        }
+        let estimate = ekf.twist();
+        assert!((estimate.vx_m_s - 0.3).abs() < 0.01);
+        assert!((estimate.vy_m_s + 0.1).abs() < 0.01);
+        assert!((estimate.omega_rad_s - 0.5).abs() < 0.05);
+        assert_covariance_valid(&ekf);
+    }
+
+    #[test]
+    fn gyro_outweighs_wheel_omega() {
+        let mut ekf = PoseEkf::new(EkfConfig::default());
+        for _ in 0..50 {
+            ekf.predict(0.02, None);
+            // The wheels report a spin the rollers slipped through.
+            ekf.update_wheels(&twist(0.0, 0.0, 1.0));
+            ekf.update_gyro(0.6);
+        }
+        let omega = ekf.twist().omega_rad_s;
+        assert!((omega - 0.6).abs() < 0.05, "omega {omega}");
+        assert_covariance_valid(&ekf);
+    }
+
+    #[test]
+    fn reset_pose_clears_pose_uncertainty_and_keeps_velocity() {
+        let mut ekf = PoseEkf::new(EkfConfig::default());
+        for _ in 0..20 {
+            ekf.predict(0.02, None);
+            ekf.update_wheels(&twist(0.2, 0.0, 0.1));
+        }
+        let velocity = ekf.twist();
+        ekf.reset_pose(1.0, -2.0, 3.0 * std::f64::consts::PI);
+
+        let (x, y, theta) = ekf.pose();
+        assert_eq!((x, y), (1.0, -2.0));
+        assert!((theta + std::f64::consts::PI).abs() < 1e-9);
+        assert_eq!(ekf.pose_covariance(), [0.0; 9]);
+        assert_eq!(ekf.twist(), velocity);
+        for i in [X, Y, THETA] {
+            for j in 0..N {
+                assert_eq!(ekf.covariance[i][j], 0.0);
+                assert_eq!(ekf.covariance[j][i], 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn zero_velocity_stops_the_pose() {
+        let mut ekf = PoseEkf::new(EkfConfig::default());
+        ekf.update_wheels(&twist(0.4, 0.0, 0.2));
+        ekf.zero_velocity();
+        let pose = ekf.pose();
+        ekf.predict(0.5, None);
+        assert_eq!(ekf.pose(), pose);
+    }
+
+    #[test]
+    fn covariance_stays_valid_over_a_long_run() {
+        let mut ekf = PoseEkf::new(EkfConfig::default());
+        for i in 0..2000 {
+            let t = i as f64 * 0.02;
+            ekf.predict(0.02, Some([0.2 * t.sin(), 0.1 * t.cos()]));
+            if i % 2 == 0 {
+                ekf.update_wheels(&twist(0.3 * t.cos(), 0.1, 0.4 * t.sin()));
+            }
+            if i % 5 == 0 {
+                ekf.update_gyro(0.4 * t.sin());
+            }
+            if i % 500 == 0 {
+                ekf.reset_pose(0.0, 0.0, 0.0);
+                ekf.predict(0.02, None);
+            }
+            assert_covariance_valid(&ekf);
+        }
+    }
+}
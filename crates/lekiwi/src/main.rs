@@ -1,7 +1,10 @@
 mod bus;
+mod camera;
 mod config;
+mod ekf;
 mod messages;
 mod services;
+mod servo;
 mod telemetry;
 mod utils;
 
@@ -17,6 +20,7 @@ use crate::bus::Bus;
 use crate::config::AppConfig;
 use crate::services::{
     behavior_router, cameras, foxglove_server, kinematics, mcap_logger, motor_bus, state_estimator,
+    transforms,
 };
 use crate::telemetry::Telemetry;
 
@@ -48,7 +52,9 @@ enum Command {
     Kinematics,
     MotorBus,
     StateEstimator,
+    Transforms,
     Cameras,
+    ServoSim,
 }
 
 #[tokio::main]
@@ -63,7 +69,7 @@ async fn main() -> Result<()> {
     )?);
 
     let ctx = foxglove::Context::get_default();
-    let telemetry = Arc::new(Telemetry::new(&ctx)?);
+    let telemetry = Arc::new(Telemetry::new(&ctx, &config.cameras)?);
     let bus = Arc::new(Bus::new());
 
     let (shutdown_tx, shutdown_rx) = watch::channel(false);
@@ -73,6 +79,7 @@ async fn main() -> Result<()> {
         host: cli.foxglove_host,
         port: cli.foxglove_port,
         name: cli.foxglove_name,
+        teleop: config.robot.teleop.clone(),
     };
 
     match cli.command {
@@ -95,32 +102,39 @@ async fn main() -> Result<()> {
                 bus,
                 telemetry,
                 ctx,
-                config.logging.logging,
+                config.logging.logging.clone(),
+                config.cameras.clone(),
                 shutdown_rx,
             )
             .await?;
         }
         Command::BehaviorRouter => {
-            behavior_router::run(
-                bus,
-                telemetry,
-                config.robot.limits,
-                config.robot.safety,
-                shutdown_rx,
-            )
-            .await?;
+            behavior_router::run(bus, telemetry, config.robot.clone(), shutdown_rx).await?;
         }
         Command::Kinematics => {
-            kinematics::run(bus, shutdown_rx).await?;
+            kinematics::run(bus, telemetry, config.robot.drive.clone(), shutdown_rx).await?;
         }
         Command::MotorBus => {
-            motor_bus::run(bus, telemetry, config.robot, shutdown_rx).await?;
+            motor_bus::run(bus, telemetry, config.robot.clone(), shutdown_rx).await?;
         }
         Command::StateEstimator => {
-            state_estimator::run(bus, telemetry, config.robot.frames, shutdown_rx).await?;
+            state_estimator::run(bus, telemetry, config.robot.clone(), shutdown_rx).await?;
+        }
+        Command::Transforms => {
+            transforms::run(
+                bus,
+                telemetry,
+                config.robot.clone(),
+                config.cameras.clone(),
+                shutdown_rx,
+            )
+            .await?;
         }
         Command::Cameras => {
-            cameras::run(bus, telemetry, config.cameras, shutdown_rx).await?;
+            cameras::run(bus, telemetry, config.cameras.clone(), shutdown_rx).await?;
+        }
+        Command::ServoSim => {
+            servo::sim::run_pty_server(config.robot.clone(), shutdown_rx).await?;
         }
     }
 
@@ -135,57 +149,60 @@ async fn run_stack(
     foxglove_cfg: foxglove_server::FoxgloveConfig,
     mut shutdown: watch::Receiver<bool>,
 ) -> Result<()> {
-    let mut handles = Vec::new();
-
-    handles.push(tokio::spawn(foxglove_server::run(
-        foxglove_cfg,
-        ctx.clone(),
-        bus.clone(),
-        telemetry.clone(),
-        shutdown.clone(),
-    )));
-
-    handles.push(tokio::spawn(mcap_logger::run(
-        bus.clone(),
-        telemetry.clone(),
-        ctx.clone(),
-        config.logging.logging.clone(),
-        shutdown.clone(),
-    )));
-
-    handles.push(tokio::spawn(behavior_router::run(
-        bus.clone(),
-        telemetry.clone(),
-        config.robot.limits.clone(),
-        config.robot.safety.clone(),
-        shutdown.clone(),
-    )));
-
-    handles.push(tokio::spawn(kinematics::run(
-        bus.clone(),
-        shutdown.clone(),
-    )));
-
-    handles.push(tokio::spawn(state_estimator::run(
-        bus.clone(),
-        telemetry.clone(),
-        config.robot.frames.clone(),
-        shutdown.clone(),
-    )));
-
-    handles.push(tokio::spawn(motor_bus::run(
-        bus.clone(),
-        telemetry.clone(),
-        config.robot.clone(),
-        shutdown.clone(),
-    )));
-
-    handles.push(tokio::spawn(cameras::run(
-        bus.clone(),
-        telemetry.clone(),
-        config.cameras.clone(),
-        shutdown.clone(),
-    )));
+    let handles = vec![
+        tokio::spawn(foxglove_server::run(
+            foxglove_cfg,
+            ctx.clone(),
+            bus.clone(),
+            telemetry.clone(),
+            shutdown.clone(),
+        )),
+        tokio::spawn(mcap_logger::run(
+            bus.clone(),
+            telemetry.clone(),
+            ctx.clone(),
+            config.logging.logging.clone(),
+            config.cameras.clone(),
+            shutdown.clone(),
+        )),
+        tokio::spawn(behavior_router::run(
+            bus.clone(),
+            telemetry.clone(),
+            config.robot.clone(),
+            shutdown.clone(),
+        )),
+        tokio::spawn(kinematics::run(
+            bus.clone(),
+            telemetry.clone(),
+            config.robot.drive.clone(),
+            shutdown.clone(),
+        )),
+        tokio::spawn(state_estimator::run(
+            bus.clone(),
+            telemetry.clone(),
+            config.robot.clone(),
+            shutdown.clone(),
+        )),
+        tokio::spawn(transforms::run(
+            bus.clone(),
+            telemetry.clone(),
+            config.robot.clone(),
+            config.cameras.clone(),
+            shutdown.clone(),
+        )),
+        tokio::spawn(motor_bus::run(
+            bus.clone(),
+            telemetry.clone(),
+            config.robot.clone(),
+            shutdown.clone(),
+        )),
+        tokio::spawn(cameras::run(
+            bus.clone(),
+            telemetry.clone(),
+            config.cameras.clone(),
+            shutdown.clone(),
+        )),
+    ];
 
     wait_for_shutdown(&mut shutdown).await;
 
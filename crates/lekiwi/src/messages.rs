@@ -1,3 +1,4 @@
+use foxglove::bytes::Bytes;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
@@ -10,6 +11,10 @@ pub struct VelocityCommand {
     pub omega_rad_s: f32,
     pub source: String,
     pub priority: i32,
+    /// Per-client counter that must increase with every command; doubles as
+    /// the teleop heartbeat.
+    #[serde(default)]
+    pub seq: Option<u64>,
 }
 
 impl VelocityCommand {
@@ -21,8 +26,13 @@ impl VelocityCommand {
             omega_rad_s: 0.0,
             source: source.into(),
             priority: 0,
+            seq: None,
         }
     }
+
+    pub fn is_zero(&self) -> bool {
+        self.vx_m_s == 0.0 && self.vy_m_s == 0.0 && self.omega_rad_s == 0.0
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -37,144 +47,121 @@ pub struct SkillCommand {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct EstopCommand {
+pub struct SkillCancel {
     #[serde(default)]
     pub timestamp_ns: u64,
-    pub enabled: bool,
+    pub request_id: String,
+    #[serde(default)]
     pub reason: String,
-    pub source: String,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum LogAction {
-    Start,
-    Stop,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct LogControl {
-    #[serde(default)]
-    pub timestamp_ns: u64,
-    pub action: LogAction,
-    pub topics: Option<Vec<String>>,
-    pub session_name: Option<String>,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkillState {
+    Accepted,
+    Running,
+    Succeeded,
+    Failed,
+    TimedOut,
+    Rejected,
+    /// Replaced by a request of equal or higher priority, or by manual teleop.
+    Preempted,
+    /// Stopped by `/cmd/skill_cancel`.
+    Cancelled,
+    /// Stopped by e-stop.
+    Aborted,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct LogStatus {
-    pub timestamp_ns: u64,
-    pub active: bool,
-    pub file_path: Option<String>,
-    pub size_bytes: Option<u64>,
-    pub duration_s: Option<f64>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Odometry {
-    pub timestamp_ns: u64,
+pub struct Pose2D {
     pub x_m: f64,
     pub y_m: f64,
     pub theta_rad: f64,
-    pub vx_m_s: f32,
-    pub vy_m_s: f32,
-    pub omega_rad_s: f32,
-    pub frame_id: String,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ServoState {
-    pub id: u8,
-    pub name: String,
-    pub position_rad: f32,
-    pub velocity_rad_s: f32,
-    pub load: f32,
-    pub temperature_c: f32,
-    pub voltage_v: f32,
-    pub error_flags: u32,
 }
 
+/// Lifecycle of one `SkillCommand`, keyed by its `request_id`. `final_pose`
+/// is the odometry pose when the skill ended, if odometry was fresh.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ServoStateArray {
+pub struct SkillStatus {
     pub timestamp_ns: u64,
-    pub servos: Vec<ServoState>,
+    pub request_id: String,
+    pub skill_name: String,
+    pub state: SkillState,
+    pub progress: f32,
+    pub error: Option<String>,
+    pub final_pose: Option<Pose2D>,
 }
 
+/// Engages (`enabled: true`) or releases this source's e-stop hold. Releasing
+/// the last hold leaves the stop latched until an [`EstopReset`].
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct PowerState {
+pub struct EstopCommand {
+    #[serde(default)]
     pub timestamp_ns: u64,
-    pub battery_voltage_v: f32,
-    pub battery_percent: f32,
-    pub low_battery: bool,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum DiagnosticStatus {
-    Ok,
-    Warn,
-    Error,
+    pub enabled: bool,
+    pub reason: String,
+    /// Set by foxglove_server from the connection; clients may omit it.
+    #[serde(default)]
+    pub source: String,
 }
 
+/// Clears a latched e-stop once every source has released it.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Diagnostics {
+pub struct EstopReset {
+    #[serde(default)]
     pub timestamp_ns: u64,
-    pub status: DiagnosticStatus,
-    pub warnings: Vec<String>,
-    pub last_error: Option<String>,
-    pub uptime_s: f64,
+    /// Set by foxglove_server from the connection; clients may omit it.
+    #[serde(default)]
+    pub source: String,
+    #[serde(default)]
+    pub reason: String,
 }
 
+/// Switches the speed mode and/or the runtime scale on top of it; omitted
+/// fields keep their current value.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct CameraFrame {
+pub struct SpeedScaleCommand {
+    #[serde(default)]
     pub timestamp_ns: u64,
-    pub camera_name: String,
-    pub frame_id: String,
-    pub width: u32,
-    pub height: u32,
-    pub encoding: String,
-    pub data_base64: String,
+    #[serde(default)]
+    pub mode: Option<String>,
+    /// 0.0 to 1.0.
+    #[serde(default)]
+    pub scale: Option<f32>,
+    #[serde(default)]
+    pub source: String,
 }
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct VelocityCommand {
-    pub timestamp_ns: u64,
-    pub vx_m_s: f32,
-    pub vy_m_s: f32,
-    pub omega_rad_s: f32,
+pub struct EstopHold {
     pub source: String,
-    pub priority: i32,
-}
-
-impl VelocityCommand {
-    pub fn zero(source: &str, timestamp_ns: u64) -> Self {
-        Self {
-            timestamp_ns,
-            vx_m_s: 0.0,
-            vy_m_s: 0.0,
-            omega_rad_s: 0.0,
-            source: source.to_string(),
-            priority: 0,
-        }
-    }
+    pub reason: String,
+    pub since_ns: u64,
 }
 
+/// E-stop latch state, published on every engage, release and reset attempt.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SkillCommand {
-    pub request_id: String,
-    pub skill_name: String,
-    pub params: Value,
-    pub timeout_s: f32,
-    pub priority: i32,
+pub struct EstopState {
+    pub timestamp_ns: u64,
+    /// Motion is blocked while latched.
+    pub latched: bool,
+    pub holds: Vec<EstopHold>,
+    /// What changed, e.g. "engaged by foxglove: operator".
+    pub event: String,
 }
 
+/// Re-seeds the estimator pose in the odom frame; an empty payload zeroes it.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct EstopCommand {
+pub struct SetPoseCommand {
+    #[serde(default)]
     pub timestamp_ns: u64,
-    pub enabled: bool,
-    pub reason: String,
+    #[serde(default)]
+    pub x_m: f64,
+    #[serde(default)]
+    pub y_m: f64,
+    #[serde(default)]
+    pub theta_rad: f64,
+    #[serde(default)]
     pub source: String,
 }
 
@@ -187,6 +174,7 @@ pub enum LogAction {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogControl {
+    #[serde(default)]
     pub timestamp_ns: u64,
     pub action: LogAction,
     pub topics: Option<Vec<String>>,
@@ -212,6 +200,24 @@ pub struct Odometry {
     pub vy_m_s: f32,
     pub omega_rad_s: f32,
     pub frame_id: String,
+    /// Row-major 3x3 covariance of (x_m, y_m, theta_rad); only the EKF fills it.
+    #[serde(default)]
+    pub pose_covariance: Option<[f64; 9]>,
+}
+
+/// Emitted by state_estimator whenever `/cmd/set_pose` moves the pose, so
+/// recordings show where odometry jumps.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PoseReset {
+    pub timestamp_ns: u64,
+    pub previous_x_m: f64,
+    pub previous_y_m: f64,
+    pub previous_theta_rad: f64,
+    pub x_m: f64,
+    pub y_m: f64,
+    pub theta_rad: f64,
+    pub source: String,
+    pub frame_id: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -232,6 +238,37 @@ pub struct ServoStateArray {
     pub servos: Vec<ServoState>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WheelTarget {
+    pub name: String,
+    pub servo_id: u8,
+    pub velocity_rad_s: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WheelTargetArray {
+    pub timestamp_ns: u64,
+    pub wheels: Vec<WheelTarget>,
+    /// Uniform twist scale applied so no wheel exceeds its speed limit (1.0 = none).
+    pub saturation_scale: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServoBusHealth {
+    pub timestamp_ns: u64,
+    pub connected: bool,
+    pub torque_enabled: bool,
+    pub responding_ids: Vec<u8>,
+    pub missing_ids: Vec<u8>,
+    pub last_error: Option<String>,
+}
+
+impl ServoBusHealth {
+    pub fn healthy(&self) -> bool {
+        self.connected && self.torque_enabled && self.missing_ids.is_empty()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PowerState {
     pub timestamp_ns: u64,
@@ -255,8 +292,53 @@ pub struct Diagnostics {
     pub warnings: Vec<String>,
     pub last_error: Option<String>,
     pub uptime_s: f64,
+    /// Velocity source currently in control, if any.
+    #[serde(default)]
+    pub active_source: Option<String>,
+    /// Speed mode in effect and the combined scale applied to the limits.
+    #[serde(default)]
+    pub speed_mode: Option<String>,
+    #[serde(default)]
+    pub speed_scale: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SafetyEventKind {
+    /// Sustained high load while the wheel fails to track its target.
+    Stall,
+    /// Sudden load spike, e.g. driving into an obstacle.
+    Impact,
+}
+
+/// A wheel tripped stall or impact detection; motion is held at zero until
+/// the velocity command returns to zero.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SafetyEvent {
+    pub timestamp_ns: u64,
+    pub kind: SafetyEventKind,
+    pub wheel: String,
+    pub servo_id: u8,
+    pub load: f32,
+    pub commanded_rad_s: f32,
+    pub measured_rad_s: f32,
+    pub detail: String,
+}
+
+/// Body-frame IMU sample. Only the yaw rate (z) and the planar acceleration
+/// (x, y) feed the estimator.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Imu {
+    #[serde(default)]
+    pub timestamp_ns: u64,
+    #[serde(default)]
+    pub frame_id: String,
+    pub angular_velocity_rad_s: [f32; 3],
+    pub linear_acceleration_m_s2: [f32; 3],
 }
 
+/// One encoded frame. `data` is shared, so fanning frames out over the bus
+/// does not copy the image; only the legacy JSON form carries base64.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CameraFrame {
     pub timestamp_ns: u64,
@@ -265,5 +347,46 @@ pub struct CameraFrame {
     pub width: u32,
     pub height: u32,
     pub encoding: String,
-    pub data_base64: String,
+    #[serde(rename = "data_base64", with = "base64_bytes")]
+    pub data: Bytes,
+}
+
+/// Capture health for one camera, published once a second. Counters are
+/// totals since startup and survive reconnects.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CameraStats {
+    pub timestamp_ns: u64,
+    pub camera_name: String,
+    /// The device is open and streaming.
+    pub connected: bool,
+    pub fps_target: u32,
+    /// Frames delivered over the last report period.
+    pub fps_measured: f32,
+    pub frames: u64,
+    /// Frames the driver skipped, from gaps in its sequence numbers.
+    pub dropped_frames: u64,
+    /// Frames that arrived more than 1.5 periods after the previous one.
+    pub late_frames: u64,
+    pub reconnects: u64,
+    /// Time since the last frame, or since the device was opened.
+    pub last_frame_age_s: Option<f64>,
+    pub last_error: Option<String>,
+}
+
+mod base64_bytes {
+    use base64::prelude::{Engine, BASE64_STANDARD};
+    use foxglove::bytes::Bytes;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &Bytes, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&BASE64_STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Bytes, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        BASE64_STANDARD
+            .decode(encoded)
+            .map(Bytes::from)
+            .map_err(serde::de::Error::custom)
+    }
 }
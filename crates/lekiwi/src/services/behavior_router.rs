@@ -1,3 +1,13 @@
+mod arbitration;
+mod cameras;
+mod estop;
+mod geofence;
+mod limits;
+mod skills;
+mod speed;
+mod stall;
+mod supervisor;
+
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
@@ -5,31 +15,77 @@ use anyhow::Result;
 use tokio::sync::watch;
 
 use crate::bus::Bus;
-use crate::config::{LimitsConfig, SafetyConfig};
-use crate::messages::{DiagnosticStatus, Diagnostics, VelocityCommand};
+use crate::config::RobotConfig;
+use crate::messages::{
+    DiagnosticStatus, Diagnostics, EstopState, Odometry, ServoBusHealth, VelocityCommand,
+};
 use crate::telemetry::Telemetry;
 use crate::utils::now_nanos;
 
+pub use self::arbitration::{source_matches, SKILL_SOURCE};
+
+use self::arbitration::Arbiter;
+use self::cameras::CameraMonitor;
+use self::estop::{EstopLatch, EstopWatchdog};
+use self::geofence::Geofence;
+use self::limits::MotionLimiter;
+use self::skills::{FrameShift, SkillExecutor};
+use self::speed::SpeedControl;
+use self::stall::StallDetector;
+use self::supervisor::SafetySupervisor;
+
 const TICK_HZ: u64 = 50;
+const SERVO_HEALTH_TIMEOUT: Duration = Duration::from_secs(3);
+const ODOMETRY_TIMEOUT: Duration = Duration::from_millis(500);
 
 pub async fn run(
     bus: Arc<Bus>,
     telemetry: Arc<Telemetry>,
-    limits: LimitsConfig,
-    safety: SafetyConfig,
+    robot: RobotConfig,
     mut shutdown: watch::Receiver<bool>,
 ) -> Result<()> {
+    let safety = robot.safety;
+    let mut arbiter = Arbiter::new(&robot.arbitration)?;
     let mut cmd_rx = bus.cmd_in.subscribe();
     let mut estop_rx = bus.cmd_estop.subscribe();
+    let mut reset_rx = bus.cmd_estop_reset.subscribe();
+    let mut health_rx = bus.servo_health.subscribe();
+    let mut servos_rx = bus.servos.subscribe();
+    let mut power_rx = bus.power.subscribe();
+    let mut wheels_rx = bus.wheel_targets.subscribe();
+    let mut skill_rx = bus.cmd_skill.subscribe();
+    let mut cancel_rx = bus.cmd_skill_cancel.subscribe();
+    let mut odom_rx = bus.odometry.subscribe();
+    let mut pose_reset_rx = bus.pose_reset.subscribe();
+    let mut speed_rx = bus.cmd_speed_scale.subscribe();
+    let mut camera_stats_rx = bus.camera_stats.subscribe();
 
     let mut interval = tokio::time::interval(Duration::from_millis(1000 / TICK_HZ));
     let start = Instant::now();
-    let mut last_cmd = VelocityCommand::zero("behavior_router", now_nanos());
-    let mut last_seen = Instant::now();
-    let mut last_output = last_cmd.clone();
+    let mut limiter = MotionLimiter::new(
+        robot.limits.clone(),
+        VelocityCommand::zero("behavior_router", now_nanos()),
+    );
+    let mut speed = SpeedControl::new(&robot.speed_modes)?;
+    limiter.set_scale(speed.speed_scale(), speed.accel_scale());
+    let mut active_source: Option<String> = None;
     let mut last_update = Instant::now();
     let mut last_diag = Instant::now();
-    let mut estop_active = false;
+    let mut estop = EstopLatch::new(safety.estop_reset_sources.clone());
+    let mut bus_watchdog = EstopWatchdog::new("motor_bus");
+    let mut servo_health: Option<(ServoBusHealth, Instant)> = None;
+    let mut supervisor = SafetySupervisor::new(safety.clone());
+    let mut stalls = StallDetector::new(&robot.drive.wheel_mounts);
+    let mut geofence = robot
+        .geofence
+        .clone()
+        .map(|config| Geofence::new(config, &robot.limits, 1.0 / TICK_HZ as f64))
+        .transpose()?;
+    let mut geofence_warning: Option<String> = None;
+    let mut min_saturation_scale = 1.0f32;
+    let mut skills = SkillExecutor::new(&robot.skills);
+    let mut odometry: Option<(Odometry, Instant)> = None;
+    let mut cameras = CameraMonitor::default();
 
     loop {
         tokio::select! {
@@ -38,19 +94,66 @@ pub async fn run(
                 let dt = (now - last_update).as_secs_f32().max(1e-3);
                 last_update = now;
 
-                let timed_out = now.duration_since(last_seen).as_millis() as u64 > safety.command_timeout_ms;
-                let mut target = if timed_out {
-                    VelocityCommand::zero("timeout", now_nanos())
-                } else {
-                    clamp_velocity(&last_cmd, &limits, now_nanos())
+                match skills.step(fresh_odometry(odometry.as_ref())) {
+                    Some(cmd) => {
+                        arbiter.submit(cmd);
+                    }
+                    None => arbiter.release(SKILL_SOURCE),
+                }
+                publish_skill_statuses(&bus, &telemetry, &mut skills);
+
+                let active = arbiter.active();
+                let source = active.map(|(name, _)| name);
+                if source != active_source.as_deref() {
+                    match source {
+                        Some(name) => tracing::info!("Velocity source '{name}' in control"),
+                        None => tracing::info!("No live velocity source; holding zero"),
+                    }
+                    active_source = source.map(str::to_string);
+                }
+                stalls.clear_if_idle(active.is_none_or(|(_, cmd)| cmd.is_zero()));
+                let mut target = match active {
+                    Some((_, cmd)) => limiter.clamp(cmd, now_nanos()),
+                    None => VelocityCommand::zero("timeout", now_nanos()),
                 };
+                if let Some(fence) = geofence.as_mut() {
+                    geofence_warning = fence.limit(&mut target, fresh_odometry(odometry.as_ref()));
+                }
 
-                if estop_active && safety.estop_enabled {
-                    target = VelocityCommand::zero("estop", now_nanos());
+                let assessment = supervisor.assess();
+                let forced_stop = if estop.latched() && safety.estop_enabled {
+                    Some("estop")
+                } else if stalls.tripped().is_some() {
+                    Some("stall")
+                } else if assessment.stop.is_some() {
+                    Some("safety")
+                } else {
+                    None
+                };
+                match forced_stop {
+                    Some(reason) => target = VelocityCommand::zero(reason, now_nanos()),
+                    None => {
+                        target.vx_m_s *= assessment.scale;
+                        target.vy_m_s *= assessment.scale;
+                        target.omega_rad_s *= assessment.scale;
+                    }
                 }
 
-                let output = apply_rate_limits(&last_output, &target, &limits, dt);
-                last_output = output.clone();
+                let torque_block = torque_block_reason(servo_health.as_ref());
+                let bus_connected = servo_health.as_ref().is_some_and(|(health, received)| {
+                    health.connected && received.elapsed() <= SERVO_HEALTH_TIMEOUT
+                });
+                let bus_lost = torque_block.clone().filter(|_| !bus_connected);
+                if let Some(cmd) = bus_watchdog.update(bus_lost) {
+                    let _ = bus.cmd_estop.send(cmd);
+                }
+                let output = if torque_block.is_some() {
+                    let output = VelocityCommand::zero("torque_off", now_nanos());
+                    limiter.hold(&output);
+                    output
+                } else {
+                    limiter.step(&target, dt, forced_stop.is_none())
+                };
 
                 telemetry.log_cmd_velocity(&output);
                 let _ = bus.cmd_out.send(output);
@@ -58,14 +161,34 @@ pub async fn run(
                 if last_diag.elapsed() >= Duration::from_secs(1) {
                     last_diag = Instant::now();
                     let mut warnings = Vec::new();
-                    if timed_out {
+                    if active_source.is_none() {
                         warnings.push("command_timeout".to_string());
                     }
-                    if estop_active {
-                        warnings.push("estop_active".to_string());
+                    if let Some(warning) = estop.warning() {
+                        warnings.push(warning);
+                    }
+                    if let Some(warning) = &geofence_warning {
+                        warnings.push(warning.clone());
+                    }
+                    if let Some(detail) = stalls.tripped() {
+                        warnings.push(format!("stall_hold: {detail}"));
+                    }
+                    if let Some(reason) = torque_block {
+                        warnings.push(format!("torque_off: {reason}"));
                     }
+                    if min_saturation_scale < 1.0 {
+                        warnings.push(format!(
+                            "wheel_saturation: twist scaled to {:.0}%",
+                            min_saturation_scale * 100.0
+                        ));
+                    }
+                    min_saturation_scale = 1.0;
+                    warnings.extend(assessment.warnings);
+                    warnings.extend(cameras.warnings());
 
-                    let status = if warnings.is_empty() {
+                    let status = if assessment.stop.is_some() {
+                        DiagnosticStatus::Error
+                    } else if warnings.is_empty() {
                         DiagnosticStatus::Ok
                     } else {
                         DiagnosticStatus::Warn
@@ -75,20 +198,116 @@ pub async fn run(
                         timestamp_ns: now_nanos(),
                         status,
                         warnings,
-                        last_error: None,
+                        last_error: assessment.stop,
                         uptime_s: start.elapsed().as_secs_f64(),
+                        active_source: active_source.clone(),
+                        speed_mode: Some(speed.mode_name().to_string()),
+                        speed_scale: Some(speed.speed_scale()),
                     };
                     telemetry.log_diagnostics(&diag);
                     let _ = bus.diagnostics.send(diag);
+
+                    if let Some(fence) = &geofence {
+                        let timestamp_ns = now_nanos();
+                        telemetry.log_geofence(&fence.scene(&robot.frames.odom, timestamp_ns), timestamp_ns);
+                    }
                 }
             }
             Ok(cmd) = cmd_rx.recv() => {
-                last_cmd = cmd;
-                last_seen = Instant::now();
+                let sender = cmd.source.clone();
+                let slot = arbiter.submit(cmd.clone()).to_string();
+                if skills.is_active() && arbiter.takes_over_skill(&slot, &cmd) {
+                    let reason = if sender == slot {
+                        format!("/cmd/velocity from '{slot}'")
+                    } else {
+                        format!("/cmd/velocity from '{sender}' ({slot})")
+                    };
+                    skills.preempt(reason, fresh_odometry(odometry.as_ref()));
+                    publish_skill_statuses(&bus, &telemetry, &mut skills);
+                }
+            }
+            Ok(cmd) = skill_rx.recv() => {
+                if estop.latched() && safety.estop_enabled {
+                    skills.reject(cmd, "estop active".to_string());
+                } else if let Some(source) = arbiter.live_besides(SKILL_SOURCE) {
+                    skills.reject(cmd, format!("{source} in control"));
+                } else {
+                    skills.submit(cmd, fresh_odometry(odometry.as_ref()));
+                }
+                publish_skill_statuses(&bus, &telemetry, &mut skills);
+            }
+            Ok(cmd) = cancel_rx.recv() => {
+                let reason = if cmd.reason.is_empty() {
+                    "cancel requested".to_string()
+                } else {
+                    cmd.reason.clone()
+                };
+                if !skills.cancel(&cmd.request_id, reason, fresh_odometry(odometry.as_ref())) {
+                    tracing::warn!("No running skill with request_id {} to cancel", cmd.request_id);
+                }
+                publish_skill_statuses(&bus, &telemetry, &mut skills);
+            }
+            Ok(cmd) = speed_rx.recv() => {
+                if speed.apply(&cmd) {
+                    limiter.set_scale(speed.speed_scale(), speed.accel_scale());
+                }
+            }
+            Ok(odom) = odom_rx.recv() => {
+                odometry = Some((odom, Instant::now()));
+            }
+            Ok(reset) = pose_reset_rx.recv() => {
+                let shift = FrameShift::new(&reset);
+                // Odometry from before the reset is still in the old frame.
+                if let Some((odom, _)) = odometry.as_mut() {
+                    if odom.timestamp_ns < reset.timestamp_ns {
+                        (odom.x_m, odom.y_m) = shift.point(odom.x_m, odom.y_m);
+                        odom.theta_rad = shift.heading(odom.theta_rad);
+                    }
+                }
+                skills.rebase(&shift);
+            }
+            Ok(targets) = wheels_rx.recv() => {
+                min_saturation_scale = min_saturation_scale.min(targets.saturation_scale);
+                stalls.update_targets(targets);
+            }
+            Ok(state) = servos_rx.recv() => {
+                if let Some(event) = stalls.update_servos(&state) {
+                    skills.abort(event.detail.clone(), fresh_odometry(odometry.as_ref()));
+                    publish_skill_statuses(&bus, &telemetry, &mut skills);
+                    telemetry.log_safety_event(&event);
+                    let _ = bus.safety_event.send(event);
+                }
+                supervisor.update_servos(state);
+            }
+            Ok(power) = power_rx.recv() => {
+                supervisor.update_power(&power);
+            }
+            Ok(health) = health_rx.recv() => {
+                servo_health = Some((health, Instant::now()));
+            }
+            Ok(stats) = camera_stats_rx.recv() => {
+                cameras.update(stats);
             }
             Ok(cmd) = estop_rx.recv() => {
-                estop_active = cmd.enabled;
                 telemetry.log_cmd_estop(&cmd);
+                let state = if cmd.enabled {
+                    estop.engage(&cmd)
+                } else {
+                    estop.release(&cmd)
+                };
+                publish_estop_state(&bus, &telemetry, state);
+                if cmd.enabled && safety.estop_enabled {
+                    skills.abort(format!("estop: {}", cmd.reason), fresh_odometry(odometry.as_ref()));
+                    publish_skill_statuses(&bus, &telemetry, &mut skills);
+                }
+            }
+            Ok(cmd) = reset_rx.recv() => {
+                let moving = arbiter.live().find(|(_, cmd)| !cmd.is_zero()).map(|(name, _)| name);
+                let (state, accepted) = estop.reset(&cmd, moving);
+                publish_estop_state(&bus, &telemetry, state);
+                if accepted {
+                    supervisor.reset();
+                }
             }
             _ = shutdown.changed() => {
                 break;
@@ -99,169 +318,42 @@ pub async fn run(
     Ok(())
 }
 
-fn clamp_velocity(cmd: &VelocityCommand, limits: &LimitsConfig, timestamp_ns: u64) -> VelocityCommand {
-    let mut clamped = cmd.clone();
-    clamped.timestamp_ns = timestamp_ns;
-    clamped.vx_m_s = clamp(cmd.vx_m_s, limits.max_vx_m_s);
-    clamped.vy_m_s = clamp(cmd.vy_m_s, limits.max_vy_m_s);
-    clamped.omega_rad_s = clamp(cmd.omega_rad_s, limits.max_omega_rad_s);
-    clamped.source = "behavior_router".to_string();
-    clamped
+fn publish_skill_statuses(bus: &Bus, telemetry: &Telemetry, skills: &mut SkillExecutor) {
+    for status in skills.take_statuses() {
+        telemetry.log_skill_status(&status);
+        let _ = bus.skill_status.send(status);
+    }
 }
 
-fn apply_rate_limits(
-    last: &VelocityCommand,
-    target: &VelocityCommand,
-    limits: &LimitsConfig,
-    dt: f32,
-) -> VelocityCommand {
-    let max_dv = limits.max_accel_m_s2 * dt;
-    let max_dw = limits.max_alpha_rad_s2 * dt;
-
-    let mut output = target.clone();
-    output.vx_m_s = limit_delta(last.vx_m_s, target.vx_m_s, max_dv);
-    output.vy_m_s = limit_delta(last.vy_m_s, target.vy_m_s, max_dv);
-    output.omega_rad_s = limit_delta(last.omega_rad_s, target.omega_rad_s, max_dw);
-    output
+fn publish_estop_state(bus: &Bus, telemetry: &Telemetry, state: EstopState) {
+    telemetry.log_estop_state(&state);
+    let _ = bus.estop_state.send(state);
 }
 
-fn clamp(value: f32, max: f32) -> f32 {
-    if value > max {
-        max
-    } else if value < -max {
-        -max
-    } else {
-        value
-    }
+fn fresh_odometry(odometry: Option<&(Odometry, Instant)>) -> Option<&Odometry> {
+    odometry
+        .filter(|(_, received)| received.elapsed() <= ODOMETRY_TIMEOUT)
+        .map(|(odom, _)| odom)
 }
 
-fn limit_delta(current: f32, target: f32, max_delta: f32) -> f32 {
-    let delta = target - current;
-    if delta > max_delta {
-        current + max_delta
-    } else if delta < -max_delta {
-        current - max_delta
-    } else {
-        target
+/// Motion is refused until motor_bus reports every servo answering with
+/// torque on; returns why output is being held at zero.
+fn torque_block_reason(health: Option<&(ServoBusHealth, Instant)>) -> Option<String> {
+    let Some((health, received)) = health else {
+        return Some("waiting for servo bus health".to_string());
+    };
+    if received.elapsed() > SERVO_HEALTH_TIMEOUT {
+        return Some("servo bus health stale".to_string());
     }
-}
-use std::sync::Arc;
-use std::time::Duration;
-
-use anyhow::Result;
-use tokio::sync::watch;
-use tokio::time::{interval, Instant};
-
-use crate::bus::Bus;
-use crate::config::{LimitsConfig, SafetyConfig};
-use crate::messages::{DiagnosticStatus, Diagnostics, EstopCommand, VelocityCommand};
-use crate::telemetry::Telemetry;
-use crate::utils::now_nanos;
-
-pub async fn run(
-    bus: Arc<Bus>,
-    telemetry: Arc<Telemetry>,
-    limits: LimitsConfig,
-    safety: SafetyConfig,
-    mut shutdown: watch::Receiver<bool>,
-) -> Result<()> {
-    let mut cmd_rx = bus.cmd_in.subscribe();
-    let mut estop_rx = bus.cmd_estop.subscribe();
-    let mut ticker = interval(Duration::from_millis(20));
-    let mut last_cmd = VelocityCommand::zero("behavior_router", now_nanos());
-    let mut last_seen = Instant::now();
-    let mut last_output = last_cmd.clone();
-    let mut estop_active = safety.estop_enabled && false;
-    let mut last_diag = Instant::now();
-    let start_time = Instant::now();
-
-    loop {
-        tokio::select! {
-            _ = ticker.tick() => {
-                let now = Instant::now();
-                let timed_out = now.duration_since(last_seen).as_millis() as u64 > safety.command_timeout_ms;
-                let mut target = if timed_out {
-                    VelocityCommand::zero("timeout", now_nanos())
-                } else {
-                    clamp_velocity(&last_cmd, &limits, now_nanos())
-                };
-
-                if estop_active {
-                    target = VelocityCommand::zero("estop", now_nanos());
-                }
-
-                let dt = 0.02;
-                target.vx_m_s = rate_limit(target.vx_m_s, last_output.vx_m_s, limits.max_accel_m_s2 * dt);
-                target.vy_m_s = rate_limit(target.vy_m_s, last_output.vy_m_s, limits.max_accel_m_s2 * dt);
-                target.omega_rad_s = rate_limit(
-                    target.omega_rad_s,
-                    last_output.omega_rad_s,
-                    limits.max_alpha_rad_s2 * dt,
-                );
-
-                last_output = target.clone();
-                telemetry.log_cmd_velocity(&target);
-                let _ = bus.cmd_out.send(target);
-
-                if last_diag.elapsed() >= Duration::from_secs(1) {
-                    let mut warnings = Vec::new();
-                    let status = if estop_active {
-                        warnings.push("estop_active".to_string());
-                        DiagnosticStatus::Error
-                    } else if timed_out {
-                        warnings.push("command_timeout".to_string());
-                        DiagnosticStatus::Warn
-                    } else {
-                        DiagnosticStatus::Ok
-                    };
-                    let diag = Diagnostics {
-                        timestamp_ns: now_nanos(),
-                        status,
-                        warnings,
-                        last_error: None,
-                        uptime_s: start_time.elapsed().as_secs_f64(),
-                    };
-                    telemetry.log_diagnostics(&diag);
-                    let _ = bus.diagnostics.send(diag);
-                    last_diag = Instant::now();
-                }
-            }
-            Ok(cmd) = cmd_rx.recv() => {
-                last_cmd = cmd;
-                last_seen = Instant::now();
-            }
-            Ok(estop) = estop_rx.recv() => {
-                estop_active = estop.enabled;
-                telemetry.log_cmd_estop(&estop);
-            }
-            _ = shutdown.changed() => {
-                if *shutdown.borrow() {
-                    break;
-                }
-            }
-        }
+    if health.healthy() {
+        return None;
     }
-    Ok(())
-}
-
-fn clamp_velocity(cmd: &VelocityCommand, limits: &LimitsConfig, timestamp_ns: u64) -> VelocityCommand {
-    let mut out = cmd.clone();
-    out.timestamp_ns = timestamp_ns;
-    out.vx_m_s = clamp(cmd.vx_m_s, limits.max_vx_m_s);
-    out.vy_m_s = clamp(cmd.vy_m_s, limits.max_vy_m_s);
-    out.omega_rad_s = clamp(cmd.omega_rad_s, limits.max_omega_rad_s);
-    out
-}
-
-fn clamp(value: f32, max_abs: f32) -> f32 {
-    value.max(-max_abs).min(max_abs)
-}
-
-fn rate_limit(target: f32, current: f32, max_delta: f32) -> f32 {
-    let delta = target - current;
-    if delta.abs() > max_delta {
-        current + delta.signum() * max_delta
-    } else {
-        target
+    if !health.connected {
+        let detail = health.last_error.as_deref().unwrap_or("not connected");
+        return Some(format!("servo bus disconnected ({detail})"));
+    }
+    if !health.missing_ids.is_empty() {
+        return Some(format!("servos {:?} not responding", health.missing_ids));
     }
+    Some("servo torque disabled".to_string())
 }
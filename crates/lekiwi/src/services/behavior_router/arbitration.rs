@@ -0,0 +1,201 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, ensure, Result};
+
+use crate::config::{ArbitrationConfig, CommandSourceConfig};
+use crate::messages::VelocityCommand;
+
+/// Slot the skill executor's output is routed through.
+pub const SKILL_SOURCE: &str = "skill";
+
+struct Slot {
+    name: String,
+    lease: Duration,
+    latest: Option<(VelocityCommand, Instant)>,
+}
+
+impl Slot {
+    fn live(&self) -> Option<&VelocityCommand> {
+        self.latest
+            .as_ref()
+            .filter(|(_, received)| received.elapsed() <= self.lease)
+            .map(|(cmd, _)| cmd)
+    }
+}
+
+/// One command slot per configured source. The highest-priority slot whose
+/// lease is live drives the base; when it goes quiet control falls back to
+/// the next live slot. Ranking comes from config rather than the message
+/// `priority`. The slot follows the client-declared `source`, so this orders
+/// cooperating clients; it does not stop a client naming itself `foxglove`.
+pub struct Arbiter {
+    /// Sorted by descending priority.
+    slots: Vec<Slot>,
+    default_slot: usize,
+}
+
+impl Arbiter {
+    pub fn new(config: &ArbitrationConfig) -> Result<Self> {
+        let mut sources: Vec<&CommandSourceConfig> = config.sources.iter().collect();
+        sources.sort_by_key(|source| std::cmp::Reverse(source.priority));
+        for (i, source) in sources.iter().enumerate() {
+            ensure!(
+                !source.name.is_empty() && !source.name.contains(':'),
+                "arbitration source name '{}' must be non-empty without ':'",
+                source.name
+            );
+            ensure!(
+                sources[..i].iter().all(|other| other.name != source.name),
+                "arbitration source '{}' is listed twice",
+                source.name
+            );
+        }
+
+        let slots: Vec<Slot> = sources
+            .into_iter()
+            .map(|source| Slot {
+                name: source.name.clone(),
+                lease: Duration::from_millis(source.lease_ms),
+                latest: None,
+            })
+            .collect();
+        let position = |name: &str| slots.iter().position(|slot| slot.name == name);
+        let Some(default_slot) = position(&config.default_source) else {
+            bail!(
+                "arbitration default_source '{}' is not a configured source",
+                config.default_source
+            );
+        };
+        ensure!(
+            position(SKILL_SOURCE).is_some(),
+            "arbitration sources must include '{SKILL_SOURCE}'"
+        );
+
+        Ok(Self {
+            slots,
+            default_slot,
+        })
+    }
+
+    /// Stores `cmd` in its source's slot and returns the slot name.
+    pub fn submit(&mut self, cmd: VelocityCommand) -> &str {
+        let index = self
+            .slots
+            .iter()
+            .position(|slot| source_matches(&slot.name, &cmd.source))
+            .unwrap_or(self.default_slot);
+        let slot = &mut self.slots[index];
+        slot.latest = Some((cmd, Instant::now()));
+        &slot.name
+    }
+
+    /// Drops whatever `name` last sent, ending its lease early.
+    pub fn release(&mut self, name: &str) {
+        if let Some(slot) = self.slots.iter_mut().find(|slot| slot.name == name) {
+            slot.latest = None;
+        }
+    }
+
+    /// The source in control and its latest command.
+    pub fn active(&self) -> Option<(&str, &VelocityCommand)> {
+        self.live().next()
+    }
+
+    /// Every source with a live lease, highest priority first.
+    pub fn live(&self) -> impl Iterator<Item = (&str, &VelocityCommand)> {
+        self.slots
+            .iter()
+            .filter_map(|slot| slot.live().map(|cmd| (slot.name.as_str(), cmd)))
+    }
+
+    /// The highest-priority live source other than `name`, if any.
+    pub fn live_besides(&self, name: &str) -> Option<&str> {
+        self.live()
+            .map(|(source, _)| source)
+            .find(|source| *source != name)
+    }
+
+    /// Whether `cmd`, just submitted to `slot`, takes over from a running
+    /// skill: it must command motion and lead every other live source. Zero
+    /// commands (idle teleop, the stop sent when a client leaves) never do.
+    pub fn takes_over_skill(&self, slot: &str, cmd: &VelocityCommand) -> bool {
+        slot != SKILL_SOURCE && !cmd.is_zero() && self.live_besides(SKILL_SOURCE) == Some(slot)
+    }
+}
+
+/// Whether `source` is `name` itself or `name:detail`.
+pub fn source_matches(name: &str, source: &str) -> bool {
+    source == name
+        || source
+            .strip_prefix(name)
+            .is_some_and(|rest| rest.starts_with(':'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(source: &str, vx_m_s: f32) -> VelocityCommand {
+        VelocityCommand {
+            vx_m_s,
+            ..VelocityCommand::zero(source, 0)
+        }
+    }
+
+    fn arbiter() -> Arbiter {
+        Arbiter::new(&ArbitrationConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn routes_by_source_name_and_prefix() {
+        let mut arbiter = arbiter();
+        assert_eq!(arbiter.submit(command("foxglove", 0.1)), "foxglove");
+        assert_eq!(arbiter.submit(command("script:square", 0.1)), "script");
+        assert_eq!(arbiter.submit(command("scripted", 0.1)), "script");
+        assert_eq!(arbiter.submit(command("unknown", 0.1)), "script");
+    }
+
+    #[test]
+    fn highest_live_source_drives() {
+        let mut arbiter = arbiter();
+        arbiter.submit(command("script", 0.1));
+        arbiter.submit(command("foxglove", 0.2));
+        let (name, cmd) = arbiter.active().unwrap();
+        assert_eq!((name, cmd.vx_m_s), ("foxglove", 0.2));
+        arbiter.release("foxglove");
+        assert_eq!(arbiter.active().unwrap().0, "script");
+    }
+
+    #[test]
+    fn motion_from_the_leading_source_takes_over_a_skill() {
+        let mut arbiter = arbiter();
+        arbiter.submit(command(SKILL_SOURCE, 0.3));
+        let cmd = command("script:test", 0.1);
+        let slot = arbiter.submit(cmd.clone()).to_string();
+        assert!(arbiter.takes_over_skill(&slot, &cmd));
+    }
+
+    #[test]
+    fn zero_commands_never_take_over_a_skill() {
+        let mut arbiter = arbiter();
+        arbiter.submit(command(SKILL_SOURCE, 0.3));
+        // Idle teleop heartbeat, and the stop sent when a client disconnects.
+        for cmd in [
+            command("foxglove", 0.0),
+            VelocityCommand::zero("foxglove", 1),
+        ] {
+            let slot = arbiter.submit(cmd.clone()).to_string();
+            assert!(!arbiter.takes_over_skill(&slot, &cmd));
+        }
+    }
+
+    #[test]
+    fn outranked_motion_does_not_take_over_a_skill() {
+        let mut arbiter = arbiter();
+        arbiter.submit(command(SKILL_SOURCE, 0.3));
+        arbiter.submit(command("foxglove", 0.0));
+        let cmd = command("script", 0.1);
+        let slot = arbiter.submit(cmd.clone()).to_string();
+        assert!(!arbiter.takes_over_skill(&slot, &cmd));
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::messages::CameraStats;
+
+/// Cameras send stats every second; a camera silent for longer has lost its
+/// capture thread.
+const STATS_TIMEOUT: Duration = Duration::from_secs(3);
+/// The field checklist tolerates at most 1–2 s without frames.
+const FRAME_GAP_WARN_S: f64 = 1.0;
+
+struct CameraReport {
+    stats: CameraStats,
+    received: Instant,
+    /// Counter increases since the previous report.
+    new_dropped: u64,
+    new_late: u64,
+}
+
+/// Turns `/sensors/camera/<name>/stats` into diagnostics warnings. Cameras
+/// never stop the base; they only show up as warnings.
+#[derive(Default)]
+pub struct CameraMonitor {
+    cameras: BTreeMap<String, CameraReport>,
+}
+
+impl CameraMonitor {
+    pub fn update(&mut self, stats: CameraStats) {
+        let (new_dropped, new_late) = match self.cameras.get(&stats.camera_name) {
+            Some(previous) => (
+                stats
+                    .dropped_frames
+                    .saturating_sub(previous.stats.dropped_frames),
+                stats.late_frames.saturating_sub(previous.stats.late_frames),
+            ),
+            None => (stats.dropped_frames, stats.late_frames),
+        };
+        self.cameras.insert(
+            stats.camera_name.clone(),
+            CameraReport {
+                stats,
+                received: Instant::now(),
+                new_dropped,
+                new_late,
+            },
+        );
+    }
+
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        for (name, report) in &self.cameras {
+            let stats = &report.stats;
+            if report.received.elapsed() > STATS_TIMEOUT {
+                warnings.push(format!(
+                    "camera_silent: {name} (no stats for {:.1} s)",
+                    report.received.elapsed().as_secs_f32()
+                ));
+            } else if !stats.connected {
+                let detail = stats.last_error.as_deref().unwrap_or("disconnected");
+                warnings.push(format!("camera_lost: {name} ({detail})"));
+            } else if let Some(age) = stats.last_frame_age_s.filter(|age| *age > FRAME_GAP_WARN_S) {
+                warnings.push(format!("camera_stalled: {name} (no frame for {age:.1} s)"));
+            } else if report.new_dropped > 0 || report.new_late > 0 {
+                warnings.push(format!(
+                    "camera_frames: {name} ({} dropped, {} late; {:.1}/{} fps)",
+                    report.new_dropped, report.new_late, stats.fps_measured, stats.fps_target
+                ));
+            }
+        }
+        warnings
+    }
+}
@@ -0,0 +1,297 @@
+use anyhow::{bail, ensure, Result};
+
+use super::arbitration::source_matches;
+use crate::messages::{EstopCommand, EstopHold, EstopReset, EstopState};
+use crate::utils::now_nanos;
+
+/// Prefix of the holds behavior_router takes itself when an input is lost.
+const WATCHDOG_SOURCE: &str = "watchdog";
+
+/// Latched e-stop with independent holds per source (Foxglove UI, hardware
+/// button, watchdog, ...). Any source can engage it; each source releases
+/// only its own hold, and motion stays blocked after the last release until
+/// an allowed source resets the latch while no source commands motion.
+pub struct EstopLatch {
+    holds: Vec<EstopHold>,
+    latched: bool,
+    reset_sources: Vec<String>,
+}
+
+impl EstopLatch {
+    pub fn new(reset_sources: Vec<String>) -> Self {
+        Self {
+            holds: Vec::new(),
+            latched: false,
+            reset_sources,
+        }
+    }
+
+    pub fn latched(&self) -> bool {
+        self.latched
+    }
+
+    pub fn engage(&mut self, cmd: &EstopCommand) -> EstopState {
+        match self.holds.iter_mut().find(|hold| hold.source == cmd.source) {
+            Some(hold) => hold.reason = cmd.reason.clone(),
+            None => self.holds.push(EstopHold {
+                source: cmd.source.clone(),
+                reason: cmd.reason.clone(),
+                since_ns: cmd.timestamp_ns,
+            }),
+        }
+        self.latched = true;
+
+        let event = format!("engaged by {}: {}", cmd.source, cmd.reason);
+        tracing::warn!("E-stop {event}");
+        self.state(event)
+    }
+
+    pub fn release(&mut self, cmd: &EstopCommand) -> EstopState {
+        let held = self.holds.len();
+        self.holds.retain(|hold| hold.source != cmd.source);
+        if self.holds.len() == held {
+            let event = format!(
+                "release by {} ignored: no hold from that source",
+                cmd.source
+            );
+            tracing::warn!("E-stop {event}");
+            return self.state(event);
+        }
+
+        let mut event = format!("released by {}: {}", cmd.source, cmd.reason);
+        if self.holds.is_empty() {
+            event.push_str("; latched until reset");
+        } else {
+            event.push_str(&format!("; still held by {}", self.holders()));
+        }
+        tracing::info!("E-stop {event}");
+        self.state(event)
+    }
+
+    /// `moving_source` names a live velocity source commanding non-zero
+    /// motion. Also returns whether the reset was accepted.
+    pub fn reset(&mut self, cmd: &EstopReset, moving_source: Option<&str>) -> (EstopState, bool) {
+        let checked = self.check_reset(cmd, moving_source);
+        let accepted = checked.is_ok();
+        let event = match checked {
+            Ok(()) => {
+                self.latched = false;
+                let mut event = format!("reset by {}", cmd.source);
+                if !cmd.reason.is_empty() {
+                    event.push_str(&format!(": {}", cmd.reason));
+                }
+                tracing::info!("E-stop {event}");
+                event
+            }
+            Err(err) => {
+                let event = format!("reset by {} rejected: {err}", cmd.source);
+                tracing::warn!("E-stop {event}");
+                event
+            }
+        };
+        (self.state(event), accepted)
+    }
+
+    /// Diagnostics warning while motion is blocked.
+    pub fn warning(&self) -> Option<String> {
+        if !self.latched {
+            None
+        } else if self.holds.is_empty() {
+            Some("estop_latched: awaiting reset".to_string())
+        } else {
+            Some(format!("estop_active: held by {}", self.holders()))
+        }
+    }
+
+    fn check_reset(&self, cmd: &EstopReset, moving_source: Option<&str>) -> Result<()> {
+        ensure!(
+            self.reset_sources
+                .iter()
+                .any(|name| source_matches(name, &cmd.source)),
+            "source '{}' is not allowed to reset",
+            cmd.source
+        );
+        ensure!(self.holds.is_empty(), "still held by {}", self.holders());
+        if let Some(source) = moving_source {
+            bail!("commanded velocity from '{source}' is not zero");
+        }
+        Ok(())
+    }
+
+    fn holders(&self) -> String {
+        self.holds
+            .iter()
+            .map(|hold| hold.source.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn state(&self, event: String) -> EstopState {
+        EstopState {
+            timestamp_ns: now_nanos(),
+            latched: self.latched,
+            holds: self.holds.clone(),
+            event,
+        }
+    }
+}
+
+/// Holds the e-stop while a monitored input is lost. It arms once the input
+/// has been up, so startup does not trip it, and releases its hold when the
+/// input returns; the latch then still needs a reset like any other stop.
+pub struct EstopWatchdog {
+    source: String,
+    armed: bool,
+    holding: bool,
+}
+
+impl EstopWatchdog {
+    pub fn new(input: &str) -> Self {
+        Self {
+            source: format!("{WATCHDOG_SOURCE}:{input}"),
+            armed: false,
+            holding: false,
+        }
+    }
+
+    /// `lost` says why the input is down, if it is. Returns the command to
+    /// send on /cmd/estop when the hold changes.
+    pub fn update(&mut self, lost: Option<String>) -> Option<EstopCommand> {
+        self.armed |= lost.is_none();
+        let hold = self.armed && lost.is_some();
+        if hold == self.holding {
+            return None;
+        }
+        self.holding = hold;
+        Some(EstopCommand {
+            timestamp_ns: now_nanos(),
+            enabled: hold,
+            reason: lost.unwrap_or_else(|| "recovered".to_string()),
+            source: self.source.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn latch() -> EstopLatch {
+        EstopLatch::new(vec!["foxglove".to_string()])
+    }
+
+    fn command(source: &str, enabled: bool) -> EstopCommand {
+        EstopCommand {
+            timestamp_ns: 0,
+            enabled,
+            reason: "test".to_string(),
+            source: source.to_string(),
+        }
+    }
+
+    fn reset(source: &str) -> EstopReset {
+        EstopReset {
+            timestamp_ns: 0,
+            source: source.to_string(),
+            reason: String::new(),
+        }
+    }
+
+    #[test]
+    fn each_source_holds_and_releases_its_own() {
+        let mut latch = latch();
+        latch.engage(&command("foxglove:1", true));
+        let state = latch.engage(&command("button", true));
+        assert_eq!(state.holds.len(), 2);
+
+        let state = latch.release(&command("foxglove:2", false));
+        assert!(state.event.contains("ignored"));
+        assert_eq!(state.holds.len(), 2);
+
+        let state = latch.release(&command("foxglove:1", false));
+        assert!(state.event.ends_with("still held by button"));
+        let state = latch.release(&command("button", false));
+        assert!(state.holds.is_empty());
+        assert!(
+            state.latched,
+            "releasing the last hold must not re-enable motion"
+        );
+        assert_eq!(latch.warning().unwrap(), "estop_latched: awaiting reset");
+    }
+
+    #[test]
+    fn engaging_again_keeps_one_hold_per_source() {
+        let mut latch = latch();
+        latch.engage(&command("button", true));
+        let state = latch.engage(&command("button", true));
+        assert_eq!(state.holds.len(), 1);
+    }
+
+    #[test]
+    fn reset_only_from_an_allowed_source() {
+        let mut latch = latch();
+        latch.engage(&command("button", true));
+        latch.release(&command("button", false));
+
+        for source in ["button", "foxglovex", "watchdog:motor_bus"] {
+            let (state, accepted) = latch.reset(&reset(source), None);
+            assert!(!accepted, "{source} reset");
+            assert!(state.latched);
+        }
+        let (state, accepted) = latch.reset(&reset("foxglove:3"), None);
+        assert!(accepted);
+        assert!(!state.latched);
+        assert!(latch.warning().is_none());
+    }
+
+    #[test]
+    fn reset_refused_while_held() {
+        let mut latch = latch();
+        latch.engage(&command("foxglove:1", true));
+        let (state, accepted) = latch.reset(&reset("foxglove:1"), None);
+        assert!(!accepted);
+        assert!(state.event.contains("still held by foxglove:1"));
+        assert!(latch.latched());
+    }
+
+    #[test]
+    fn reset_refused_while_a_source_commands_motion() {
+        let mut latch = latch();
+        latch.engage(&command("foxglove:1", true));
+        latch.release(&command("foxglove:1", false));
+        let (state, accepted) = latch.reset(&reset("foxglove:1"), Some("script"));
+        assert!(!accepted);
+        assert!(state.event.contains("'script' is not zero"));
+        let (_, accepted) = latch.reset(&reset("foxglove:1"), None);
+        assert!(accepted);
+    }
+
+    #[test]
+    fn watchdog_arms_only_after_the_input_was_up() {
+        let mut watchdog = EstopWatchdog::new("motor_bus");
+        assert!(watchdog.update(Some("waiting".to_string())).is_none());
+        assert!(watchdog.update(None).is_none());
+
+        let engage = watchdog.update(Some("disconnected".to_string())).unwrap();
+        assert!(engage.enabled);
+        assert_eq!(engage.source, "watchdog:motor_bus");
+        assert_eq!(engage.reason, "disconnected");
+        assert!(watchdog.update(Some("still down".to_string())).is_none());
+
+        let release = watchdog.update(None).unwrap();
+        assert!(!release.enabled);
+        assert!(watchdog.update(None).is_none());
+    }
+
+    #[test]
+    fn watchdog_recovery_still_needs_a_reset() {
+        let mut latch = latch();
+        let mut watchdog = EstopWatchdog::new("motor_bus");
+        watchdog.update(None);
+        latch.engage(&watchdog.update(Some("lost".to_string())).unwrap());
+        let state = latch.release(&watchdog.update(None).unwrap());
+        assert!(state.latched);
+        let (_, accepted) = latch.reset(&reset("foxglove:1"), None);
+        assert!(accepted);
+    }
+}
@@ -0,0 +1,553 @@
+use anyhow::{ensure, Result};
+use foxglove::schemas::{line_primitive, Color, LinePrimitive, Point3, SceneEntity, SceneUpdate};
+
+use crate::config::{GeofenceConfig, LimitsConfig};
+use crate::messages::{Odometry, VelocityCommand};
+use crate::utils::{foxglove_timestamp, now_nanos};
+
+const CIRCLE_SEGMENTS: usize = 72;
+const LINE_THICKNESS_M: f64 = 0.02;
+/// Positions this close to the boundary still count as inside, and edges
+/// this close to their line count as crossed.
+const TOLERANCE_M: f64 = 1e-9;
+/// Rounds of per-constraint projection before falling back to scaling.
+const PROJECTION_PASSES: usize = 4;
+const INSIDE_COLOR: Color = Color {
+    r: 0.2,
+    g: 0.8,
+    b: 0.3,
+    a: 1.0,
+};
+const OUTSIDE_COLOR: Color = Color {
+    r: 0.9,
+    g: 0.2,
+    b: 0.2,
+    a: 1.0,
+};
+
+/// Keeps the base inside a circle or polygon in the odom frame. The part of
+/// the commanded velocity heading toward each piece of boundary within
+/// stopping distance (every nearby edge and corner of a polygon) is capped
+/// at the speed from which the base can still brake to a stop there, so it
+/// slows on approach, cannot go further once outside, and can always drive
+/// back in. Rotation is never limited.
+///
+/// The stopping distance from speed v is v * t + v^2 / (2 * a), where a is
+/// the limiter's braking cap (never scaled by the speed mode) and t covers
+/// everything before full deceleration: the control tick, the odometry age
+/// and, with a jerk limit, the a / jerk ramp.
+pub struct Geofence {
+    config: GeofenceConfig,
+    decel_m_s2: f64,
+    /// Reaction time before full deceleration, excluding odometry age.
+    reaction_s: f64,
+    outside: bool,
+}
+
+impl Geofence {
+    pub fn new(config: GeofenceConfig, limits: &LimitsConfig, tick_s: f64) -> Result<Self> {
+        match &config {
+            GeofenceConfig::Circle { center_m, radius_m } => {
+                ensure!(
+                    center_m.iter().all(|value| value.is_finite()),
+                    "geofence center must be finite"
+                );
+                ensure!(
+                    radius_m.is_finite() && *radius_m > 0.0,
+                    "geofence radius_m must be positive"
+                );
+            }
+            GeofenceConfig::Polygon { vertices_m } => {
+                ensure!(
+                    vertices_m.len() >= 3,
+                    "geofence polygon needs at least 3 vertices"
+                );
+                ensure!(
+                    vertices_m.iter().flatten().all(|value| value.is_finite()),
+                    "geofence vertices must be finite"
+                );
+                ensure!(
+                    signed_area(vertices_m).abs() > 1e-6,
+                    "geofence polygon has no area"
+                );
+            }
+        }
+        ensure!(
+            limits.max_accel_m_s2 > 0.0,
+            "geofence needs limits.max_accel_m_s2 > 0"
+        );
+        let decel_m_s2 = limits.max_accel_m_s2 as f64;
+        let ramp_s = limits
+            .max_jerk_m_s3
+            .filter(|jerk| *jerk > 0.0)
+            .map_or(0.0, |jerk| decel_m_s2 / jerk as f64);
+        Ok(Self {
+            config,
+            decel_m_s2,
+            reaction_s: tick_s + ramp_s,
+            outside: false,
+        })
+    }
+
+    /// Limits `cmd` for the current pose and returns a diagnostics warning
+    /// while the fence is violated. Without odometry translation is blocked.
+    pub fn limit(&mut self, cmd: &mut VelocityCommand, odom: Option<&Odometry>) -> Option<String> {
+        let Some(odom) = odom else {
+            cmd.vx_m_s = 0.0;
+            cmd.vy_m_s = 0.0;
+            return Some("geofence: odometry stale, translation blocked".to_string());
+        };
+
+        let position = [odom.x_m, odom.y_m];
+        let (distance, normal) = self.boundary(position);
+        let outside = distance > TOLERANCE_M;
+        if outside != self.outside {
+            if outside {
+                tracing::warn!("Left geofence ({distance:.2} m outside); blocking outward motion");
+            } else {
+                tracing::info!("Back inside geofence");
+            }
+            self.outside = outside;
+        }
+
+        let (sin_t, cos_t) = odom.theta_rad.sin_cos();
+        let (vx, vy) = (cmd.vx_m_s as f64, cmd.vy_m_s as f64);
+        let mut world = [vx * cos_t - vy * sin_t, vx * sin_t + vy * cos_t];
+        let odom_age_s = now_nanos().saturating_sub(odom.timestamp_ns) as f64 * 1e-9;
+        let reaction_s = self.reaction_s + odom_age_s;
+        let speed = world[0].hypot(world[1]);
+        let horizon = speed * reaction_s + speed * speed / (2.0 * self.decel_m_s2);
+        let mut caps: Vec<([f64; 2], f64)> = self
+            .approaches(position, horizon.max(distance), outside)
+            .into_iter()
+            .map(|(direction, range)| (direction, self.stopping_speed(range, reaction_s)))
+            .collect();
+        if outside {
+            caps.push((normal, 0.0));
+        }
+        if cap_velocity(&mut world, &caps) {
+            cmd.vx_m_s = (world[0] * cos_t + world[1] * sin_t) as f32;
+            cmd.vy_m_s = (-world[0] * sin_t + world[1] * cos_t) as f32;
+        }
+
+        outside.then(|| format!("geofence_violation: {distance:.2} m outside"))
+    }
+
+    /// Largest v with v * reaction_s + v^2 / (2 * a) <= distance.
+    fn stopping_speed(&self, distance: f64, reaction_s: f64) -> f64 {
+        let a = self.decel_m_s2;
+        let lead = a * reaction_s;
+        (lead * lead + 2.0 * a * distance).sqrt() - lead
+    }
+
+    /// The fence outline, red while the base is outside.
+    pub fn scene(&self, frame_id: &str, timestamp_ns: u64) -> SceneUpdate {
+        let points = match &self.config {
+            GeofenceConfig::Circle { center_m, radius_m } => (0..CIRCLE_SEGMENTS)
+                .map(|i| {
+                    let angle = std::f64::consts::TAU * i as f64 / CIRCLE_SEGMENTS as f64;
+                    point([
+                        center_m[0] + radius_m * angle.cos(),
+                        center_m[1] + radius_m * angle.sin(),
+                    ])
+                })
+                .collect(),
+            GeofenceConfig::Polygon { vertices_m } => {
+                vertices_m.iter().copied().map(point).collect()
+            }
+        };
+        let color = if self.outside {
+            OUTSIDE_COLOR
+        } else {
+            INSIDE_COLOR
+        };
+
+        SceneUpdate {
+            deletions: Vec::new(),
+            entities: vec![SceneEntity {
+                timestamp: Some(foxglove_timestamp(timestamp_ns)),
+                frame_id: frame_id.to_string(),
+                id: "geofence".to_string(),
+                lines: vec![LinePrimitive {
+                    r#type: line_primitive::Type::LineLoop as i32,
+                    thickness: LINE_THICKNESS_M,
+                    points,
+                    color: Some(color),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        }
+    }
+
+    /// Signed distance to the boundary (positive outside) and the outward
+    /// unit normal at the nearest boundary point.
+    fn boundary(&self, p: [f64; 2]) -> (f64, [f64; 2]) {
+        match &self.config {
+            GeofenceConfig::Circle { center_m, radius_m } => {
+                let offset = [p[0] - center_m[0], p[1] - center_m[1]];
+                let range = offset[0].hypot(offset[1]);
+                let normal = if range > 1e-9 {
+                    [offset[0] / range, offset[1] / range]
+                } else {
+                    [1.0, 0.0]
+                };
+                (range - radius_m, normal)
+            }
+            GeofenceConfig::Polygon { vertices_m } => polygon_boundary(vertices_m, p),
+        }
+    }
+
+    /// Unit directions from `p` toward every part of the boundary within
+    /// `reach`, with their distances. Each polygon edge counts once, through
+    /// its nearest point, which is a corner when `p` lies beyond the end of
+    /// the edge. Outside the fence an edge whose line `p` has crossed
+    /// counts along its outward normal at zero distance instead, so sliding
+    /// along it cannot carry the base out past a corner.
+    fn approaches(&self, p: [f64; 2], reach: f64, outside: bool) -> Vec<([f64; 2], f64)> {
+        match &self.config {
+            GeofenceConfig::Circle { center_m, radius_m } => {
+                let offset = [p[0] - center_m[0], p[1] - center_m[1]];
+                let range = offset[0].hypot(offset[1]);
+                let direction = if range > 1e-9 {
+                    [offset[0] / range, offset[1] / range]
+                } else {
+                    [1.0, 0.0]
+                };
+                let distance = radius_m - range;
+                if !outside && distance <= reach {
+                    vec![(direction, distance.max(0.0))]
+                } else {
+                    Vec::new()
+                }
+            }
+            GeofenceConfig::Polygon { vertices_m } => {
+                let orientation = signed_area(vertices_m).signum();
+                let mut approaches = Vec::new();
+                for (i, a) in vertices_m.iter().enumerate() {
+                    let b = vertices_m[(i + 1) % vertices_m.len()];
+                    let Some((closest, edge_normal)) = nearest_on_edge(*a, b, p, orientation)
+                    else {
+                        continue;
+                    };
+                    let distance = (closest[0] - p[0]).hypot(closest[1] - p[1]);
+                    if distance > reach {
+                        continue;
+                    }
+                    let beyond = (p[0] - a[0]) * edge_normal[0] + (p[1] - a[1]) * edge_normal[1];
+                    if outside && beyond > -TOLERANCE_M {
+                        approaches.push((edge_normal, 0.0));
+                    } else if distance > 1e-9 {
+                        let direction = [
+                            (closest[0] - p[0]) / distance,
+                            (closest[1] - p[1]) / distance,
+                        ];
+                        approaches.push((direction, distance));
+                    } else {
+                        approaches.push((edge_normal, 0.0));
+                    }
+                }
+                approaches
+            }
+        }
+    }
+}
+
+/// Caps the component of `velocity` along each unit direction at its
+/// allowed speed and returns whether anything changed. Projections can
+/// push each other back over the cap at acute corners, so whatever they
+/// leave is scaled down, which keeps the direction and always fits because
+/// every cap is at least zero.
+fn cap_velocity(velocity: &mut [f64; 2], caps: &[([f64; 2], f64)]) -> bool {
+    let over = |velocity: &[f64; 2], (direction, allowed): &([f64; 2], f64)| {
+        velocity[0] * direction[0] + velocity[1] * direction[1] - allowed
+    };
+    let mut changed = false;
+    for _ in 0..PROJECTION_PASSES {
+        let mut projected = false;
+        for cap in caps {
+            let excess = over(velocity, cap);
+            if excess > 1e-9 {
+                velocity[0] -= excess * cap.0[0];
+                velocity[1] -= excess * cap.0[1];
+                projected = true;
+            }
+        }
+        if !projected {
+            return changed;
+        }
+        changed = true;
+    }
+    let scale = caps
+        .iter()
+        .filter(|cap| over(velocity, cap) > 1e-9)
+        .map(|cap| cap.1 / (cap.1 + over(velocity, cap)))
+        .fold(1.0, f64::min);
+    velocity[0] *= scale;
+    velocity[1] *= scale;
+    changed
+}
+
+/// Nearest point to `p` on the edge from `a` to `b` and the edge's outward
+/// normal, or `None` for a zero-length edge.
+fn nearest_on_edge(
+    a: [f64; 2],
+    b: [f64; 2],
+    p: [f64; 2],
+    orientation: f64,
+) -> Option<([f64; 2], [f64; 2])> {
+    let edge = [b[0] - a[0], b[1] - a[1]];
+    let length_sq = edge[0] * edge[0] + edge[1] * edge[1];
+    if length_sq <= 0.0 {
+        return None;
+    }
+    let t = (((p[0] - a[0]) * edge[0] + (p[1] - a[1]) * edge[1]) / length_sq).clamp(0.0, 1.0);
+    let length = length_sq.sqrt();
+    // Right-hand normal points out of a counter-clockwise polygon.
+    Some((
+        [a[0] + t * edge[0], a[1] + t * edge[1]],
+        [
+            orientation * edge[1] / length,
+            -orientation * edge[0] / length,
+        ],
+    ))
+}
+
+fn polygon_boundary(vertices: &[[f64; 2]], p: [f64; 2]) -> (f64, [f64; 2]) {
+    let orientation = signed_area(vertices).signum();
+    let mut nearest = (f64::INFINITY, p, [1.0, 0.0]);
+    for (i, a) in vertices.iter().enumerate() {
+        let b = vertices[(i + 1) % vertices.len()];
+        let Some((closest, edge_normal)) = nearest_on_edge(*a, b, p, orientation) else {
+            continue;
+        };
+        let distance = (p[0] - closest[0]).hypot(p[1] - closest[1]);
+        if distance < nearest.0 {
+            nearest = (distance, closest, edge_normal);
+        }
+    }
+
+    let (distance, closest, edge_normal) = nearest;
+    let inside = contains(vertices, p);
+    let normal = if distance > 1e-9 {
+        let away = [
+            (p[0] - closest[0]) / distance,
+            (p[1] - closest[1]) / distance,
+        ];
+        if inside {
+            [-away[0], -away[1]]
+        } else {
+            away
+        }
+    } else {
+        edge_normal
+    };
+    (if inside { -distance } else { distance }, normal)
+}
+
+/// Even-odd ray casting.
+fn contains(vertices: &[[f64; 2]], p: [f64; 2]) -> bool {
+    let mut inside = false;
+    for (i, a) in vertices.iter().enumerate() {
+        let b = vertices[(i + 1) % vertices.len()];
+        if (a[1] > p[1]) != (b[1] > p[1]) {
+            let x = a[0] + (p[1] - a[1]) / (b[1] - a[1]) * (b[0] - a[0]);
+            if p[0] < x {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+fn signed_area(vertices: &[[f64; 2]]) -> f64 {
+    let twice: f64 = vertices
+        .iter()
+        .enumerate()
+        .map(|(i, a)| {
+            let b = vertices[(i + 1) % vertices.len()];
+            a[0] * b[1] - b[0] * a[1]
+        })
+        .sum();
+    twice / 2.0
+}
+
+fn point([x, y]: [f64; 2]) -> Point3 {
+    Point3 { x, y, z: 0.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK_S: f64 = 0.02;
+    /// Commands are f32, so a base sliding along an edge may round a hair out.
+    const SLACK_M: f64 = 1e-6;
+
+    fn limits() -> LimitsConfig {
+        LimitsConfig {
+            max_vx_m_s: 1.0,
+            max_vy_m_s: 1.0,
+            max_omega_rad_s: 2.0,
+            max_speed_m_s: None,
+            max_accel_m_s2: 1.0,
+            max_alpha_rad_s2: 4.0,
+            max_jerk_m_s3: None,
+            max_jerk_rad_s3: None,
+        }
+    }
+
+    fn circle() -> Geofence {
+        let config = GeofenceConfig::Circle {
+            center_m: [0.0, 0.0],
+            radius_m: 1.0,
+        };
+        Geofence::new(config, &limits(), TICK_S).unwrap()
+    }
+
+    fn square() -> Geofence {
+        let config = GeofenceConfig::Polygon {
+            vertices_m: vec![[-1.0, -1.0], [1.0, -1.0], [1.0, 1.0], [-1.0, 1.0]],
+        };
+        Geofence::new(config, &limits(), TICK_S).unwrap()
+    }
+
+    fn odom(x_m: f64, y_m: f64, theta_rad: f64) -> Odometry {
+        Odometry {
+            timestamp_ns: now_nanos(),
+            x_m,
+            y_m,
+            theta_rad,
+            vx_m_s: 0.0,
+            vy_m_s: 0.0,
+            omega_rad_s: 0.0,
+            frame_id: "odom".to_string(),
+            pose_covariance: None,
+        }
+    }
+
+    fn command(vx_m_s: f32, vy_m_s: f32) -> VelocityCommand {
+        VelocityCommand {
+            vx_m_s,
+            vy_m_s,
+            omega_rad_s: 0.5,
+            ..VelocityCommand::zero("test", 0)
+        }
+    }
+
+    /// Drives `cmd` (body frame) from `start` for `ticks` and returns the
+    /// largest signed boundary distance seen.
+    fn drive(
+        fence: &mut Geofence,
+        start: [f64; 2],
+        theta: f64,
+        cmd: (f32, f32),
+        ticks: usize,
+    ) -> f64 {
+        let mut position = start;
+        let mut worst = f64::NEG_INFINITY;
+        for _ in 0..ticks {
+            let mut limited = command(cmd.0, cmd.1);
+            fence.limit(&mut limited, Some(&odom(position[0], position[1], theta)));
+            let (sin_t, cos_t) = theta.sin_cos();
+            let (vx, vy) = (limited.vx_m_s as f64, limited.vy_m_s as f64);
+            position[0] += (vx * cos_t - vy * sin_t) * TICK_S;
+            position[1] += (vx * sin_t + vy * cos_t) * TICK_S;
+            worst = worst.max(fence.boundary(position).0);
+        }
+        worst
+    }
+
+    #[test]
+    fn leaves_commands_far_from_the_boundary_alone() {
+        let mut fence = circle();
+        let mut cmd = command(0.5, -0.3);
+        assert!(fence.limit(&mut cmd, Some(&odom(0.0, 0.0, 0.0))).is_none());
+        assert_eq!((cmd.vx_m_s, cmd.vy_m_s, cmd.omega_rad_s), (0.5, -0.3, 0.5));
+    }
+
+    #[test]
+    fn circle_slows_outward_motion_but_not_tangential() {
+        let mut fence = circle();
+        let mut cmd = command(0.5, 0.5);
+        fence.limit(&mut cmd, Some(&odom(0.95, 0.0, 0.0)));
+        assert!(cmd.vx_m_s > 0.0 && cmd.vx_m_s < 0.3, "vx {}", cmd.vx_m_s);
+        assert!((cmd.vy_m_s - 0.5).abs() < 1e-6);
+        assert_eq!(cmd.omega_rad_s, 0.5);
+    }
+
+    #[test]
+    fn circle_is_never_left() {
+        let mut fence = circle();
+        let worst = drive(&mut fence, [0.0, 0.0], 0.7, (1.0, 0.4), 300);
+        assert!(worst <= SLACK_M, "left the circle by {worst}");
+    }
+
+    #[test]
+    fn polygon_limits_the_edge_ahead_not_only_the_nearest() {
+        // The top edge is nearest, but the right edge is the one being approached.
+        let mut fence = square();
+        let mut cmd = command(1.0, 0.0);
+        fence.limit(&mut cmd, Some(&odom(0.9, 0.95, 0.0)));
+        assert!(cmd.vx_m_s > 0.0 && cmd.vx_m_s < 0.5, "vx {}", cmd.vx_m_s);
+        assert!(cmd.vy_m_s.abs() < 1e-6);
+    }
+
+    #[test]
+    fn polygon_corner_approach_stays_inside() {
+        let mut fence = square();
+        let worst = drive(&mut fence, [0.0, 0.0], 0.3, (1.0, 0.5), 300);
+        assert!(worst <= SLACK_M, "left the square by {worst}");
+        let worst = drive(&mut fence, [0.9, 0.0], 0.0, (0.2, 1.0), 300);
+        assert!(worst <= SLACK_M, "slid out past the corner by {worst}");
+    }
+
+    #[test]
+    fn acute_corner_is_never_left() {
+        let config = GeofenceConfig::Polygon {
+            vertices_m: vec![[0.0, 0.0], [2.0, 0.0], [0.0, 0.4]],
+        };
+        let mut fence = Geofence::new(config, &limits(), TICK_S).unwrap();
+        let worst = drive(&mut fence, [0.3, 0.1], 0.0, (1.0, 0.0), 300);
+        assert!(worst <= SLACK_M, "left the wedge by {worst}");
+    }
+
+    #[test]
+    fn outside_blocks_outward_motion_and_allows_recovery() {
+        for mut fence in [circle(), square()] {
+            let outside = odom(1.2, 0.0, 0.0);
+            let mut outward = command(0.5, 0.2);
+            assert!(fence.limit(&mut outward, Some(&outside)).is_some());
+            assert!(outward.vx_m_s.abs() < 1e-6);
+            assert!((outward.vy_m_s - 0.2).abs() < 1e-6);
+
+            let mut inward = command(-0.5, 0.0);
+            fence.limit(&mut inward, Some(&outside));
+            assert_eq!(inward.vx_m_s, -0.5);
+
+            drive(&mut fence, [1.2, 0.0], 0.0, (-0.5, 0.0), 50);
+            assert!(!fence.outside, "still outside after driving back in");
+        }
+    }
+
+    #[test]
+    fn stale_odometry_blocks_translation() {
+        let mut fence = square();
+        let mut cmd = command(0.5, 0.5);
+        assert!(fence.limit(&mut cmd, None).is_some());
+        assert_eq!((cmd.vx_m_s, cmd.vy_m_s, cmd.omega_rad_s), (0.0, 0.0, 0.5));
+    }
+
+    #[test]
+    fn rejects_degenerate_fences() {
+        let flat = GeofenceConfig::Polygon {
+            vertices_m: vec![[0.0, 0.0], [1.0, 0.0], [2.0, 0.0]],
+        };
+        assert!(Geofence::new(flat, &limits(), TICK_S).is_err());
+        let empty = GeofenceConfig::Circle {
+            center_m: [0.0, 0.0],
+            radius_m: 0.0,
+        };
+        assert!(Geofence::new(empty, &limits(), TICK_S).is_err());
+    }
+}
@@ -0,0 +1,322 @@
+use crate::config::LimitsConfig;
+use crate::messages::VelocityCommand;
+
+/// Shapes commands before they reach kinematics: speed caps on the way in,
+/// then acceleration (and optionally jerk) limits against the previous
+/// output. The planar part is limited as a vector, so diagonal motion keeps
+/// its direction and never exceeds the speed or acceleration caps. Braking
+/// always uses the configured caps; the speed mode only softens speeding up.
+pub struct MotionLimiter {
+    /// Limits from robot.yaml; `limits` is these scaled by the speed mode.
+    base: LimitsConfig,
+    limits: LimitsConfig,
+    last: VelocityCommand,
+    /// Planar and angular acceleration of the last step, for the jerk limit.
+    accel: [f32; 2],
+    alpha: [f32; 1],
+}
+
+impl MotionLimiter {
+    pub fn new(limits: LimitsConfig, initial: VelocityCommand) -> Self {
+        Self {
+            base: limits.clone(),
+            limits,
+            last: initial,
+            accel: [0.0; 2],
+            alpha: [0.0; 1],
+        }
+    }
+
+    /// Rescales the configured limits: `speed` for the velocity caps and
+    /// `accel` for the acceleration and jerk caps while speeding up.
+    pub fn set_scale(&mut self, speed: f32, accel: f32) {
+        let base = &self.base;
+        self.limits = LimitsConfig {
+            max_vx_m_s: base.max_vx_m_s * speed,
+            max_vy_m_s: base.max_vy_m_s * speed,
+            max_omega_rad_s: base.max_omega_rad_s * speed,
+            max_speed_m_s: Some(
+                base.max_speed_m_s
+                    .unwrap_or(base.max_vx_m_s.max(base.max_vy_m_s))
+                    * speed,
+            ),
+            max_accel_m_s2: base.max_accel_m_s2 * accel,
+            max_alpha_rad_s2: base.max_alpha_rad_s2 * accel,
+            max_jerk_m_s3: base.max_jerk_m_s3.map(|jerk| jerk * accel),
+            max_jerk_rad_s3: base.max_jerk_rad_s3.map(|jerk| jerk * accel),
+        };
+    }
+
+    /// Scales the planar velocity uniformly to fit `max_vx_m_s`,
+    /// `max_vy_m_s` and the speed norm, and clamps omega.
+    pub fn clamp(&self, cmd: &VelocityCommand, timestamp_ns: u64) -> VelocityCommand {
+        let limits = &self.limits;
+        let max_speed = limits
+            .max_speed_m_s
+            .unwrap_or(limits.max_vx_m_s.max(limits.max_vy_m_s));
+        let speed = cmd.vx_m_s.hypot(cmd.vy_m_s);
+        let scale = [
+            (cmd.vx_m_s.abs(), limits.max_vx_m_s),
+            (cmd.vy_m_s.abs(), limits.max_vy_m_s),
+            (speed, max_speed),
+        ]
+        .into_iter()
+        .filter(|(value, max)| value > max)
+        .map(|(value, max)| max / value)
+        .fold(1.0f32, f32::min);
+
+        let mut clamped = cmd.clone();
+        clamped.timestamp_ns = timestamp_ns;
+        clamped.vx_m_s = cmd.vx_m_s * scale;
+        clamped.vy_m_s = cmd.vy_m_s * scale;
+        clamped.omega_rad_s = cmd
+            .omega_rad_s
+            .clamp(-limits.max_omega_rad_s, limits.max_omega_rad_s);
+        clamped.source = "behavior_router".to_string();
+        clamped
+    }
+
+    /// Moves the output toward `target`. With `smooth` false the jerk limit
+    /// is skipped, so forced stops brake at full deceleration. Steps that
+    /// slow the base use the unscaled caps whatever the speed mode.
+    pub fn step(&mut self, target: &VelocityCommand, dt: f32, smooth: bool) -> VelocityCommand {
+        let last = [self.last.vx_m_s, self.last.vy_m_s];
+        let planar = [target.vx_m_s, target.vy_m_s];
+        let limits = if !smooth || slowing(&last, &planar) {
+            &self.base
+        } else {
+            &self.limits
+        };
+        let [vx, vy] = limit_step(
+            last,
+            planar,
+            &mut self.accel,
+            limits.max_accel_m_s2,
+            limits.max_jerk_m_s3.filter(|_| smooth),
+            dt,
+        );
+        let last = [self.last.omega_rad_s];
+        let angular = [target.omega_rad_s];
+        let limits = if !smooth || slowing(&last, &angular) {
+            &self.base
+        } else {
+            &self.limits
+        };
+        let [omega] = limit_step(
+            last,
+            angular,
+            &mut self.alpha,
+            limits.max_alpha_rad_s2,
+            limits.max_jerk_rad_s3.filter(|_| smooth),
+            dt,
+        );
+
+        let mut output = target.clone();
+        output.vx_m_s = vx;
+        output.vy_m_s = vy;
+        output.omega_rad_s = omega;
+        self.last = output.clone();
+        output
+    }
+
+    /// Records an output produced outside the limiter, e.g. the torque-off
+    /// zero, so the next step ramps from it.
+    pub fn hold(&mut self, output: &VelocityCommand) {
+        self.last = output.clone();
+        self.accel = [0.0; 2];
+        self.alpha = [0.0; 1];
+    }
+}
+
+/// One step of a vector rate limiter. Without a jerk limit the velocity
+/// moves straight toward the target at up to `max_accel`. With one, the
+/// acceleration itself ramps at `max_jerk` and is capped at
+/// sqrt(2 * jerk * remaining) so it can wind down before the target.
+fn limit_step<const N: usize>(
+    last: [f32; N],
+    target: [f32; N],
+    accel: &mut [f32; N],
+    max_accel: f32,
+    max_jerk: Option<f32>,
+    dt: f32,
+) -> [f32; N] {
+    let delta: [f32; N] = std::array::from_fn(|i| target[i] - last[i]);
+    let remaining = norm(&delta);
+
+    let Some(max_jerk) = max_jerk else {
+        let step = clamp_norm(delta, max_accel * dt);
+        *accel = step.map(|value| value / dt);
+        return std::array::from_fn(|i| last[i] + step[i]);
+    };
+
+    let cap = max_accel.min((2.0 * max_jerk * remaining).sqrt());
+    let desired = clamp_norm(delta.map(|value| value / dt), cap);
+    let change: [f32; N] = std::array::from_fn(|i| desired[i] - accel[i]);
+    let change = clamp_norm(change, max_jerk * dt);
+    let next_accel: [f32; N] = std::array::from_fn(|i| accel[i] + change[i]);
+    let step = next_accel.map(|value| value * dt);
+    if norm(&step) >= remaining {
+        *accel = delta.map(|value| value / dt);
+        return target;
+    }
+    *accel = next_accel;
+    std::array::from_fn(|i| last[i] + step[i])
+}
+
+/// Whether moving from `last` to `target` takes speed off, including the
+/// part of a reversal down to zero.
+fn slowing<const N: usize>(last: &[f32; N], target: &[f32; N]) -> bool {
+    (0..N).map(|i| (target[i] - last[i]) * last[i]).sum::<f32>() < 0.0
+}
+
+fn norm<const N: usize>(v: &[f32; N]) -> f32 {
+    v.iter().map(|value| value * value).sum::<f32>().sqrt()
+}
+
+fn clamp_norm<const N: usize>(v: [f32; N], max: f32) -> [f32; N] {
+    let length = norm(&v);
+    if length > max && length > 0.0 {
+        v.map(|value| value * max / length)
+    } else {
+        v
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.02;
+    const EPS: f32 = 1e-5;
+
+    fn config() -> LimitsConfig {
+        LimitsConfig {
+            max_vx_m_s: 0.5,
+            max_vy_m_s: 0.3,
+            max_omega_rad_s: 2.0,
+            max_speed_m_s: None,
+            max_accel_m_s2: 1.0,
+            max_alpha_rad_s2: 4.0,
+            max_jerk_m_s3: None,
+            max_jerk_rad_s3: None,
+        }
+    }
+
+    fn twist(vx_m_s: f32, vy_m_s: f32, omega_rad_s: f32) -> VelocityCommand {
+        VelocityCommand {
+            vx_m_s,
+            vy_m_s,
+            omega_rad_s,
+            ..VelocityCommand::zero("test", 1)
+        }
+    }
+
+    #[test]
+    fn clamp_scales_the_planar_vector() {
+        let limiter = MotionLimiter::new(config(), twist(0.0, 0.0, 0.0));
+
+        // The vy cap binds: both axes shrink by the same factor.
+        let clamped = limiter.clamp(&twist(0.4, 0.4, 0.0), 7);
+        assert!((clamped.vx_m_s - 0.3).abs() < EPS && (clamped.vy_m_s - 0.3).abs() < EPS);
+        assert_eq!(clamped.timestamp_ns, 7);
+
+        // Then the speed norm, which defaults to the larger axis cap.
+        let clamped = limiter.clamp(&twist(0.45, 0.3, 0.0), 7);
+        let speed = clamped.vx_m_s.hypot(clamped.vy_m_s);
+        assert!((speed - 0.5).abs() < EPS, "{speed}");
+        assert!((clamped.vx_m_s / clamped.vy_m_s - 1.5).abs() < EPS);
+
+        let clamped = limiter.clamp(&twist(0.1, -0.1, -5.0), 7);
+        assert_eq!((clamped.vx_m_s, clamped.vy_m_s), (0.1, -0.1));
+        assert_eq!(clamped.omega_rad_s, -2.0);
+    }
+
+    #[test]
+    fn clamp_follows_the_speed_scale() {
+        let mut limiter = MotionLimiter::new(config(), twist(0.0, 0.0, 0.0));
+        limiter.set_scale(0.5, 1.0);
+        let clamped = limiter.clamp(&twist(1.0, 0.0, 3.0), 0);
+        assert!((clamped.vx_m_s - 0.25).abs() < EPS);
+        assert!((clamped.omega_rad_s - 1.0).abs() < EPS);
+    }
+
+    #[test]
+    fn acceleration_is_limited_as_a_vector() {
+        let mut limiter = MotionLimiter::new(config(), twist(0.0, 0.0, 0.0));
+        let output = limiter.step(&twist(0.3, 0.3, 2.0), DT, true);
+        let step = output.vx_m_s.hypot(output.vy_m_s);
+        assert!((step - 1.0 * DT).abs() < EPS, "{step}");
+        assert!((output.vx_m_s - output.vy_m_s).abs() < EPS);
+        assert!((output.omega_rad_s - 4.0 * DT).abs() < EPS);
+
+        for _ in 0..100 {
+            limiter.step(&twist(0.3, 0.3, 2.0), DT, true);
+        }
+        let output = limiter.step(&twist(0.3, 0.3, 2.0), DT, true);
+        assert_eq!(
+            (output.vx_m_s, output.vy_m_s, output.omega_rad_s),
+            (0.3, 0.3, 2.0)
+        );
+    }
+
+    #[test]
+    fn jerk_ramps_the_acceleration_without_overshoot() {
+        let config = LimitsConfig {
+            max_jerk_m_s3: Some(5.0),
+            ..config()
+        };
+        let mut limiter = MotionLimiter::new(config, twist(0.0, 0.0, 0.0));
+        let (mut speed, mut accel) = (0.0f32, 0.0f32);
+        let mut steps = 0;
+        while speed < 0.4 {
+            steps += 1;
+            assert!(steps < 100, "stuck at {speed}");
+            let next = limiter.step(&twist(0.4, 0.0, 0.0), DT, true).vx_m_s;
+            assert!(next <= 0.4, "overshoot to {next}");
+            if next == 0.4 {
+                // The last step snaps onto the target.
+                break;
+            }
+            let next_accel = (next - speed) / DT;
+            assert!(next_accel <= 1.0 + 1e-3, "accel {next_accel} at {next}");
+            assert!(
+                (next_accel - accel).abs() <= 5.0 * DT + 1e-3,
+                "jerk at {next}"
+            );
+            (speed, accel) = (next, next_accel);
+        }
+        // Plain acceleration limiting would take 20 steps.
+        assert!(steps > 20, "{steps}");
+    }
+
+    #[test]
+    fn braking_uses_the_unscaled_caps() {
+        let mut limiter = MotionLimiter::new(config(), twist(0.4, 0.0, 2.0));
+        limiter.set_scale(0.5, 0.25);
+
+        let output = limiter.step(&twist(0.0, 0.0, 0.0), DT, true);
+        assert!((output.vx_m_s - (0.4 - 1.0 * DT)).abs() < EPS);
+        assert!((output.omega_rad_s - (2.0 - 4.0 * DT)).abs() < EPS);
+
+        // Speeding up again is held to the scaled acceleration.
+        limiter.hold(&twist(0.0, 0.0, 0.0));
+        let output = limiter.step(&twist(0.4, 0.0, 2.0), DT, true);
+        assert!((output.vx_m_s - 0.25 * DT).abs() < EPS);
+        assert!((output.omega_rad_s - 1.0 * DT).abs() < EPS);
+    }
+
+    #[test]
+    fn forced_stops_skip_the_jerk_limit() {
+        let config = LimitsConfig {
+            max_jerk_m_s3: Some(5.0),
+            ..config()
+        };
+        let mut limiter = MotionLimiter::new(config, twist(0.4, 0.0, 0.0));
+        let smooth = limiter.step(&twist(0.0, 0.0, 0.0), DT, true);
+        assert!(smooth.vx_m_s > 0.4 - 1.0 * DT + EPS);
+
+        limiter.hold(&twist(0.4, 0.0, 0.0));
+        let forced = limiter.step(&twist(0.0, 0.0, 0.0), DT, false);
+        assert!((forced.vx_m_s - (0.4 - 1.0 * DT)).abs() < EPS);
+    }
+}
@@ -0,0 +1,891 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::config::SkillsConfig;
+use crate::messages::{
+    Odometry, Pose2D, PoseReset, SkillCommand, SkillState, SkillStatus, VelocityCommand,
+};
+use crate::utils::{normalize_angle, now_nanos};
+
+/// Proportional gains for the closed-loop skills.
+const K_LINEAR: f64 = 1.5;
+const K_ANGULAR: f64 = 2.0;
+/// Floor on commanded speed so the base does not stall inside the servo
+/// deadband just short of the tolerance.
+const MIN_SPEED_M_S: f64 = 0.03;
+const MIN_OMEGA_RAD_S: f64 = 0.15;
+/// `stop` succeeds once odometry reports the base at rest.
+const STOPPED_SPEED_M_S: f64 = 0.02;
+const STOPPED_OMEGA_RAD_S: f64 = 0.05;
+/// Angles beyond two turns either way are a unit mistake, not a goal.
+const MAX_ABS_ANGLE_RAD: f64 = 4.0 * std::f64::consts::PI;
+
+/// What a skill wants this tick.
+pub enum SkillStep {
+    /// Body-frame velocity to command and progress towards the goal (0..1).
+    Active {
+        vx_m_s: f64,
+        vy_m_s: f64,
+        omega_rad_s: f64,
+        progress: f32,
+    },
+    Done,
+}
+
+/// A closed-loop behaviour driven by odometry. Skills are built from the
+/// request params and the pose at the time they start.
+pub trait Skill: Send {
+    fn step(&mut self, odom: &Odometry) -> SkillStep;
+
+    /// Moves any odom-frame goal along with a pose reset, so the skill still
+    /// ends where it would have on the floor.
+    fn rebase(&mut self, _shift: &FrameShift) {}
+}
+
+/// The rigid move a /cmd/set_pose applies to the odom frame: it maps poses
+/// from before the reset onto the same physical poses after it.
+pub struct FrameShift {
+    rotation_rad: f64,
+    from: [f64; 2],
+    to: [f64; 2],
+}
+
+impl FrameShift {
+    pub fn new(reset: &PoseReset) -> Self {
+        Self {
+            rotation_rad: reset.theta_rad - reset.previous_theta_rad,
+            from: [reset.previous_x_m, reset.previous_y_m],
+            to: [reset.x_m, reset.y_m],
+        }
+    }
+
+    pub fn point(&self, x_m: f64, y_m: f64) -> (f64, f64) {
+        let (sin_r, cos_r) = self.rotation_rad.sin_cos();
+        let (dx, dy) = (x_m - self.from[0], y_m - self.from[1]);
+        (
+            self.to[0] + cos_r * dx - sin_r * dy,
+            self.to[1] + sin_r * dx + cos_r * dy,
+        )
+    }
+
+    pub fn heading(&self, theta_rad: f64) -> f64 {
+        normalize_angle(theta_rad + self.rotation_rad)
+    }
+}
+
+type Constructor = fn(&Value, &Odometry) -> Result<Box<dyn Skill>>;
+
+const REGISTRY: &[(&str, Constructor)] = &[
+    ("rotate_to_heading", RotateToHeading::build),
+    ("drive_distance", GotoPose::build_drive),
+    ("strafe", GotoPose::build_strafe),
+    ("goto_pose", GotoPose::build_goto),
+    ("stop", Stop::build),
+];
+
+fn build_skill(name: &str, params: &Value, odom: &Odometry) -> Result<Box<dyn Skill>> {
+    let Some((_, build)) = REGISTRY.iter().find(|(registered, _)| *registered == name) else {
+        let known: Vec<&str> = REGISTRY.iter().map(|(registered, _)| *registered).collect();
+        bail!("unknown skill '{name}' (expected one of {known:?})");
+    };
+    build(params, odom).with_context(|| format!("invalid params for {name}"))
+}
+
+/// Running skills report progress at this rate between state changes.
+const STATUS_INTERVAL: Duration = Duration::from_millis(200);
+
+struct RunningSkill {
+    request: SkillCommand,
+    skill: Box<dyn Skill>,
+    deadline: Instant,
+    progress: f32,
+    last_status: Option<Instant>,
+}
+
+/// Runs at most one skill at a time. A request preempts the running skill
+/// if its priority is at least as high (the newer request wins ties) and is
+/// rejected otherwise; `stop` always wins. Every state change is queued as a
+/// `SkillStatus` for the router to publish.
+pub struct SkillExecutor {
+    max_timeout_s: f32,
+    active: Option<RunningSkill>,
+    statuses: Vec<SkillStatus>,
+}
+
+impl SkillExecutor {
+    pub fn new(config: &SkillsConfig) -> Self {
+        Self {
+            max_timeout_s: config.max_timeout_s,
+            active: None,
+            statuses: Vec::new(),
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.is_some()
+    }
+
+    /// Statuses produced since the last call, oldest first.
+    pub fn take_statuses(&mut self) -> Vec<SkillStatus> {
+        std::mem::take(&mut self.statuses)
+    }
+
+    pub fn submit(&mut self, request: SkillCommand, odom: Option<&Odometry>) {
+        match self.start(&request, odom) {
+            Ok((skill, deadline)) => {
+                let reason = format!(
+                    "preempted by request {} at priority {}",
+                    request.request_id, request.priority
+                );
+                self.finish(SkillState::Preempted, Some(reason), odom);
+                tracing::info!(
+                    "Skill '{}' ({}) started",
+                    request.skill_name,
+                    request.request_id
+                );
+                let running = RunningSkill {
+                    deadline,
+                    request,
+                    skill,
+                    progress: 0.0,
+                    last_status: None,
+                };
+                self.statuses
+                    .push(status(&running, SkillState::Accepted, None, None));
+                self.active = Some(running);
+            }
+            Err(err) => self.reject(request, format!("{err:#}")),
+        }
+    }
+
+    pub fn reject(&mut self, request: SkillCommand, reason: String) {
+        tracing::warn!("Rejected skill {}: {reason}", request.request_id);
+        self.statuses.push(SkillStatus {
+            timestamp_ns: now_nanos(),
+            request_id: request.request_id,
+            skill_name: request.skill_name,
+            state: SkillState::Rejected,
+            progress: 0.0,
+            error: Some(reason),
+            final_pose: None,
+        });
+    }
+
+    /// Cancels the running skill if it belongs to `request_id`.
+    pub fn cancel(&mut self, request_id: &str, reason: String, odom: Option<&Odometry>) -> bool {
+        let matches = self
+            .active
+            .as_ref()
+            .is_some_and(|running| running.request.request_id == request_id);
+        if matches {
+            self.finish(SkillState::Cancelled, Some(reason), odom);
+        }
+        matches
+    }
+
+    /// Ends the running skill because a higher-priority source took over.
+    pub fn preempt(&mut self, reason: String, odom: Option<&Odometry>) {
+        self.finish(SkillState::Preempted, Some(reason), odom);
+    }
+
+    /// Ends the running skill because motion is no longer allowed at all.
+    pub fn abort(&mut self, reason: String, odom: Option<&Odometry>) {
+        self.finish(SkillState::Aborted, Some(reason), odom);
+    }
+
+    /// Moves the running skill's goal with a /cmd/set_pose.
+    pub fn rebase(&mut self, shift: &FrameShift) {
+        if let Some(running) = self.active.as_mut() {
+            running.skill.rebase(shift);
+            tracing::info!(
+                "Skill '{}' ({}) goal moved with the pose reset",
+                running.request.skill_name,
+                running.request.request_id
+            );
+        }
+    }
+
+    /// Builds the requested skill and its deadline, or explains why not.
+    fn start(
+        &self,
+        request: &SkillCommand,
+        odom: Option<&Odometry>,
+    ) -> Result<(Box<dyn Skill>, Instant)> {
+        ensure!(
+            request.timeout_s > 0.0 && request.timeout_s <= self.max_timeout_s,
+            "timeout_s must be above 0 and at most {} s",
+            self.max_timeout_s
+        );
+        let deadline = Duration::try_from_secs_f32(request.timeout_s)
+            .ok()
+            .and_then(|timeout| Instant::now().checked_add(timeout))
+            .ok_or_else(|| anyhow!("timeout_s {} is out of range", request.timeout_s))?;
+        if let Some(running) = &self.active {
+            ensure!(
+                request.skill_name == "stop" || request.priority >= running.request.priority,
+                "priority {} is below running skill '{}' ({}) at priority {}",
+                request.priority,
+                running.request.skill_name,
+                running.request.request_id,
+                running.request.priority
+            );
+        }
+        let odom = odom.ok_or_else(|| anyhow!("no fresh odometry to run skills against"))?;
+        let skill = build_skill(&request.skill_name, &request.params, odom)?;
+        Ok((skill, deadline))
+    }
+
+    /// Advances the running skill and returns the command it wants, or `None`
+    /// when idle. Without fresh odometry the skill fails rather than drive blind.
+    pub fn step(&mut self, odom: Option<&Odometry>) -> Option<VelocityCommand> {
+        let running = self.active.as_mut()?;
+        if Instant::now() >= running.deadline {
+            self.finish(SkillState::TimedOut, None, odom);
+            return None;
+        }
+        let Some(odom) = odom else {
+            self.finish(SkillState::Failed, Some("odometry stale".to_string()), None);
+            return None;
+        };
+
+        match running.skill.step(odom) {
+            SkillStep::Active {
+                vx_m_s,
+                vy_m_s,
+                omega_rad_s,
+                progress,
+            } => {
+                running.progress = progress.clamp(0.0, 1.0);
+                let cmd = VelocityCommand {
+                    timestamp_ns: now_nanos(),
+                    vx_m_s: vx_m_s as f32,
+                    vy_m_s: vy_m_s as f32,
+                    omega_rad_s: omega_rad_s as f32,
+                    source: format!("skill:{}", running.request.skill_name),
+                    priority: running.request.priority,
+                    seq: None,
+                };
+                let due = running
+                    .last_status
+                    .is_none_or(|last| last.elapsed() >= STATUS_INTERVAL);
+                if due {
+                    running.last_status = Some(Instant::now());
+                    self.statuses
+                        .push(status(running, SkillState::Running, None, None));
+                }
+                Some(cmd)
+            }
+            SkillStep::Done => {
+                running.progress = 1.0;
+                self.finish(SkillState::Succeeded, None, Some(odom));
+                None
+            }
+        }
+    }
+
+    fn finish(&mut self, state: SkillState, error: Option<String>, odom: Option<&Odometry>) {
+        let Some(running) = self.active.take() else {
+            return;
+        };
+        let name = &running.request.skill_name;
+        let id = &running.request.request_id;
+        match (&state, &error) {
+            (SkillState::Succeeded, _) => tracing::info!("Skill '{name}' ({id}) succeeded"),
+            (_, Some(reason)) => tracing::warn!("Skill '{name}' ({id}) {state:?}: {reason}"),
+            (_, None) => tracing::warn!(
+                "Skill '{name}' ({id}) {state:?} at {:.0}% progress",
+                running.progress * 100.0
+            ),
+        }
+        self.statuses.push(status(&running, state, error, odom));
+    }
+}
+
+fn status(
+    running: &RunningSkill,
+    state: SkillState,
+    error: Option<String>,
+    odom: Option<&Odometry>,
+) -> SkillStatus {
+    SkillStatus {
+        timestamp_ns: now_nanos(),
+        request_id: running.request.request_id.clone(),
+        skill_name: running.request.skill_name.clone(),
+        state,
+        progress: running.progress,
+        error,
+        final_pose: odom.map(|odom| Pose2D {
+            x_m: odom.x_m,
+            y_m: odom.y_m,
+            theta_rad: odom.theta_rad,
+        }),
+    }
+}
+
+fn parse_params<T: DeserializeOwned>(params: &Value) -> Result<T> {
+    // A missing params object is fine for skills whose fields all default.
+    let params = if params.is_null() {
+        Value::Object(Default::default())
+    } else {
+        params.clone()
+    };
+    Ok(serde_json::from_value(params)?)
+}
+
+fn ensure_finite(name: &str, value: f64) -> Result<()> {
+    ensure!(value.is_finite(), "{name} must be finite");
+    Ok(())
+}
+
+fn ensure_angle(name: &str, value: f64) -> Result<()> {
+    ensure!(
+        value.is_finite() && value.abs() <= MAX_ABS_ANGLE_RAD,
+        "{name} must be finite and within ±{MAX_ABS_ANGLE_RAD:.2} rad"
+    );
+    Ok(())
+}
+
+fn ensure_positive(name: &str, value: f64) -> Result<()> {
+    ensure!(value.is_finite() && value > 0.0, "{name} must be positive");
+    Ok(())
+}
+
+/// Proportional approach towards zero error, with the speed kept between
+/// `min` and `max` so it neither overshoots nor stalls.
+fn approach(error: f64, gain: f64, min: f64, max: f64) -> f64 {
+    let speed = (error.abs() * gain).clamp(min.min(max), max);
+    speed.copysign(error)
+}
+
+fn default_tolerance_m() -> f64 {
+    0.02
+}
+
+fn default_tolerance_rad() -> f64 {
+    0.05
+}
+
+fn default_max_speed_m_s() -> f64 {
+    0.2
+}
+
+fn default_max_omega_rad_s() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RotateParams {
+    theta_rad: f64,
+    #[serde(default = "default_tolerance_rad")]
+    tolerance_rad: f64,
+    #[serde(default = "default_max_omega_rad_s")]
+    max_omega_rad_s: f64,
+}
+
+/// Turns in place to an absolute heading in the odom frame.
+struct RotateToHeading {
+    params: RotateParams,
+    initial_error: f64,
+}
+
+impl RotateToHeading {
+    fn build(params: &Value, odom: &Odometry) -> Result<Box<dyn Skill>> {
+        let params: RotateParams = parse_params(params)?;
+        ensure_angle("theta_rad", params.theta_rad)?;
+        ensure_positive("tolerance_rad", params.tolerance_rad)?;
+        ensure_positive("max_omega_rad_s", params.max_omega_rad_s)?;
+        let initial_error = normalize_angle(params.theta_rad - odom.theta_rad).abs();
+        Ok(Box::new(Self {
+            params,
+            initial_error,
+        }))
+    }
+}
+
+impl Skill for RotateToHeading {
+    fn step(&mut self, odom: &Odometry) -> SkillStep {
+        let error = normalize_angle(self.params.theta_rad - odom.theta_rad);
+        if error.abs() <= self.params.tolerance_rad {
+            return SkillStep::Done;
+        }
+        SkillStep::Active {
+            vx_m_s: 0.0,
+            vy_m_s: 0.0,
+            omega_rad_s: approach(
+                error,
+                K_ANGULAR,
+                MIN_OMEGA_RAD_S,
+                self.params.max_omega_rad_s,
+            ),
+            progress: fraction_done(error.abs(), self.initial_error),
+        }
+    }
+
+    fn rebase(&mut self, shift: &FrameShift) {
+        self.params.theta_rad = shift.heading(self.params.theta_rad);
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DistanceParams {
+    distance_m: f64,
+    #[serde(default = "default_tolerance_m")]
+    tolerance_m: f64,
+    #[serde(default = "default_max_speed_m_s")]
+    max_speed_m_s: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GotoParams {
+    x_m: f64,
+    y_m: f64,
+    /// Final heading; omitted keeps the heading held at start.
+    theta_rad: Option<f64>,
+    #[serde(default = "default_tolerance_m")]
+    tolerance_m: f64,
+    #[serde(default = "default_tolerance_rad")]
+    tolerance_rad: f64,
+    #[serde(default = "default_max_speed_m_s")]
+    max_speed_m_s: f64,
+    #[serde(default = "default_max_omega_rad_s")]
+    max_omega_rad_s: f64,
+}
+
+/// Holonomic move to a pose in the odom frame. `drive_distance` and `strafe`
+/// are the same controller with a goal along the starting body x or y axis,
+/// so heading is held and cross-track drift is corrected along the way.
+struct GotoPose {
+    goal: GotoParams,
+    theta_rad: f64,
+    initial_distance: f64,
+    initial_heading_error: f64,
+}
+
+impl GotoPose {
+    fn build_drive(params: &Value, odom: &Odometry) -> Result<Box<dyn Skill>> {
+        Self::build_relative(params, odom, 0.0)
+    }
+
+    fn build_strafe(params: &Value, odom: &Odometry) -> Result<Box<dyn Skill>> {
+        Self::build_relative(params, odom, std::f64::consts::FRAC_PI_2)
+    }
+
+    /// Goal `distance_m` from the current pose along the body axis rotated
+    /// by `axis_rad` (0 = forward, pi/2 = left).
+    fn build_relative(params: &Value, odom: &Odometry, axis_rad: f64) -> Result<Box<dyn Skill>> {
+        let params: DistanceParams = parse_params(params)?;
+        ensure_finite("distance_m", params.distance_m)?;
+        ensure_positive("tolerance_m", params.tolerance_m)?;
+        ensure_positive("max_speed_m_s", params.max_speed_m_s)?;
+
+        let direction = odom.theta_rad + axis_rad;
+        let goal = GotoParams {
+            x_m: odom.x_m + params.distance_m * direction.cos(),
+            y_m: odom.y_m + params.distance_m * direction.sin(),
+            theta_rad: None,
+            tolerance_m: params.tolerance_m,
+            tolerance_rad: default_tolerance_rad(),
+            max_speed_m_s: params.max_speed_m_s,
+            max_omega_rad_s: default_max_omega_rad_s(),
+        };
+        Ok(Box::new(Self::new(goal, odom)))
+    }
+
+    fn build_goto(params: &Value, odom: &Odometry) -> Result<Box<dyn Skill>> {
+        let params: GotoParams = parse_params(params)?;
+        ensure_finite("x_m", params.x_m)?;
+        ensure_finite("y_m", params.y_m)?;
+        if let Some(theta) = params.theta_rad {
+            ensure_angle("theta_rad", theta)?;
+        }
+        ensure_positive("tolerance_m", params.tolerance_m)?;
+        ensure_positive("tolerance_rad", params.tolerance_rad)?;
+        ensure_positive("max_speed_m_s", params.max_speed_m_s)?;
+        ensure_positive("max_omega_rad_s", params.max_omega_rad_s)?;
+        Ok(Box::new(Self::new(params, odom)))
+    }
+
+    fn new(goal: GotoParams, odom: &Odometry) -> Self {
+        let theta_rad = normalize_angle(goal.theta_rad.unwrap_or(odom.theta_rad));
+        Self {
+            initial_distance: (goal.x_m - odom.x_m).hypot(goal.y_m - odom.y_m),
+            initial_heading_error: normalize_angle(theta_rad - odom.theta_rad).abs(),
+            theta_rad,
+            goal,
+        }
+    }
+}
+
+impl Skill for GotoPose {
+    fn step(&mut self, odom: &Odometry) -> SkillStep {
+        let goal = &self.goal;
+        let dx = goal.x_m - odom.x_m;
+        let dy = goal.y_m - odom.y_m;
+        let distance = dx.hypot(dy);
+        let heading_error = normalize_angle(self.theta_rad - odom.theta_rad);
+
+        let position_done = distance <= goal.tolerance_m;
+        let heading_done = heading_error.abs() <= goal.tolerance_rad;
+        if position_done && heading_done {
+            return SkillStep::Done;
+        }
+
+        let (vx_m_s, vy_m_s) = if position_done {
+            (0.0, 0.0)
+        } else {
+            let speed = approach(distance, K_LINEAR, MIN_SPEED_M_S, goal.max_speed_m_s);
+            let (sin_t, cos_t) = odom.theta_rad.sin_cos();
+            let (ox, oy) = (dx / distance * speed, dy / distance * speed);
+            (cos_t * ox + sin_t * oy, -sin_t * ox + cos_t * oy)
+        };
+        let omega_rad_s = if heading_done {
+            0.0
+        } else {
+            approach(
+                heading_error,
+                K_ANGULAR,
+                MIN_OMEGA_RAD_S,
+                goal.max_omega_rad_s,
+            )
+        };
+
+        SkillStep::Active {
+            vx_m_s,
+            vy_m_s,
+            omega_rad_s,
+            progress: fraction_done(distance, self.initial_distance).min(fraction_done(
+                heading_error.abs(),
+                self.initial_heading_error,
+            )),
+        }
+    }
+
+    fn rebase(&mut self, shift: &FrameShift) {
+        (self.goal.x_m, self.goal.y_m) = shift.point(self.goal.x_m, self.goal.y_m);
+        self.theta_rad = shift.heading(self.theta_rad);
+    }
+}
+
+/// Commands zero until odometry shows the base has come to rest.
+struct Stop;
+
+impl Stop {
+    fn build(params: &Value, _odom: &Odometry) -> Result<Box<dyn Skill>> {
+        let _: StopParams = parse_params(params)?;
+        Ok(Box::new(Self))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StopParams {}
+
+impl Skill for Stop {
+    fn step(&mut self, odom: &Odometry) -> SkillStep {
+        let speed = (odom.vx_m_s as f64).hypot(odom.vy_m_s as f64);
+        if speed <= STOPPED_SPEED_M_S && (odom.omega_rad_s as f64).abs() <= STOPPED_OMEGA_RAD_S {
+            return SkillStep::Done;
+        }
+        SkillStep::Active {
+            vx_m_s: 0.0,
+            vy_m_s: 0.0,
+            omega_rad_s: 0.0,
+            progress: 0.0,
+        }
+    }
+}
+
+fn fraction_done(remaining: f64, initial: f64) -> f32 {
+    if initial <= f64::EPSILON {
+        return 1.0;
+    }
+    (1.0 - remaining / initial).clamp(0.0, 1.0) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const DT_S: f64 = 0.02;
+
+    fn executor() -> SkillExecutor {
+        SkillExecutor::new(&SkillsConfig::default())
+    }
+
+    fn odom(x_m: f64, y_m: f64, theta_rad: f64) -> Odometry {
+        Odometry {
+            timestamp_ns: 0,
+            x_m,
+            y_m,
+            theta_rad,
+            vx_m_s: 0.0,
+            vy_m_s: 0.0,
+            omega_rad_s: 0.0,
+            frame_id: "odom".to_string(),
+            pose_covariance: None,
+        }
+    }
+
+    fn request(skill_name: &str, params: Value) -> SkillCommand {
+        SkillCommand {
+            timestamp_ns: 0,
+            request_id: format!("{skill_name}-1"),
+            skill_name: skill_name.to_string(),
+            params,
+            timeout_s: 30.0,
+            priority: 0,
+        }
+    }
+
+    fn last_state(executor: &mut SkillExecutor) -> SkillState {
+        executor.take_statuses().last().unwrap().state
+    }
+
+    /// Steps the running skill against a base that follows every command
+    /// exactly, until it finishes, and returns its final state.
+    fn run(executor: &mut SkillExecutor, odom: &mut Odometry) -> SkillState {
+        for _ in 0..5000 {
+            let Some(cmd) = executor.step(Some(odom)) else {
+                return last_state(executor);
+            };
+            let (vx, vy) = (cmd.vx_m_s as f64, cmd.vy_m_s as f64);
+            let (sin_t, cos_t) = odom.theta_rad.sin_cos();
+            odom.x_m += (vx * cos_t - vy * sin_t) * DT_S;
+            odom.y_m += (vx * sin_t + vy * cos_t) * DT_S;
+            odom.theta_rad = normalize_angle(odom.theta_rad + cmd.omega_rad_s as f64 * DT_S);
+            odom.vx_m_s = cmd.vx_m_s;
+            odom.vy_m_s = cmd.vy_m_s;
+            odom.omega_rad_s = cmd.omega_rad_s;
+        }
+        panic!("skill did not finish");
+    }
+
+    fn assert_pose(odom: &Odometry, x_m: f64, y_m: f64, theta_rad: f64) {
+        assert!(
+            (odom.x_m - x_m).hypot(odom.y_m - y_m) <= 0.02,
+            "at ({:.3}, {:.3}), expected ({x_m:.3}, {y_m:.3})",
+            odom.x_m,
+            odom.y_m
+        );
+        assert!(
+            normalize_angle(odom.theta_rad - theta_rad).abs() <= 0.05,
+            "heading {:.3}, expected {theta_rad:.3}",
+            odom.theta_rad
+        );
+    }
+
+    fn accepts(skill_name: &str, params: Value) -> bool {
+        let mut executor = executor();
+        executor.submit(request(skill_name, params), Some(&odom(0.0, 0.0, 0.0)));
+        last_state(&mut executor) == SkillState::Accepted
+    }
+
+    #[test]
+    fn rotate_to_heading_turns_the_short_way() {
+        let mut executor = executor();
+        let mut odom = odom(0.0, 0.0, 3.0);
+        let params = json!({ "theta_rad": -3.0 });
+        executor.submit(request("rotate_to_heading", params), Some(&odom));
+        assert_eq!(
+            executor.step(Some(&odom)).unwrap().omega_rad_s.signum(),
+            1.0
+        );
+        assert_eq!(run(&mut executor, &mut odom), SkillState::Succeeded);
+        assert_pose(&odom, 0.0, 0.0, -3.0);
+    }
+
+    #[test]
+    fn drive_distance_and_strafe_follow_the_starting_heading() {
+        let mut executor = executor();
+        let mut odom = odom(1.0, 1.0, std::f64::consts::FRAC_PI_2);
+        executor.submit(
+            request("drive_distance", json!({ "distance_m": 0.5 })),
+            Some(&odom),
+        );
+        assert_eq!(run(&mut executor, &mut odom), SkillState::Succeeded);
+        assert_pose(&odom, 1.0, 1.5, std::f64::consts::FRAC_PI_2);
+
+        let (x_m, y_m) = (odom.x_m, odom.y_m);
+        executor.submit(request("strafe", json!({ "distance_m": 0.3 })), Some(&odom));
+        assert_eq!(run(&mut executor, &mut odom), SkillState::Succeeded);
+        // Left of a base facing +y is -x.
+        assert_pose(&odom, x_m - 0.3, y_m, std::f64::consts::FRAC_PI_2);
+    }
+
+    #[test]
+    fn goto_pose_reaches_position_and_heading() {
+        let mut executor = executor();
+        let mut odom = odom(0.0, 0.0, 0.0);
+        let params = json!({ "x_m": 0.4, "y_m": -0.2, "theta_rad": -1.0 });
+        executor.submit(request("goto_pose", params), Some(&odom));
+        assert_eq!(run(&mut executor, &mut odom), SkillState::Succeeded);
+        assert_pose(&odom, 0.4, -0.2, -1.0);
+    }
+
+    #[test]
+    fn stop_waits_for_the_base_to_come_to_rest() {
+        let mut executor = executor();
+        let mut odom = odom(0.0, 0.0, 0.0);
+        odom.vx_m_s = 0.3;
+        executor.submit(request("stop", Value::Null), Some(&odom));
+        let cmd = executor.step(Some(&odom)).unwrap();
+        assert!(cmd.is_zero());
+        assert_eq!(run(&mut executor, &mut odom), SkillState::Succeeded);
+    }
+
+    #[test]
+    fn times_out_at_the_deadline() {
+        let mut executor = executor();
+        let odom = odom(0.0, 0.0, 0.0);
+        let mut slow = request("drive_distance", json!({ "distance_m": 5.0 }));
+        slow.timeout_s = 0.05;
+        executor.submit(slow, Some(&odom));
+        assert!(executor.step(Some(&odom)).is_some());
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(executor.step(Some(&odom)).is_none());
+        assert_eq!(last_state(&mut executor), SkillState::TimedOut);
+        assert!(!executor.is_active());
+    }
+
+    #[test]
+    fn fails_without_odometry() {
+        let mut executor = executor();
+        executor.submit(request("stop", Value::Null), Some(&odom(0.0, 0.0, 0.0)));
+        assert!(executor.step(None).is_none());
+        assert_eq!(last_state(&mut executor), SkillState::Failed);
+    }
+
+    #[test]
+    fn angles_are_bounded_to_two_turns() {
+        let turns = |turns: f64| json!({ "theta_rad": turns * std::f64::consts::PI });
+        assert!(accepts("rotate_to_heading", turns(4.0)));
+        assert!(accepts("rotate_to_heading", turns(-4.0)));
+        assert!(!accepts("rotate_to_heading", turns(4.01)));
+        assert!(!accepts("rotate_to_heading", turns(-4.01)));
+        let goto = json!({ "x_m": 0.0, "y_m": 0.0, "theta_rad": 13.0 });
+        assert!(!accepts("goto_pose", goto));
+    }
+
+    #[test]
+    fn rejects_invalid_params() {
+        assert!(!accepts("drive_distance", json!({})));
+        assert!(!accepts(
+            "drive_distance",
+            json!({ "distance_m": 1.0, "speed": 1.0 })
+        ));
+        assert!(!accepts(
+            "drive_distance",
+            json!({ "distance_m": 1.0, "max_speed_m_s": 0.0 })
+        ));
+        assert!(!accepts(
+            "strafe",
+            json!({ "distance_m": 1.0, "tolerance_m": -0.1 })
+        ));
+        assert!(!accepts("fly", Value::Null));
+        assert!(accepts("stop", json!({})));
+    }
+
+    #[test]
+    fn timeout_is_bounded_by_max_timeout_s() {
+        let odom = odom(0.0, 0.0, 0.0);
+        for (timeout_s, accepted) in [(0.0, false), (-1.0, false), (300.0, true), (300.5, false)] {
+            let mut executor = executor();
+            let mut stop = request("stop", Value::Null);
+            stop.timeout_s = timeout_s;
+            executor.submit(stop, Some(&odom));
+            let state = last_state(&mut executor);
+            assert_eq!(
+                state == SkillState::Accepted,
+                accepted,
+                "timeout_s {timeout_s}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_requests_without_odometry() {
+        let mut executor = executor();
+        executor.submit(request("stop", Value::Null), None);
+        assert_eq!(last_state(&mut executor), SkillState::Rejected);
+    }
+
+    #[test]
+    fn lower_priority_is_rejected_but_stop_always_wins() {
+        let mut executor = executor();
+        let odom = odom(0.0, 0.0, 0.0);
+        let mut running = request("drive_distance", json!({ "distance_m": 1.0 }));
+        running.priority = 5;
+        executor.submit(running, Some(&odom));
+        executor.submit(request("strafe", json!({ "distance_m": 1.0 })), Some(&odom));
+        assert_eq!(last_state(&mut executor), SkillState::Rejected);
+        assert!(executor.is_active());
+
+        executor.submit(request("stop", Value::Null), Some(&odom));
+        let states: Vec<SkillState> = executor
+            .take_statuses()
+            .iter()
+            .map(|status| status.state)
+            .collect();
+        assert_eq!(states, [SkillState::Preempted, SkillState::Accepted]);
+    }
+
+    #[test]
+    fn cancel_matches_the_request_id() {
+        let mut executor = executor();
+        let odom = odom(0.0, 0.0, 0.0);
+        executor.submit(
+            request("drive_distance", json!({ "distance_m": 1.0 })),
+            Some(&odom),
+        );
+        executor.take_statuses();
+        assert!(!executor.cancel("other", "test".to_string(), Some(&odom)));
+        assert!(executor.is_active());
+        assert!(executor.cancel("drive_distance-1", "test".to_string(), Some(&odom)));
+        assert_eq!(last_state(&mut executor), SkillState::Cancelled);
+        assert!(executor.step(Some(&odom)).is_none());
+    }
+
+    #[test]
+    fn pose_reset_moves_the_goal_with_the_frame() {
+        let mut executor = executor();
+        let mut odom = odom(0.0, 0.0, 0.0);
+        let params = json!({ "x_m": 1.0, "y_m": 0.0, "theta_rad": 0.5 });
+        executor.submit(request("goto_pose", params), Some(&odom));
+        for _ in 0..20 {
+            let cmd = executor.step(Some(&odom)).unwrap();
+            odom.x_m += cmd.vx_m_s as f64 * DT_S;
+            odom.theta_rad += cmd.omega_rad_s as f64 * DT_S;
+        }
+
+        let reset = PoseReset {
+            timestamp_ns: 0,
+            previous_x_m: odom.x_m,
+            previous_y_m: odom.y_m,
+            previous_theta_rad: odom.theta_rad,
+            x_m: 5.0,
+            y_m: 5.0,
+            theta_rad: odom.theta_rad + std::f64::consts::FRAC_PI_2,
+            source: "test".to_string(),
+            frame_id: "odom".to_string(),
+        };
+        let shift = FrameShift::new(&reset);
+        let (goal_x, goal_y) = shift.point(1.0, 0.0);
+        let goal_theta = shift.heading(0.5);
+        let x_before = odom.x_m;
+        (odom.x_m, odom.y_m, odom.theta_rad) = (reset.x_m, reset.y_m, reset.theta_rad);
+        executor.rebase(&shift);
+
+        assert!((goal_x - 5.0).abs() < 1e-9 && (goal_y - (6.0 - x_before)).abs() < 1e-9);
+        assert_eq!(run(&mut executor, &mut odom), SkillState::Succeeded);
+        assert_pose(&odom, goal_x, goal_y, goal_theta);
+    }
+}
@@ -0,0 +1,201 @@
+use anyhow::{anyhow, ensure, Result};
+
+use crate::config::{SpeedModeConfig, SpeedModesConfig};
+use crate::messages::SpeedScaleCommand;
+
+/// Runtime speed selection: a named mode from config times an operator
+/// scale from /cmd/speed_scale. Changes take effect on the next tick, so a
+/// new driver can be put in crawl without editing robot.yaml.
+pub struct SpeedControl {
+    modes: Vec<SpeedModeConfig>,
+    mode: usize,
+    scale: f32,
+}
+
+impl SpeedControl {
+    pub fn new(config: &SpeedModesConfig) -> Result<Self> {
+        for (i, mode) in config.modes.iter().enumerate() {
+            ensure!(
+                config.modes[..i]
+                    .iter()
+                    .all(|other| other.name != mode.name),
+                "speed mode '{}' is listed twice",
+                mode.name
+            );
+            ensure!(
+                mode.speed_scale > 0.0 && mode.speed_scale <= 1.0,
+                "speed mode '{}' speed_scale must be in (0, 1]",
+                mode.name
+            );
+            ensure!(
+                mode.accel_scale > 0.0 && mode.accel_scale <= 1.0,
+                "speed mode '{}' accel_scale must be in (0, 1]",
+                mode.name
+            );
+        }
+        let mode = find(&config.modes, &config.default_mode).ok_or_else(|| {
+            anyhow!(
+                "speed_modes.default_mode '{}' is not a configured mode",
+                config.default_mode
+            )
+        })?;
+        Ok(Self {
+            modes: config.modes.clone(),
+            mode,
+            scale: 1.0,
+        })
+    }
+
+    /// Applies a request, or leaves everything unchanged and warns if any
+    /// part of it is invalid. Returns whether the limits changed.
+    pub fn apply(&mut self, cmd: &SpeedScaleCommand) -> bool {
+        let mode = match cmd.mode.as_deref() {
+            Some(name) => match find(&self.modes, name) {
+                Some(mode) => mode,
+                None => {
+                    let known: Vec<&str> =
+                        self.modes.iter().map(|mode| mode.name.as_str()).collect();
+                    tracing::warn!(
+                        "Unknown speed mode '{name}' from {}; known modes: {}",
+                        cmd.source,
+                        known.join(", ")
+                    );
+                    return false;
+                }
+            },
+            None => self.mode,
+        };
+        let scale = match cmd.scale {
+            Some(scale) if !(0.0..=1.0).contains(&scale) => {
+                tracing::warn!(
+                    "Ignoring speed scale {scale} from {}; must be within 0.0..=1.0",
+                    cmd.source
+                );
+                return false;
+            }
+            Some(scale) => scale,
+            None => self.scale,
+        };
+        if mode == self.mode && scale == self.scale {
+            return false;
+        }
+
+        self.mode = mode;
+        self.scale = scale;
+        tracing::info!(
+            "Speed mode '{}' at {:.0}% of limits (set by {})",
+            self.mode_name(),
+            self.speed_scale() * 100.0,
+            cmd.source
+        );
+        true
+    }
+
+    pub fn mode_name(&self) -> &str {
+        &self.modes[self.mode].name
+    }
+
+    /// Combined factor for the velocity caps.
+    pub fn speed_scale(&self) -> f32 {
+        self.modes[self.mode].speed_scale * self.scale
+    }
+
+    /// Factor for the acceleration and jerk caps while speeding up. Neither
+    /// this nor the operator scale softens braking; the limiter always
+    /// decelerates at the configured caps.
+    pub fn accel_scale(&self) -> f32 {
+        self.modes[self.mode].accel_scale
+    }
+}
+
+fn find(modes: &[SpeedModeConfig], name: &str) -> Option<usize> {
+    modes.iter().position(|mode| mode.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(mode: Option<&str>, scale: Option<f32>) -> SpeedScaleCommand {
+        SpeedScaleCommand {
+            timestamp_ns: 1,
+            mode: mode.map(str::to_string),
+            scale,
+            source: "test".to_string(),
+        }
+    }
+
+    fn control() -> SpeedControl {
+        SpeedControl::new(&SpeedModesConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn starts_in_the_default_mode() {
+        let speed = control();
+        assert_eq!(speed.mode_name(), "normal");
+        assert_eq!((speed.speed_scale(), speed.accel_scale()), (1.0, 1.0));
+    }
+
+    #[test]
+    fn selects_modes_and_keeps_the_operator_scale() {
+        let mut speed = control();
+        assert!(speed.apply(&request(None, Some(0.5))));
+        assert!(speed.apply(&request(Some("crawl"), None)));
+        assert_eq!(speed.mode_name(), "crawl");
+        assert!((speed.speed_scale() - 0.1).abs() < 1e-6);
+        assert_eq!(speed.accel_scale(), 0.5);
+
+        // Repeating the current setting changes nothing.
+        assert!(!speed.apply(&request(Some("crawl"), Some(0.5))));
+        assert!(speed.apply(&request(Some("normal"), Some(1.0))));
+        assert_eq!(speed.speed_scale(), 1.0);
+    }
+
+    #[test]
+    fn accepts_the_scale_bounds_and_rejects_beyond() {
+        let mut speed = control();
+        assert!(speed.apply(&request(None, Some(0.0))));
+        assert_eq!(speed.speed_scale(), 0.0);
+        assert!(speed.apply(&request(None, Some(1.0))));
+        for scale in [-0.1, 1.01, f32::NAN] {
+            assert!(!speed.apply(&request(None, Some(scale))), "{scale}");
+            assert_eq!(speed.speed_scale(), 1.0);
+        }
+    }
+
+    #[test]
+    fn invalid_requests_change_nothing() {
+        let mut speed = control();
+        assert!(!speed.apply(&request(Some("ludicrous"), Some(0.5))));
+        // The valid scale in the same request is not applied either.
+        assert!(!speed.apply(&request(Some("demo"), Some(2.0))));
+        assert_eq!(speed.mode_name(), "normal");
+        assert_eq!(speed.speed_scale(), 1.0);
+    }
+
+    #[test]
+    fn rejects_invalid_mode_tables() {
+        let mode = |name: &str, speed_scale, accel_scale| SpeedModeConfig {
+            name: name.to_string(),
+            speed_scale,
+            accel_scale,
+        };
+        let config = |modes, default_mode: &str| SpeedModesConfig {
+            modes,
+            default_mode: default_mode.to_string(),
+        };
+        let invalid = [
+            config(vec![mode("normal", 1.0, 1.0)], "turbo"),
+            config(
+                vec![mode("normal", 1.0, 1.0), mode("normal", 0.5, 0.5)],
+                "normal",
+            ),
+            config(vec![mode("normal", 0.0, 1.0)], "normal"),
+            config(vec![mode("normal", 1.5, 1.0)], "normal"),
+            config(vec![mode("normal", 1.0, 0.0)], "normal"),
+        ];
+        for config in invalid {
+            assert!(SpeedControl::new(&config).is_err(), "{config:?}");
+        }
+    }
+}
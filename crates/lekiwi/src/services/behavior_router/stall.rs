@@ -0,0 +1,278 @@
+use std::time::{Duration, Instant};
+
+use crate::config::{StallConfig, WheelMount};
+use crate::messages::{SafetyEvent, SafetyEventKind, ServoStateArray, WheelTargetArray};
+use crate::utils::now_nanos;
+
+/// Wheel targets older than this count as a zero command, matching what
+/// motor_bus drives on a stalled pipeline.
+const TARGET_TIMEOUT: Duration = Duration::from_millis(500);
+
+struct WheelMonitor {
+    name: String,
+    servo_id: u8,
+    config: StallConfig,
+    /// When high load and tracking error started holding together.
+    stalling_since: Option<Instant>,
+    last_load: Option<f32>,
+}
+
+/// Compares each wheel's measured speed and load against its target. A wheel
+/// that pushes hard without tracking for `debounce_ms` is stalled; a sudden
+/// load rise while driving is an impact. Either one trips a hold that keeps
+/// the base stopped until the velocity command returns to zero, so the
+/// robot does not keep shoving into whatever it hit.
+pub struct StallDetector {
+    wheels: Vec<WheelMonitor>,
+    targets: Option<(WheelTargetArray, Instant)>,
+    tripped: Option<String>,
+}
+
+impl StallDetector {
+    pub fn new(mounts: &[WheelMount]) -> Self {
+        let wheels = mounts
+            .iter()
+            .map(|mount| WheelMonitor {
+                name: mount.name.clone(),
+                servo_id: mount.servo_id,
+                config: mount.stall.clone(),
+                stalling_since: None,
+                last_load: None,
+            })
+            .collect();
+        Self {
+            wheels,
+            targets: None,
+            tripped: None,
+        }
+    }
+
+    pub fn update_targets(&mut self, targets: WheelTargetArray) {
+        self.targets = Some((targets, Instant::now()));
+    }
+
+    /// Checks every wheel in `state`; returns the event that tripped the
+    /// hold, if this reading did.
+    pub fn update_servos(&mut self, state: &ServoStateArray) -> Option<SafetyEvent> {
+        let now = Instant::now();
+        let targets = self
+            .targets
+            .as_ref()
+            .filter(|(_, received)| received.elapsed() <= TARGET_TIMEOUT)
+            .map(|(targets, _)| targets);
+
+        let mut event = None;
+        for servo in &state.servos {
+            let Some(wheel) = self
+                .wheels
+                .iter_mut()
+                .find(|wheel| wheel.servo_id == servo.id)
+            else {
+                continue;
+            };
+            let commanded = targets
+                .and_then(|targets| {
+                    targets
+                        .wheels
+                        .iter()
+                        .find(|target| target.servo_id == servo.id)
+                })
+                .map(|target| target.velocity_rad_s)
+                .unwrap_or(0.0);
+            let measured = servo.velocity_rad_s;
+            let load = servo.load.abs();
+            let tracking_error = (commanded - measured).abs();
+            let load_rise = wheel.last_load.map(|last| load - last);
+            wheel.last_load = Some(load);
+
+            let config = &wheel.config;
+            let stalling =
+                load >= config.load_threshold && tracking_error >= config.velocity_error_rad_s;
+            if !stalling {
+                wheel.stalling_since = None;
+            }
+            let since = stalling.then(|| *wheel.stalling_since.get_or_insert(now));
+
+            let kind = match (config.impact_load_jump, load_rise) {
+                (Some(jump), Some(rise)) if commanded != 0.0 && rise >= jump => {
+                    Some(SafetyEventKind::Impact)
+                }
+                _ => since
+                    .filter(|since| {
+                        now.duration_since(*since) >= Duration::from_millis(config.debounce_ms)
+                    })
+                    .map(|_| SafetyEventKind::Stall),
+            };
+            if let (Some(kind), None) = (kind, &event) {
+                let detail = match kind {
+                    SafetyEventKind::Stall => format!(
+                        "{} stalled: load {load:.2}, commanded {commanded:.2} rad/s, measured {measured:.2} rad/s",
+                        wheel.name
+                    ),
+                    SafetyEventKind::Impact => format!(
+                        "{} impact: load rose by {:.2} to {load:.2}",
+                        wheel.name,
+                        load_rise.unwrap_or_default()
+                    ),
+                };
+                event = Some(SafetyEvent {
+                    timestamp_ns: now_nanos(),
+                    kind,
+                    wheel: wheel.name.clone(),
+                    servo_id: servo.id,
+                    load: servo.load,
+                    commanded_rad_s: commanded,
+                    measured_rad_s: measured,
+                    detail,
+                });
+            }
+        }
+
+        if self.tripped.is_some() {
+            return None;
+        }
+        let event = event?;
+        tracing::warn!("Stopping: {}", event.detail);
+        self.tripped = Some(event.detail.clone());
+        Some(event)
+    }
+
+    /// Why motion is held, if it is.
+    pub fn tripped(&self) -> Option<&str> {
+        self.tripped.as_deref()
+    }
+
+    /// Releases the hold once nothing is commanding motion.
+    pub fn clear_if_idle(&mut self, idle: bool) {
+        if idle && self.tripped.take().is_some() {
+            tracing::info!("Stall hold cleared; command returned to zero");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use super::*;
+    use crate::messages::{ServoState, WheelTarget};
+
+    const DEBOUNCE: Duration = Duration::from_millis(30);
+
+    fn detector(impact_load_jump: Option<f32>) -> StallDetector {
+        let mount = WheelMount {
+            name: "front".to_string(),
+            angle_deg: 0.0,
+            servo_id: 1,
+            direction: 1,
+            stall: StallConfig {
+                load_threshold: 0.8,
+                velocity_error_rad_s: 1.0,
+                debounce_ms: DEBOUNCE.as_millis() as u64,
+                impact_load_jump,
+            },
+        };
+        StallDetector::new(&[mount])
+    }
+
+    fn command(detector: &mut StallDetector, velocity_rad_s: f32) {
+        detector.update_targets(WheelTargetArray {
+            timestamp_ns: now_nanos(),
+            wheels: vec![WheelTarget {
+                name: "front".to_string(),
+                servo_id: 1,
+                velocity_rad_s,
+            }],
+            saturation_scale: 1.0,
+        });
+    }
+
+    fn reading(velocity_rad_s: f32, load: f32) -> ServoStateArray {
+        ServoStateArray {
+            timestamp_ns: now_nanos(),
+            servos: vec![ServoState {
+                id: 1,
+                name: "front".to_string(),
+                position_rad: 0.0,
+                velocity_rad_s,
+                load,
+                temperature_c: 30.0,
+                voltage_v: 12.0,
+                error_flags: 0,
+            }],
+        }
+    }
+
+    #[test]
+    fn stall_is_declared_after_the_debounce() {
+        let mut stall = detector(None);
+        command(&mut stall, 2.0);
+        assert!(stall.update_servos(&reading(0.0, 0.9)).is_none());
+        assert!(stall.update_servos(&reading(0.0, -0.9)).is_none());
+        sleep(DEBOUNCE);
+
+        let event = stall.update_servos(&reading(0.0, -0.9)).unwrap();
+        assert_eq!(event.kind, SafetyEventKind::Stall);
+        assert_eq!((event.servo_id, event.commanded_rad_s), (1, 2.0));
+        assert!(stall.tripped().is_some());
+    }
+
+    #[test]
+    fn a_break_in_the_stall_restarts_the_debounce() {
+        let mut stall = detector(None);
+        command(&mut stall, 2.0);
+        stall.update_servos(&reading(0.0, 0.9));
+        sleep(DEBOUNCE);
+        // Tracking again, even under load.
+        assert!(stall.update_servos(&reading(1.8, 0.9)).is_none());
+        assert!(stall.update_servos(&reading(0.0, 0.9)).is_none());
+        assert!(stall.tripped().is_none());
+    }
+
+    #[test]
+    fn load_alone_or_lag_alone_is_not_a_stall() {
+        let mut stall = detector(None);
+        command(&mut stall, 2.0);
+        for _ in 0..2 {
+            assert!(stall.update_servos(&reading(1.9, 0.95)).is_none());
+            assert!(stall.update_servos(&reading(0.0, 0.3)).is_none());
+            sleep(DEBOUNCE);
+        }
+    }
+
+    #[test]
+    fn load_jump_while_driving_is_an_impact() {
+        let mut stall = detector(Some(0.5));
+        command(&mut stall, 2.0);
+        assert!(stall.update_servos(&reading(2.0, 0.1)).is_none());
+        let event = stall.update_servos(&reading(1.5, 0.7)).unwrap();
+        assert_eq!(event.kind, SafetyEventKind::Impact);
+    }
+
+    #[test]
+    fn load_jump_while_parked_is_ignored() {
+        let mut stall = detector(Some(0.5));
+        command(&mut stall, 0.0);
+        assert!(stall.update_servos(&reading(0.0, 0.0)).is_none());
+        assert!(stall.update_servos(&reading(0.0, 0.7)).is_none());
+    }
+
+    #[test]
+    fn hold_lasts_until_the_command_goes_idle() {
+        let mut stall = detector(Some(0.5));
+        command(&mut stall, 2.0);
+        stall.update_servos(&reading(2.0, 0.1));
+        assert!(stall.update_servos(&reading(1.5, 0.7)).is_some());
+
+        // Further trips while held are not reported again.
+        stall.update_servos(&reading(2.0, 0.1));
+        assert!(stall.update_servos(&reading(1.5, 0.7)).is_none());
+        stall.clear_if_idle(false);
+        assert!(stall.tripped().is_some());
+
+        stall.clear_if_idle(true);
+        assert!(stall.tripped().is_none());
+        stall.update_servos(&reading(2.0, 0.1));
+        assert!(stall.update_servos(&reading(1.5, 0.7)).is_some());
+    }
+}
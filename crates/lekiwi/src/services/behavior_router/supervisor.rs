@@ -0,0 +1,278 @@
+use std::time::{Duration, Instant};
+
+use crate::config::SafetyConfig;
+use crate::messages::{PowerState, ServoStateArray};
+use crate::servo::feetech::error_bits;
+
+/// Older servo readings are ignored; the torque gate already holds the base
+/// when the bus goes quiet.
+const SERVO_STATE_TIMEOUT: Duration = Duration::from_secs(3);
+/// Low-battery readings must persist this long, by their own timestamps,
+/// before they stop the base, so a sag under a load spike does not.
+const LOW_BATTERY_HOLD: Duration = Duration::from_secs(3);
+
+/// What the supervisor allows this tick.
+pub struct SafetyAssessment {
+    /// Speed multiplier from temperature derating, in (0, 1].
+    pub scale: f32,
+    /// Why motion must stop, if it must.
+    pub stop: Option<String>,
+    pub warnings: Vec<String>,
+}
+
+impl SafetyAssessment {
+    fn stop(&mut self, reason: String) {
+        self.warnings.push(reason.clone());
+        self.stop.get_or_insert(reason);
+    }
+}
+
+/// Derates speed as the hottest servo nears `servo_temp_limit_c` and stops
+/// at the limit, on any STS error flag, or on low battery that lasts
+/// `LOW_BATTERY_HOLD`. The low-battery stop latches, since the pack voltage
+/// recovers once the load is gone and driving again would just sag it back
+/// down; an accepted e-stop reset clears it once the pack reads above the
+/// cutoff again.
+pub struct SafetySupervisor {
+    config: SafetyConfig,
+    servos: Option<(ServoStateArray, Instant)>,
+    /// Whether the latest power reading was below the cutoff.
+    battery_low: bool,
+    /// Timestamp of the first reading in the current low streak.
+    low_since_ns: Option<u64>,
+    low_battery_v: Option<f32>,
+    stopped: Option<String>,
+}
+
+impl SafetySupervisor {
+    pub fn new(config: SafetyConfig) -> Self {
+        Self {
+            config,
+            servos: None,
+            battery_low: false,
+            low_since_ns: None,
+            low_battery_v: None,
+            stopped: None,
+        }
+    }
+
+    pub fn update_servos(&mut self, state: ServoStateArray) {
+        self.servos = Some((state, Instant::now()));
+    }
+
+    pub fn update_power(&mut self, power: &PowerState) {
+        self.battery_low = power.low_battery;
+        if !power.low_battery || !self.config.low_battery_stop {
+            self.low_since_ns = None;
+            return;
+        }
+        let since_ns = *self.low_since_ns.get_or_insert(power.timestamp_ns);
+        let low_for = Duration::from_nanos(power.timestamp_ns.saturating_sub(since_ns));
+        if low_for >= LOW_BATTERY_HOLD && self.low_battery_v.is_none() {
+            self.low_battery_v = Some(power.battery_voltage_v);
+        }
+    }
+
+    /// Called when an e-stop reset is accepted; clears the low-battery latch
+    /// unless the pack still reads below the cutoff.
+    pub fn reset(&mut self) {
+        let Some(voltage) = self.low_battery_v else {
+            return;
+        };
+        if self.battery_low {
+            tracing::warn!("Low-battery stop kept: pack still below the cutoff");
+        } else {
+            tracing::info!("Low-battery stop ({voltage:.1} V) cleared by reset");
+            self.low_battery_v = None;
+        }
+    }
+
+    pub fn assess(&mut self) -> SafetyAssessment {
+        let mut assessment = SafetyAssessment {
+            scale: 1.0,
+            stop: None,
+            warnings: Vec::new(),
+        };
+
+        let servos = self
+            .servos
+            .as_ref()
+            .filter(|(_, received)| received.elapsed() <= SERVO_STATE_TIMEOUT)
+            .map(|(state, _)| state.servos.as_slice())
+            .unwrap_or_default();
+        for servo in servos {
+            let faults = error_bits::names(servo.error_flags as u8);
+            if !faults.is_empty() {
+                assessment.stop(format!("servo_fault: {} {}", servo.name, faults.join(", ")));
+            }
+        }
+
+        let limit = self.config.servo_temp_limit_c;
+        let hottest = servos
+            .iter()
+            .max_by(|a, b| a.temperature_c.total_cmp(&b.temperature_c));
+        if let Some(servo) = hottest {
+            let temperature = servo.temperature_c;
+            if temperature >= limit {
+                assessment.stop(format!(
+                    "servo_overtemp: {} at {temperature:.0} C (limit {limit:.0} C)",
+                    servo.name
+                ));
+            } else {
+                let band = self.config.servo_temp_derate_c.max(f32::EPSILON);
+                let scale = ((limit - temperature) / band).min(1.0);
+                if scale < 1.0 {
+                    assessment.scale = scale;
+                    assessment.warnings.push(format!(
+                        "servo_temp_derate: {} at {temperature:.0} C, speed scaled to {:.0}%",
+                        servo.name,
+                        scale * 100.0
+                    ));
+                }
+            }
+        }
+
+        if let Some(voltage) = self.low_battery_v {
+            assessment.stop(format!("low_battery: {voltage:.1} V"));
+        }
+
+        if assessment.stop != self.stopped {
+            match &assessment.stop {
+                Some(reason) => tracing::error!("Safety stop: {reason}"),
+                None => tracing::info!("Safety stop cleared"),
+            }
+            self.stopped = assessment.stop.clone();
+        }
+        assessment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::ServoState;
+
+    const SECOND_NS: u64 = 1_000_000_000;
+
+    fn supervisor() -> SafetySupervisor {
+        SafetySupervisor::new(SafetyConfig {
+            command_timeout_ms: 250,
+            estop_enabled: true,
+            estop_reset_sources: vec!["foxglove".to_string()],
+            servo_temp_limit_c: 70.0,
+            servo_temp_derate_c: 10.0,
+            low_battery_stop: true,
+        })
+    }
+
+    fn servos(temperatures: &[f32], error_flags: u32) -> ServoStateArray {
+        let servos = temperatures
+            .iter()
+            .enumerate()
+            .map(|(i, temperature_c)| ServoState {
+                id: i as u8 + 7,
+                name: format!("wheel_{i}"),
+                position_rad: 0.0,
+                velocity_rad_s: 0.0,
+                load: 0.0,
+                temperature_c: *temperature_c,
+                voltage_v: 12.0,
+                error_flags: if i == 0 { error_flags } else { 0 },
+            })
+            .collect();
+        ServoStateArray {
+            timestamp_ns: 0,
+            servos,
+        }
+    }
+
+    fn power(at_s: u64, battery_voltage_v: f32, low_battery: bool) -> PowerState {
+        PowerState {
+            timestamp_ns: at_s * SECOND_NS,
+            battery_voltage_v,
+            battery_percent: 0.0,
+            low_battery,
+        }
+    }
+
+    #[test]
+    fn cool_servos_run_at_full_speed() {
+        let mut supervisor = supervisor();
+        supervisor.update_servos(servos(&[40.0, 45.0, 50.0], 0));
+        let assessment = supervisor.assess();
+        assert_eq!(assessment.scale, 1.0);
+        assert!(assessment.stop.is_none());
+        assert!(assessment.warnings.is_empty());
+    }
+
+    #[test]
+    fn derates_linearly_toward_the_temperature_limit() {
+        let mut supervisor = supervisor();
+        supervisor.update_servos(servos(&[40.0, 65.0, 50.0], 0));
+        let assessment = supervisor.assess();
+        assert!((assessment.scale - 0.5).abs() < 1e-6);
+        assert!(assessment.stop.is_none());
+        assert!(assessment.warnings[0].starts_with("servo_temp_derate: wheel_1"));
+    }
+
+    #[test]
+    fn stops_at_the_temperature_limit() {
+        let mut supervisor = supervisor();
+        supervisor.update_servos(servos(&[40.0, 70.0, 50.0], 0));
+        let stop = supervisor.assess().stop.unwrap();
+        assert!(stop.starts_with("servo_overtemp: wheel_1"), "{stop}");
+    }
+
+    #[test]
+    fn error_flags_stop_until_they_clear() {
+        let mut supervisor = supervisor();
+        supervisor.update_servos(servos(&[40.0; 3], error_bits::OVERLOAD as u32));
+        let stop = supervisor.assess().stop.unwrap();
+        assert_eq!(stop, "servo_fault: wheel_0 overload");
+
+        supervisor.update_servos(servos(&[40.0; 3], 0));
+        assert!(supervisor.assess().stop.is_none());
+    }
+
+    #[test]
+    fn brief_low_battery_does_not_stop() {
+        let mut supervisor = supervisor();
+        supervisor.update_power(&power(10, 10.5, true));
+        supervisor.update_power(&power(12, 10.4, true));
+        assert!(supervisor.assess().stop.is_none());
+        // Recovering restarts the streak.
+        supervisor.update_power(&power(13, 11.5, false));
+        supervisor.update_power(&power(14, 10.5, true));
+        supervisor.update_power(&power(16, 10.5, true));
+        assert!(supervisor.assess().stop.is_none());
+    }
+
+    #[test]
+    fn sustained_low_battery_latches_until_reset_after_recovery() {
+        let mut supervisor = supervisor();
+        supervisor.update_power(&power(10, 10.5, true));
+        supervisor.update_power(&power(13, 10.4, true));
+        assert_eq!(supervisor.assess().stop.unwrap(), "low_battery: 10.4 V");
+
+        // Unloaded, the pack reads fine again, but the stop holds.
+        supervisor.update_power(&power(14, 11.6, false));
+        assert!(supervisor.assess().stop.is_some());
+
+        supervisor.update_power(&power(15, 10.5, true));
+        supervisor.reset();
+        assert!(supervisor.assess().stop.is_some(), "reset while still low");
+
+        supervisor.update_power(&power(16, 11.6, false));
+        supervisor.reset();
+        assert!(supervisor.assess().stop.is_none());
+    }
+
+    #[test]
+    fn low_battery_stop_can_be_disabled() {
+        let mut supervisor = supervisor();
+        supervisor.config.low_battery_stop = false;
+        supervisor.update_power(&power(10, 10.5, true));
+        supervisor.update_power(&power(20, 10.4, true));
+        assert!(supervisor.assess().stop.is_none());
+    }
+}
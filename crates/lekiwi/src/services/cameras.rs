@@ -1,14 +1,24 @@
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
+use foxglove::bytes::Bytes;
 use tokio::sync::watch;
 
 use crate::bus::Bus;
+use crate::camera::stats::FrameStats;
+use crate::camera::{self, CameraSource};
 use crate::config::{CameraConfig, CamerasConfig};
 use crate::messages::CameraFrame;
 use crate::telemetry::Telemetry;
-use crate::utils::now_nanos;
+
+/// How long a capture thread waits for a frame before checking shutdown.
+const FRAME_WAIT: Duration = Duration::from_millis(200);
+/// A streaming camera that delivers nothing for this long is reopened.
+const STALL_TIMEOUT: Duration = Duration::from_secs(3);
+const REOPEN_BACKOFF_MIN: Duration = Duration::from_millis(500);
+const REOPEN_BACKOFF_MAX: Duration = Duration::from_secs(10);
+const STATS_PERIOD: Duration = Duration::from_secs(1);
 
 pub async fn run(
     bus: Arc<Bus>,
@@ -20,9 +30,10 @@ pub async fn run(
     for camera in cameras.cameras {
         let bus = bus.clone();
         let telemetry = telemetry.clone();
-        let mut shutdown = shutdown.clone();
-        handles.push(tokio::spawn(async move {
-            run_camera(camera, bus, telemetry, &mut shutdown).await
+        let shutdown = shutdown.clone();
+        // Capture blocks in poll/ioctl, so each camera gets its own thread.
+        handles.push(tokio::task::spawn_blocking(move || {
+            run_camera(camera, &bus, &telemetry, &shutdown)
         }));
     }
 
@@ -33,35 +44,94 @@ pub async fn run(
     Ok(())
 }
 
-async fn run_camera(
+/// Captures until shutdown. A lost or stalled device is dropped and the
+/// configured path reopened with backoff; `/dev/v4l/by-id` links come back
+/// under the same name when a USB camera is replugged.
+fn run_camera(
     camera: CameraConfig,
-    bus: Arc<Bus>,
-    telemetry: Arc<Telemetry>,
-    shutdown: &mut watch::Receiver<bool>,
-) -> Result<()> {
-    let fps = camera.fps.max(1) as f64;
-    let mut interval = tokio::time::interval(Duration::from_secs_f64(1.0 / fps));
+    bus: &Bus,
+    telemetry: &Telemetry,
+    shutdown: &watch::Receiver<bool>,
+) {
+    let frame_id = camera.frame_id();
+    let mut stats = FrameStats::new(&camera.name, camera.fps);
+    let mut source: Option<Box<dyn CameraSource>> = None;
+    let mut last_error: Option<String> = None;
+    let mut backoff = REOPEN_BACKOFF_MIN;
+    let mut reopen_at = Instant::now();
+    let mut next_report = Instant::now() + STATS_PERIOD;
+
+    while !*shutdown.borrow() {
+        if Instant::now() >= next_report {
+            next_report = Instant::now() + STATS_PERIOD;
+            let report = stats.report(source.is_some(), last_error.clone());
+            telemetry.log_camera_stats(&report);
+            let _ = bus.camera_stats.send(report);
+        }
 
-    loop {
-        tokio::select! {
-            _ = interval.tick() => {
+        let Some(active) = source.as_mut() else {
+            let wait = reopen_at.saturating_duration_since(Instant::now());
+            if !wait.is_zero() {
+                std::thread::sleep(wait.min(FRAME_WAIT));
+                continue;
+            }
+            match camera::open(&camera) {
+                Ok(opened) => {
+                    tracing::info!(
+                        "Camera {} capturing from {}",
+                        camera.name,
+                        opened.describe()
+                    );
+                    stats.opened();
+                    source = Some(opened);
+                }
+                Err(err) => {
+                    // Warn once per outage; retries stay at debug.
+                    if last_error.is_none() {
+                        tracing::warn!("Camera {} unavailable: {err:#}", camera.name);
+                    } else {
+                        tracing::debug!("Camera {} still unavailable: {err:#}", camera.name);
+                    }
+                    last_error = Some(format!("{err:#}"));
+                    reopen_at = Instant::now() + backoff;
+                    backoff = (backoff * 2).min(REOPEN_BACKOFF_MAX);
+                }
+            }
+            continue;
+        };
+
+        let lost = match active.next_frame(FRAME_WAIT) {
+            Ok(Some(captured)) => {
+                stats.record(&captured);
+                if last_error.take().is_some() {
+                    tracing::info!("Camera {} recovered", camera.name);
+                }
+                backoff = REOPEN_BACKOFF_MIN;
                 let frame = CameraFrame {
-                    timestamp_ns: now_nanos(),
+                    timestamp_ns: captured.timestamp_ns,
                     camera_name: camera.name.clone(),
-                    frame_id: format!("camera_{}", camera.name),
-                    width: camera.width,
-                    height: camera.height,
-                    encoding: camera.format.clone(),
-                    data_base64: String::new(),
+                    frame_id: frame_id.clone(),
+                    width: captured.width,
+                    height: captured.height,
+                    encoding: captured.encoding,
+                    data: Bytes::from(captured.data),
                 };
                 telemetry.log_camera_frame(&frame);
                 let _ = bus.camera.send(frame);
+                None
             }
-            _ = shutdown.changed() => {
-                break;
-            }
+            Ok(None) => stats
+                .idle_for()
+                .filter(|idle| *idle >= STALL_TIMEOUT)
+                .map(|idle| format!("no frames for {:.1} s", idle.as_secs_f32())),
+            Err(err) => Some(format!("{err:#}")),
+        };
+        if let Some(reason) = lost {
+            tracing::warn!("Camera {} lost: {reason}; reopening", camera.name);
+            source = None;
+            last_error = Some(reason);
+            reopen_at = Instant::now() + backoff;
+            backoff = (backoff * 2).min(REOPEN_BACKOFF_MAX);
         }
     }
-
-    Ok(())
 }
@@ -1,21 +1,46 @@
-use std::sync::Arc;
+mod teleop;
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use anyhow::Result;
-use foxglove::websocket::{Capability, Client, ClientChannel, ServerListener};
+use foxglove::websocket::{
+    Capability, ChannelView, Client, ClientChannel, ClientId, ServerListener,
+};
+use tokio::runtime::Handle;
 use tokio::sync::watch;
 
 use crate::bus::Bus;
-use crate::messages::{EstopCommand, LogControl, LogStatus, SkillCommand, VelocityCommand};
+use crate::config::TeleopConfig;
+use crate::messages::{
+    EstopCommand, EstopReset, Imu, LogControl, LogStatus, SetPoseCommand, SkillCancel,
+    SkillCommand, SpeedScaleCommand, VelocityCommand,
+};
 use crate::telemetry::{
-    Telemetry, TOPIC_CMD_ESTOP, TOPIC_CMD_SKILL, TOPIC_CMD_VELOCITY, TOPIC_LOG_CONTROL,
+    Telemetry, TOPIC_CMD_ESTOP, TOPIC_CMD_ESTOP_RESET, TOPIC_CMD_SET_POSE, TOPIC_CMD_SKILL,
+    TOPIC_CMD_SKILL_CANCEL, TOPIC_CMD_SPEED_SCALE, TOPIC_CMD_VELOCITY, TOPIC_LOG_CONTROL,
+    TOPIC_SENSORS_IMU,
 };
 use crate::utils::now_nanos;
 
+use self::teleop::TeleopGate;
+
+/// How long after a disconnect the departed clients are swept; the server
+/// still holds the connection while it reports the disconnect.
+const DISCONNECT_SWEEP_DELAY: Duration = Duration::from_millis(100);
+
+/// Clients whose e-stop hold is engaged, with their connection so the hold
+/// can be released when they leave.
+type EstopHolders = Arc<Mutex<HashMap<ClientId, Client>>>;
+
 #[derive(Debug, Clone)]
 pub struct FoxgloveConfig {
     pub host: String,
     pub port: u16,
     pub name: String,
+    pub teleop: TeleopConfig,
 }
 
 pub async fn run(
@@ -25,7 +50,13 @@ pub async fn run(
     telemetry: Arc<Telemetry>,
     mut shutdown: watch::Receiver<bool>,
 ) -> Result<()> {
-    let listener = Arc::new(FoxgloveListener { bus, telemetry });
+    let listener = Arc::new(FoxgloveListener {
+        bus,
+        telemetry,
+        teleop: Arc::new(TeleopGate::new(config.teleop)),
+        estop_holders: EstopHolders::default(),
+        runtime: Handle::current(),
+    });
     let server = ctx
         .websocket_server()
         .name(config.name)
@@ -46,10 +77,13 @@ pub async fn run(
 struct FoxgloveListener {
     bus: Arc<Bus>,
     telemetry: Arc<Telemetry>,
+    teleop: Arc<TeleopGate>,
+    estop_holders: EstopHolders,
+    runtime: Handle,
 }
 
 impl ServerListener for FoxgloveListener {
-    fn on_message_data(&self, _client: Client, channel: &ClientChannel, payload: &[u8]) {
+    fn on_message_data(&self, client: Client, channel: &ClientChannel, payload: &[u8]) {
         if channel.encoding != "json" {
             tracing::warn!(
                 "Unsupported client encoding '{}' on {}",
@@ -62,6 +96,15 @@ impl ServerListener for FoxgloveListener {
         match channel.topic.as_str() {
             TOPIC_CMD_VELOCITY => match serde_json::from_slice::<VelocityCommand>(payload) {
                 Ok(mut cmd) => {
+                    // Self-declared: it picks the arbitration slot but is not
+                    // verified, and the gate rejects the reserved skill slot.
+                    if cmd.source.is_empty() {
+                        cmd.source = "foxglove".to_string();
+                    }
+                    if !self.teleop.admit(&client, &cmd) {
+                        return;
+                    }
+                    // Only reachable unstamped with max_command_age_ms off.
                     if cmd.timestamp_ns == 0 {
                         cmd.timestamp_ns = now_nanos();
                     }
@@ -79,15 +122,69 @@ impl ServerListener for FoxgloveListener {
                 }
                 Err(err) => tracing::warn!("Invalid /cmd/skill payload: {err}"),
             },
+            TOPIC_CMD_SKILL_CANCEL => match serde_json::from_slice::<SkillCancel>(payload) {
+                Ok(mut cmd) => {
+                    if cmd.timestamp_ns == 0 {
+                        cmd.timestamp_ns = now_nanos();
+                    }
+                    self.telemetry.log_cmd_skill_cancel(&cmd);
+                    let _ = self.bus.cmd_skill_cancel.send(cmd);
+                }
+                Err(err) => tracing::warn!("Invalid /cmd/skill_cancel payload: {err}"),
+            },
             TOPIC_CMD_ESTOP => match serde_json::from_slice::<EstopCommand>(payload) {
                 Ok(mut cmd) => {
                     if cmd.timestamp_ns == 0 {
                         cmd.timestamp_ns = now_nanos();
                     }
+                    cmd.source = client_source(client.id());
+                    let mut holders = self.estop_holders.lock().unwrap();
+                    if cmd.enabled {
+                        holders.insert(client.id(), client.clone());
+                    } else {
+                        holders.remove(&client.id());
+                    }
                     let _ = self.bus.cmd_estop.send(cmd);
                 }
                 Err(err) => tracing::warn!("Invalid /cmd/estop payload: {err}"),
             },
+            TOPIC_CMD_ESTOP_RESET => match serde_json::from_slice::<EstopReset>(payload) {
+                Ok(mut cmd) => {
+                    if cmd.timestamp_ns == 0 {
+                        cmd.timestamp_ns = now_nanos();
+                    }
+                    cmd.source = client_source(client.id());
+                    self.telemetry.log_cmd_estop_reset(&cmd);
+                    let _ = self.bus.cmd_estop_reset.send(cmd);
+                }
+                Err(err) => tracing::warn!("Invalid /cmd/estop_reset payload: {err}"),
+            },
+            TOPIC_CMD_SPEED_SCALE => match serde_json::from_slice::<SpeedScaleCommand>(payload) {
+                Ok(mut cmd) => {
+                    if cmd.timestamp_ns == 0 {
+                        cmd.timestamp_ns = now_nanos();
+                    }
+                    if cmd.source.is_empty() {
+                        cmd.source = "foxglove".to_string();
+                    }
+                    self.telemetry.log_cmd_speed_scale(&cmd);
+                    let _ = self.bus.cmd_speed_scale.send(cmd);
+                }
+                Err(err) => tracing::warn!("Invalid /cmd/speed_scale payload: {err}"),
+            },
+            TOPIC_CMD_SET_POSE => match serde_json::from_slice::<SetPoseCommand>(payload) {
+                Ok(mut cmd) => {
+                    if cmd.timestamp_ns == 0 {
+                        cmd.timestamp_ns = now_nanos();
+                    }
+                    if cmd.source.is_empty() {
+                        cmd.source = "foxglove".to_string();
+                    }
+                    self.telemetry.log_cmd_set_pose(&cmd);
+                    let _ = self.bus.cmd_set_pose.send(cmd);
+                }
+                Err(err) => tracing::warn!("Invalid /cmd/set_pose payload: {err}"),
+            },
             TOPIC_LOG_CONTROL => match serde_json::from_slice::<LogControl>(payload) {
                 Ok(mut cmd) => {
                     if cmd.timestamp_ns == 0 {
@@ -98,6 +195,16 @@ impl ServerListener for FoxgloveListener {
                 }
                 Err(err) => tracing::warn!("Invalid /log/control payload: {err}"),
             },
+            TOPIC_SENSORS_IMU => match serde_json::from_slice::<Imu>(payload) {
+                Ok(mut imu) => {
+                    if imu.timestamp_ns == 0 {
+                        imu.timestamp_ns = now_nanos();
+                    }
+                    self.telemetry.log_imu(&imu);
+                    let _ = self.bus.imu.send(imu);
+                }
+                Err(err) => tracing::warn!("Invalid /sensors/imu payload: {err}"),
+            },
             _ => {
                 tracing::debug!("Ignoring client message on {}", channel.topic);
             }
@@ -112,6 +219,53 @@ impl ServerListener for FoxgloveListener {
         );
     }
 
+    fn on_client_unadvertise(&self, client: Client, channel: &ClientChannel) {
+        if channel.topic == TOPIC_CMD_VELOCITY {
+            let sources = self.teleop.release(client.id());
+            zero_sources(&self.bus, client.id(), sources, "stopped publishing");
+        }
+    }
+
+    fn on_subscribe(&self, client: Client, _channel: ChannelView) {
+        self.teleop.subscribed(client.id());
+    }
+
+    /// Also called for every open subscription when a client disconnects.
+    fn on_unsubscribe(&self, client: Client, _channel: ChannelView) {
+        let Some(sources) = self.teleop.unsubscribed(client.id()) else {
+            return;
+        };
+        zero_sources(&self.bus, client.id(), sources, "disconnected");
+        if self.estop_holders.lock().unwrap().remove(&client.id()).is_some() {
+            release_estop(&self.bus, client.id());
+        }
+    }
+
+    /// Does not name the client, so the sweep looks for connections that are
+    /// gone; this catches clients that only publish and never subscribed.
+    fn on_client_disconnect(&self) {
+        let bus = self.bus.clone();
+        let teleop = self.teleop.clone();
+        let holders = self.estop_holders.clone();
+        self.runtime.spawn(async move {
+            tokio::time::sleep(DISCONNECT_SWEEP_DELAY).await;
+            for (client, sources) in teleop.departed() {
+                zero_sources(&bus, client, sources, "disconnected");
+            }
+            let mut departed = Vec::new();
+            holders.lock().unwrap().retain(|id, client| {
+                let connected = client.sink_id().is_some();
+                if !connected {
+                    departed.push(*id);
+                }
+                connected
+            });
+            for client in departed {
+                release_estop(&bus, client);
+            }
+        });
+    }
+
     fn on_client_connect(&self) {
         let status = LogStatus {
             timestamp_ns: now_nanos(),
@@ -124,6 +278,31 @@ impl ServerListener for FoxgloveListener {
     }
 }
 
+/// E-stop holds and resets belong to the connection, not to whatever
+/// `source` the payload claims, so one client cannot release another's hold.
+fn client_source(client: ClientId) -> String {
+    format!("foxglove:{client}")
+}
+
+/// Releases the e-stop hold of a client that left while holding it.
+fn release_estop(bus: &Bus, client: ClientId) {
+    let _ = bus.cmd_estop.send(EstopCommand {
+        timestamp_ns: now_nanos(),
+        enabled: false,
+        reason: "client disconnected".to_string(),
+        source: client_source(client),
+    });
+}
+
+/// Replaces the last command of each source a departing client drove with
+/// zero, instead of letting it run until the lease expires.
+fn zero_sources(bus: &Bus, client: impl Display, sources: Vec<String>, event: &str) {
+    for source in sources {
+        tracing::warn!("Client {client} {event} while driving as '{source}'; zeroing velocity");
+        let _ = bus.cmd_in.send(VelocityCommand::zero(source, now_nanos()));
+    }
+}
+
 async fn wait_for_shutdown(shutdown: &mut watch::Receiver<bool>) {
     loop {
         if *shutdown.borrow() {
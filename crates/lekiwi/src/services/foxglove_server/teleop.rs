@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use foxglove::websocket::{Client, ClientId};
+
+use crate::config::TeleopConfig;
+use crate::messages::VelocityCommand;
+use crate::services::behavior_router::{source_matches, SKILL_SOURCE};
+use crate::utils::now_nanos;
+
+/// Raw client id; the gate keys on it so its checks run without a server.
+type ClientKey = u32;
+
+#[derive(Default)]
+struct TeleopClient {
+    last_seq: Option<u64>,
+    /// Set while commands are being rejected, so the warning is logged once.
+    rejecting: bool,
+    /// The connection, which stops resolving once the client is gone.
+    handle: Option<Client>,
+}
+
+#[derive(Default)]
+struct GateState {
+    clients: HashMap<ClientKey, TeleopClient>,
+    /// Open subscriptions per client. The server drops them all when a
+    /// client disconnects, which names the client right away.
+    subscriptions: HashMap<ClientKey, usize>,
+    /// Client that sent the last accepted command for each source.
+    owners: HashMap<String, ClientKey>,
+}
+
+/// Per-client dead-man checks on /cmd/velocity. Each client must send a
+/// fresh timestamp and an increasing `seq`, so a frozen tab replaying its
+/// last command is dropped and the source's lease runs out. Tracks which
+/// client owns each source so its command can be zeroed when it leaves:
+/// right away when its last subscription goes, or on the sweep after a
+/// disconnect for clients that only publish.
+pub struct TeleopGate {
+    config: TeleopConfig,
+    state: Mutex<GateState>,
+}
+
+impl TeleopGate {
+    pub fn new(config: TeleopConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(GateState::default()),
+        }
+    }
+
+    /// Returns whether `cmd` from `client` may be forwarded. Expects the
+    /// command exactly as the client sent it.
+    pub fn admit(&self, client: &Client, cmd: &VelocityCommand) -> bool {
+        self.admit_key(client.id().into(), Some(client), cmd)
+    }
+
+    fn admit_key(&self, key: ClientKey, handle: Option<&Client>, cmd: &VelocityCommand) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let entry = state.clients.entry(key).or_default();
+        if entry.handle.is_none() {
+            entry.handle = handle.cloned();
+        }
+
+        if let Some(reason) = self.rejection(entry.last_seq, cmd) {
+            if !entry.rejecting {
+                tracing::warn!(
+                    "Rejecting /cmd/velocity from client {key} ({}): {reason}",
+                    cmd.source
+                );
+                entry.rejecting = true;
+            }
+            return false;
+        }
+
+        if entry.rejecting {
+            tracing::info!("Accepting /cmd/velocity from client {key} again");
+            entry.rejecting = false;
+        }
+        entry.last_seq = cmd.seq.or(entry.last_seq);
+        state.owners.insert(cmd.source.clone(), key);
+        true
+    }
+
+    /// Forgets that `client` drives anything and returns the sources it owned.
+    pub fn release(&self, client: ClientId) -> Vec<String> {
+        let mut state = self.state.lock().unwrap();
+        release_owned(&mut state, client.into())
+    }
+
+    pub fn subscribed(&self, client: ClientId) {
+        let mut state = self.state.lock().unwrap();
+        *state.subscriptions.entry(client.into()).or_default() += 1;
+    }
+
+    /// Counts down `client`'s subscriptions. When the last one goes, the
+    /// client has disconnected (or stopped listening), so its gate state is
+    /// dropped and the sources it owned are returned.
+    pub fn unsubscribed(&self, client: ClientId) -> Option<Vec<String>> {
+        let key = client.into();
+        let mut state = self.state.lock().unwrap();
+        let count = state.subscriptions.get_mut(&key)?;
+        *count = count.saturating_sub(1);
+        if *count > 0 {
+            return None;
+        }
+        state.subscriptions.remove(&key);
+        state.clients.remove(&key);
+        Some(release_owned(&mut state, key))
+    }
+
+    /// Drops every client whose connection is gone and returns the sources
+    /// each one owned.
+    pub fn departed(&self) -> Vec<(ClientKey, Vec<String>)> {
+        let mut state = self.state.lock().unwrap();
+        let gone: Vec<ClientKey> = state
+            .clients
+            .iter()
+            .filter(|(_, client)| {
+                client
+                    .handle
+                    .as_ref()
+                    .is_some_and(|handle| handle.sink_id().is_none())
+            })
+            .map(|(key, _)| *key)
+            .collect();
+        gone.into_iter()
+            .map(|key| {
+                state.clients.remove(&key);
+                state.subscriptions.remove(&key);
+                (key, release_owned(&mut state, key))
+            })
+            .collect()
+    }
+
+    fn rejection(&self, last_seq: Option<u64>, cmd: &VelocityCommand) -> Option<String> {
+        // The skill slot belongs to the executor, which also clears it.
+        if source_matches(SKILL_SOURCE, &cmd.source) {
+            return Some(format!("source '{}' is reserved for skills", cmd.source));
+        }
+        if let Some(max_age_ms) = self.config.max_command_age_ms {
+            // An unstamped command cannot prove it is fresh.
+            if cmd.timestamp_ns == 0 {
+                return Some("missing timestamp_ns".to_string());
+            }
+            let age_ms = now_nanos().saturating_sub(cmd.timestamp_ns) / 1_000_000;
+            if age_ms > max_age_ms {
+                return Some(format!(
+                    "timestamp_ns is {age_ms} ms old (max {max_age_ms} ms)"
+                ));
+            }
+        }
+        match (cmd.seq, last_seq) {
+            (None, _) if self.config.require_seq => Some("missing seq".to_string()),
+            (Some(seq), Some(last)) if seq <= last => {
+                Some(format!("seq {seq} does not follow {last}"))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn release_owned(state: &mut GateState, client: ClientKey) -> Vec<String> {
+    let owned: Vec<String> = state
+        .owners
+        .iter()
+        .filter(|(_, owner)| **owner == client)
+        .map(|(source, _)| source.clone())
+        .collect();
+    for source in &owned {
+        state.owners.remove(source);
+    }
+    owned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS_NS: u64 = 1_000_000;
+
+    fn gate() -> TeleopGate {
+        TeleopGate::new(TeleopConfig::default())
+    }
+
+    fn command(source: &str, seq: Option<u64>, age_ms: u64) -> VelocityCommand {
+        VelocityCommand {
+            vx_m_s: 0.2,
+            seq,
+            ..VelocityCommand::zero(source, now_nanos() - age_ms * MS_NS)
+        }
+    }
+
+    #[test]
+    fn admits_fresh_commands_with_increasing_seq() {
+        let gate = gate();
+        for seq in [1, 2, 5] {
+            assert!(gate.admit_key(1, None, &command("foxglove", Some(seq), 0)));
+        }
+    }
+
+    #[test]
+    fn rejects_repeated_and_out_of_order_seq() {
+        let gate = gate();
+        assert!(gate.admit_key(1, None, &command("foxglove", Some(5), 0)));
+        assert!(!gate.admit_key(1, None, &command("foxglove", Some(5), 0)));
+        assert!(!gate.admit_key(1, None, &command("foxglove", Some(3), 0)));
+        assert!(gate.admit_key(1, None, &command("foxglove", Some(6), 0)));
+    }
+
+    #[test]
+    fn tracks_seq_per_client() {
+        let gate = gate();
+        assert!(gate.admit_key(1, None, &command("foxglove", Some(10), 0)));
+        assert!(gate.admit_key(2, None, &command("foxglove", Some(1), 0)));
+    }
+
+    #[test]
+    fn rejects_missing_seq_only_when_required() {
+        assert!(!gate().admit_key(1, None, &command("foxglove", None, 0)));
+        let lax = TeleopGate::new(TeleopConfig {
+            require_seq: false,
+            ..TeleopConfig::default()
+        });
+        assert!(lax.admit_key(1, None, &command("foxglove", None, 0)));
+        assert!(lax.admit_key(1, None, &command("foxglove", None, 0)));
+    }
+
+    #[test]
+    fn rejects_stale_timestamps() {
+        let gate = gate();
+        assert!(!gate.admit_key(1, None, &command("foxglove", Some(1), 800)));
+        // A rejected command does not consume its seq.
+        assert!(gate.admit_key(1, None, &command("foxglove", Some(1), 100)));
+    }
+
+    #[test]
+    fn rejects_unstamped_commands_unless_age_checks_are_off() {
+        let mut unstamped = command("foxglove", Some(1), 0);
+        unstamped.timestamp_ns = 0;
+        assert!(!gate().admit_key(1, None, &unstamped));
+        let unchecked = TeleopGate::new(TeleopConfig {
+            max_command_age_ms: None,
+            ..TeleopConfig::default()
+        });
+        assert!(unchecked.admit_key(1, None, &unstamped));
+    }
+
+    #[test]
+    fn rejects_the_reserved_skill_source() {
+        let gate = gate();
+        assert!(!gate.admit_key(1, None, &command("skill", Some(1), 0)));
+        assert!(!gate.admit_key(1, None, &command("skill:goto_pose", Some(2), 0)));
+        assert!(gate.admit_key(1, None, &command("skills", Some(3), 0)));
+    }
+
+    #[test]
+    fn releases_only_the_sources_a_client_drove_last() {
+        let gate = gate();
+        gate.admit_key(1, None, &command("foxglove", Some(1), 0));
+        gate.admit_key(1, None, &command("script:square", Some(2), 0));
+        gate.admit_key(2, None, &command("foxglove", Some(1), 0));
+
+        let mut state = gate.state.lock().unwrap();
+        assert_eq!(release_owned(&mut state, 1), ["script:square"]);
+        assert!(release_owned(&mut state, 1).is_empty());
+        assert_eq!(release_owned(&mut state, 2), ["foxglove"]);
+    }
+}
@@ -1,23 +1,335 @@
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{bail, ensure, Result};
 use tokio::sync::watch;
 
 use crate::bus::Bus;
+use crate::config::{DriveConfig, WheelMount};
+use crate::messages::{VelocityCommand, WheelTarget, WheelTargetArray};
+use crate::telemetry::Telemetry;
+use crate::utils::now_nanos;
+
+pub async fn run(
+    bus: Arc<Bus>,
+    telemetry: Arc<Telemetry>,
+    drive: DriveConfig,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let kinematics = KiwiKinematics::new(&drive)?;
+    let mut cmd_rx = bus.cmd_out.subscribe();
+
+    loop {
+        tokio::select! {
+            Ok(cmd) = cmd_rx.recv() => {
+                let targets = kinematics.wheel_targets(&Twist::from_command(&cmd), now_nanos());
+                telemetry.log_wheel_targets(&targets);
+                let _ = bus.wheel_targets.send(targets);
+            }
+            _ = shutdown.changed() => {
+                break;
+            }
+        }
+    }
 
-pub async fn run(_bus: Arc<Bus>, mut shutdown: watch::Receiver<bool>) -> Result<()> {
-    tracing::info!("Kinematics service is a placeholder in this build");
-    wait_for_shutdown(&mut shutdown).await;
     Ok(())
 }
 
-async fn wait_for_shutdown(shutdown: &mut watch::Receiver<bool>) {
-    loop {
-        if *shutdown.borrow() {
-            break;
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Twist {
+    pub vx_m_s: f64,
+    pub vy_m_s: f64,
+    pub omega_rad_s: f64,
+}
+
+impl Twist {
+    pub fn from_command(cmd: &VelocityCommand) -> Self {
+        Self {
+            vx_m_s: cmd.vx_m_s as f64,
+            vy_m_s: cmd.vy_m_s as f64,
+            omega_rad_s: cmd.omega_rad_s as f64,
+        }
+    }
+}
+
+/// Wheel Jacobian for an omni base. Row `i` maps a body twist to the angular
+/// speed of servo `i` (direction applied), and the forward map is the
+/// least-squares pseudo-inverse so more than three wheels also work.
+#[derive(Debug, Clone)]
+pub struct KiwiKinematics {
+    mounts: Vec<WheelMount>,
+    max_wheel_speed_rad_s: f64,
+    jacobian: Vec<[f64; 3]>,
+    pseudo_inverse: [Vec<f64>; 3],
+}
+
+impl KiwiKinematics {
+    pub fn new(drive: &DriveConfig) -> Result<Self> {
+        ensure!(
+            drive.wheel_mounts.len() >= 3,
+            "kiwi drive needs at least 3 wheels, got {}",
+            drive.wheel_mounts.len()
+        );
+        ensure!(
+            drive.wheel_radius_m > 0.0,
+            "wheel_radius_m must be positive"
+        );
+        ensure!(
+            drive.max_wheel_speed_rad_s > 0.0,
+            "max_wheel_speed_rad_s must be positive"
+        );
+
+        let wheel_radius = drive.wheel_radius_m as f64;
+        let base_radius = drive.wheel_distance_m as f64;
+        let jacobian: Vec<[f64; 3]> = drive
+            .wheel_mounts
+            .iter()
+            .map(|mount| {
+                let angle = (mount.angle_deg as f64).to_radians();
+                let scale = mount.direction as f64 / wheel_radius;
+                [
+                    -angle.sin() * scale,
+                    angle.cos() * scale,
+                    base_radius * scale,
+                ]
+            })
+            .collect();
+
+        let mut jtj = [[0.0f64; 3]; 3];
+        for row in &jacobian {
+            for (r, jtj_row) in jtj.iter_mut().enumerate() {
+                for (c, value) in jtj_row.iter_mut().enumerate() {
+                    *value += row[r] * row[c];
+                }
+            }
         }
-        if shutdown.changed().await.is_err() {
-            break;
+        let Some(jtj_inv) = invert3(&jtj) else {
+            bail!("wheel mounts do not span vx, vy and omega");
+        };
+
+        let pseudo_inverse = std::array::from_fn(|r| {
+            jacobian
+                .iter()
+                .map(|row| (0..3).map(|k| jtj_inv[r][k] * row[k]).sum())
+                .collect()
+        });
+
+        Ok(Self {
+            mounts: drive.wheel_mounts.clone(),
+            max_wheel_speed_rad_s: drive.max_wheel_speed_rad_s as f64,
+            jacobian,
+            pseudo_inverse,
+        })
+    }
+
+    /// Body twist to servo angular speeds (rad/s), in `wheel_mounts` order.
+    pub fn inverse(&self, twist: &Twist) -> Vec<f64> {
+        self.jacobian
+            .iter()
+            .map(|row| row[0] * twist.vx_m_s + row[1] * twist.vy_m_s + row[2] * twist.omega_rad_s)
+            .collect()
+    }
+
+    /// Servo angular speeds (rad/s), in `wheel_mounts` order, to body twist.
+    pub fn forward(&self, wheel_speeds: &[f64]) -> Twist {
+        let project = |row: &Vec<f64>| -> f64 {
+            row.iter()
+                .zip(wheel_speeds)
+                .map(|(gain, speed)| gain * speed)
+                .sum()
+        };
+        Twist {
+            vx_m_s: project(&self.pseudo_inverse[0]),
+            vy_m_s: project(&self.pseudo_inverse[1]),
+            omega_rad_s: project(&self.pseudo_inverse[2]),
+        }
+    }
+
+    /// Scales the whole twist by one factor so the fastest wheel sits at the
+    /// limit. Clamping wheels individually would bend the motion direction.
+    pub fn saturate(&self, twist: &Twist) -> (Twist, f64) {
+        let peak = self
+            .inverse(twist)
+            .iter()
+            .fold(0.0f64, |peak, speed| peak.max(speed.abs()));
+        if peak <= self.max_wheel_speed_rad_s {
+            return (*twist, 1.0);
         }
+        let scale = self.max_wheel_speed_rad_s / peak;
+        let scaled = Twist {
+            vx_m_s: twist.vx_m_s * scale,
+            vy_m_s: twist.vy_m_s * scale,
+            omega_rad_s: twist.omega_rad_s * scale,
+        };
+        (scaled, scale)
+    }
+
+    pub fn wheel_targets(&self, twist: &Twist, timestamp_ns: u64) -> WheelTargetArray {
+        let (twist, scale) = self.saturate(twist);
+        let wheels = self
+            .mounts
+            .iter()
+            .zip(self.inverse(&twist))
+            .map(|(mount, velocity)| WheelTarget {
+                name: mount.name.clone(),
+                servo_id: mount.servo_id,
+                velocity_rad_s: velocity as f32,
+            })
+            .collect();
+        WheelTargetArray {
+            timestamp_ns,
+            wheels,
+            saturation_scale: scale as f32,
+        }
+    }
+}
+
+fn invert3(m: &[[f64; 3]; 3]) -> Option<[[f64; 3]; 3]> {
+    let det = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    Some([
+        [
+            (m[1][1] * m[2][2] - m[1][2] * m[2][1]) * inv_det,
+            (m[0][2] * m[2][1] - m[0][1] * m[2][2]) * inv_det,
+            (m[0][1] * m[1][2] - m[0][2] * m[1][1]) * inv_det,
+        ],
+        [
+            (m[1][2] * m[2][0] - m[1][0] * m[2][2]) * inv_det,
+            (m[0][0] * m[2][2] - m[0][2] * m[2][0]) * inv_det,
+            (m[0][2] * m[1][0] - m[0][0] * m[1][2]) * inv_det,
+        ],
+        [
+            (m[1][0] * m[2][1] - m[1][1] * m[2][0]) * inv_det,
+            (m[0][1] * m[2][0] - m[0][0] * m[2][1]) * inv_det,
+            (m[0][0] * m[1][1] - m[0][1] * m[1][0]) * inv_det,
+        ],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::StallConfig;
+
+    fn drive(angles_deg: &[f32], directions: &[i8]) -> DriveConfig {
+        DriveConfig {
+            wheel_radius_m: 0.05,
+            wheel_distance_m: 0.125,
+            max_wheel_speed_rad_s: 5.0,
+            wheel_mounts: angles_deg
+                .iter()
+                .zip(directions)
+                .enumerate()
+                .map(|(i, (angle_deg, direction))| WheelMount {
+                    name: format!("wheel_{i}"),
+                    angle_deg: *angle_deg,
+                    servo_id: i as u8 + 1,
+                    direction: *direction,
+                    stall: StallConfig::default(),
+                })
+                .collect(),
+        }
+    }
+
+    fn assert_twist_eq(actual: Twist, expected: Twist) {
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+        assert!(
+            close(actual.vx_m_s, expected.vx_m_s)
+                && close(actual.vy_m_s, expected.vy_m_s)
+                && close(actual.omega_rad_s, expected.omega_rad_s),
+            "{actual:?} != {expected:?}"
+        );
+    }
+
+    fn twists() -> [Twist; 5] {
+        [
+            Twist::default(),
+            Twist {
+                vx_m_s: 0.2,
+                ..Twist::default()
+            },
+            Twist {
+                vy_m_s: -0.15,
+                ..Twist::default()
+            },
+            Twist {
+                omega_rad_s: 1.0,
+                ..Twist::default()
+            },
+            Twist {
+                vx_m_s: 0.1,
+                vy_m_s: 0.05,
+                omega_rad_s: -0.4,
+            },
+        ]
+    }
+
+    #[test]
+    fn forward_inverts_inverse() {
+        for config in [
+            drive(&[0.0, 120.0, 240.0], &[1, 1, 1]),
+            drive(&[30.0, 150.0, 270.0], &[1, -1, 1]),
+            drive(&[45.0, 135.0, 225.0, 315.0], &[1, 1, -1, 1]),
+        ] {
+            let kinematics = KiwiKinematics::new(&config).unwrap();
+            for twist in twists() {
+                let wheels = kinematics.inverse(&twist);
+                assert_eq!(wheels.len(), config.wheel_mounts.len());
+                assert_twist_eq(kinematics.forward(&wheels), twist);
+            }
+        }
+    }
+
+    #[test]
+    fn direction_flips_servo_speed() {
+        let forward = KiwiKinematics::new(&drive(&[0.0, 120.0, 240.0], &[1, 1, 1])).unwrap();
+        let flipped = KiwiKinematics::new(&drive(&[0.0, 120.0, 240.0], &[1, -1, 1])).unwrap();
+        let twist = twists()[4];
+        let a = forward.inverse(&twist);
+        let b = flipped.inverse(&twist);
+        assert!((a[0] - b[0]).abs() < 1e-12);
+        assert!((a[1] + b[1]).abs() < 1e-12);
+    }
+
+    #[test]
+    fn saturation_keeps_direction() {
+        let kinematics = KiwiKinematics::new(&drive(&[0.0, 120.0, 240.0], &[1, 1, 1])).unwrap();
+        let twist = Twist {
+            vx_m_s: 1.2,
+            vy_m_s: -0.8,
+            omega_rad_s: 3.0,
+        };
+        let (scaled, scale) = kinematics.saturate(&twist);
+        assert!(scale > 0.0 && scale < 1.0);
+        assert_twist_eq(
+            scaled,
+            Twist {
+                vx_m_s: twist.vx_m_s * scale,
+                vy_m_s: twist.vy_m_s * scale,
+                omega_rad_s: twist.omega_rad_s * scale,
+            },
+        );
+        let peak = kinematics
+            .inverse(&scaled)
+            .iter()
+            .fold(0.0f64, |peak, speed| peak.max(speed.abs()));
+        assert!((peak - 5.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn slow_twist_is_not_saturated() {
+        let kinematics = KiwiKinematics::new(&drive(&[0.0, 120.0, 240.0], &[1, 1, 1])).unwrap();
+        let twist = twists()[4];
+        assert_eq!(kinematics.saturate(&twist), (twist, 1.0));
+    }
+
+    #[test]
+    fn rejects_degenerate_mounts() {
+        assert!(KiwiKinematics::new(&drive(&[0.0, 120.0], &[1, 1])).is_err());
+        assert!(KiwiKinematics::new(&drive(&[0.0, 0.0, 180.0], &[1, 1, 1])).is_err());
     }
 }
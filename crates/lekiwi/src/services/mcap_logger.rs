@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::collections::HashSet;
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -5,13 +6,13 @@ use std::sync::Arc;
 use std::time::Instant;
 
 use anyhow::{Context, Result};
-use foxglove::{McapCompression, McapWriteOptions, McapWriterHandle};
+use foxglove::{McapAttachment, McapCompression, McapWriteOptions, McapWriterHandle};
 use tokio::sync::watch;
 
 use crate::bus::Bus;
-use crate::config::LoggingSettings;
+use crate::config::{CamerasConfig, LoggingSettings};
 use crate::messages::{LogAction, LogStatus};
-use crate::telemetry::{Telemetry, TOPIC_CAMERA_BASE, TOPIC_CAMERA_WRIST};
+use crate::telemetry::{camera_calibration_topic, camera_stats_topic, camera_topic, Telemetry};
 use crate::utils::now_nanos;
 
 pub async fn run(
@@ -19,6 +20,7 @@ pub async fn run(
     telemetry: Arc<Telemetry>,
     ctx: Arc<foxglove::Context>,
     logging: LoggingSettings,
+    cameras: CamerasConfig,
     mut shutdown: watch::Receiver<bool>,
 ) -> Result<()> {
     let mut rx = bus.log_control.subscribe();
@@ -36,8 +38,9 @@ pub async fn run(
                             continue;
                         }
                         let path = build_log_path(&logging.directory, cmd.session_name.as_deref())?;
-                        let topics = resolve_topics(&logging, cmd.topics);
+                        let topics = resolve_topics(&logging, &cameras, cmd.topics);
                         let handle = create_writer(&ctx, &path, topics)?;
+                        attach_calibrations(&handle, &cameras);
 
                         start_time = Some(Instant::now());
                         active_path = Some(path.clone());
@@ -83,21 +86,69 @@ pub async fn run(
     Ok(())
 }
 
-fn resolve_topics(logging: &LoggingSettings, requested: Option<Vec<String>>) -> HashSet<String> {
+fn resolve_topics(
+    logging: &LoggingSettings,
+    cameras: &CamerasConfig,
+    requested: Option<Vec<String>>,
+) -> HashSet<String> {
     let mut topics: HashSet<String> = requested
         .filter(|list| !list.is_empty())
         .unwrap_or_else(|| logging.default_topics.clone())
         .into_iter()
         .collect();
 
+    // Stats are tiny and explain gaps in the images, so they always go in.
+    topics.extend(
+        cameras
+            .cameras
+            .iter()
+            .map(|camera| camera_stats_topic(&camera.name)),
+    );
     if logging.include_cameras {
-        topics.insert(TOPIC_CAMERA_BASE.to_string());
-        topics.insert(TOPIC_CAMERA_WRIST.to_string());
+        for camera in &cameras.cameras {
+            topics.insert(camera_topic(&camera.name));
+            if camera.calibration.is_some() {
+                topics.insert(camera_calibration_topic(&camera.name));
+            }
+        }
     }
 
     topics
 }
 
+/// Stores each camera's intrinsics as `calibration/<name>.yaml`, in the
+/// calibration_file format, so a recording can be reprocessed without the
+/// robot's config.
+fn attach_calibrations(
+    handle: &McapWriterHandle<std::io::BufWriter<std::fs::File>>,
+    cameras: &CamerasConfig,
+) {
+    for camera in &cameras.cameras {
+        let Some(calibration) = &camera.calibration else {
+            continue;
+        };
+        let result = serde_yaml::to_string(calibration)
+            .map_err(anyhow::Error::from)
+            .and_then(|yaml| {
+                let timestamp_ns = now_nanos();
+                handle.attach(&McapAttachment {
+                    log_time: timestamp_ns,
+                    create_time: timestamp_ns,
+                    name: format!("calibration/{}.yaml", camera.name),
+                    media_type: "application/yaml".to_string(),
+                    data: Cow::Owned(yaml.into_bytes()),
+                })?;
+                Ok(())
+            });
+        if let Err(err) = result {
+            tracing::warn!(
+                "Failed to attach calibration for camera {}: {err:#}",
+                camera.name
+            );
+        }
+    }
+}
+
 fn build_log_path(dir: &str, session: Option<&str>) -> Result<PathBuf> {
     let name = session
         .map(|s| s.to_string())
@@ -5,3 +5,4 @@ pub mod kinematics;
 pub mod mcap_logger;
 pub mod motor_bus;
 pub mod state_estimator;
+pub mod transforms;
@@ -1,16 +1,21 @@
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use tokio::sync::watch;
 
 use crate::bus::Bus;
-use crate::config::{BatteryConfig, DriveConfig, RobotConfig};
-use crate::messages::{PowerState, ServoState, ServoStateArray, VelocityCommand};
+use crate::config::{BatteryConfig, DriveConfig, RobotConfig, ServoBusConfig};
+use crate::messages::{
+    PowerState, ServoBusHealth, ServoState, ServoStateArray, WheelTargetArray,
+};
+use crate::servo::feetech::{FeetechBus, Protocol, ServoReading};
+use crate::servo::transport::{self, ServoTransport};
 use crate::telemetry::Telemetry;
 use crate::utils::now_nanos;
 
-const SERVO_PUBLISH_HZ: u64 = 10;
+const CONTROL_HZ: u64 = 50;
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
 
 pub async fn run(
     bus: Arc<Bus>,
@@ -18,27 +23,88 @@ pub async fn run(
     robot: RobotConfig,
     mut shutdown: watch::Receiver<bool>,
 ) -> Result<()> {
-    let mut cmd_rx = bus.cmd_out.subscribe();
-    let mut interval = tokio::time::interval(Duration::from_millis(1000 / SERVO_PUBLISH_HZ));
-    let mut last_cmd = VelocityCommand::zero("motor_bus", now_nanos());
+    let protocol = Protocol::from_config(&robot.servo_bus.protocol)?;
+    let ids: Vec<u8> = robot
+        .drive
+        .wheel_mounts
+        .iter()
+        .map(|mount| mount.servo_id)
+        .collect();
+    let mut targets_rx = bus.wheel_targets.subscribe();
+    let target_timeout = Duration::from_millis(robot.safety.command_timeout_ms);
+    let mut interval = tokio::time::interval(Duration::from_millis(1000 / CONTROL_HZ));
+    let mut last_targets: Option<(WheelTargetArray, Instant)> = None;
     let mut last_power = Instant::now();
+    let mut last_health = Instant::now();
+    let mut last_connect: Option<Instant> = None;
+    let mut driver: Option<ServoBus> = None;
+    let mut last_voltage: Option<f32> = None;
+    let mut last_error: Option<String> = None;
+    let mut health = build_health(None, &ids, last_error.clone());
 
     loop {
         tokio::select! {
             _ = interval.tick() => {
-                let servo_state = build_servo_state(&last_cmd, &robot.drive, &robot.battery);
-                telemetry.log_servo_state(&servo_state);
-                let _ = bus.servos.send(servo_state);
+                if driver.is_none() && last_connect.is_none_or(|t| t.elapsed() >= RECONNECT_INTERVAL) {
+                    last_connect = Some(Instant::now());
+                    match connect(&robot.servo_bus, &ids, protocol).await {
+                        Ok(servo_bus) => driver = Some(servo_bus),
+                        Err(err) => last_error = Some(format!("{err:#}")),
+                    }
+                }
+
+                if let Some(servo_bus) = driver.take() {
+                    let targets = servo_targets(last_targets.as_ref(), &ids, target_timeout);
+                    let drive = robot.drive.clone();
+                    let (servo_bus, result) = tokio::task::spawn_blocking(move || {
+                        let mut servo_bus = servo_bus;
+                        let result = servo_bus.cycle(&targets, &drive);
+                        (servo_bus, result)
+                    })
+                    .await?;
+
+                    match result {
+                        Ok(servo_state) => {
+                            driver = Some(servo_bus);
+                            if let Some(voltage) = mean_voltage(&servo_state) {
+                                last_voltage = Some(voltage);
+                            }
+                            telemetry.log_servo_state(&servo_state);
+                            let _ = bus.servos.send(servo_state);
+                        }
+                        Err(err) => {
+                            tracing::warn!("Servo bus cycle failed, reconnecting: {err:#}");
+                            last_error = Some(format!("{err:#}"));
+                            // The bus may still reach the servos; stop them
+                            // rather than leave the last goal speed running.
+                            let mut servo_bus = servo_bus;
+                            let _ = tokio::task::spawn_blocking(move || servo_bus.release()).await;
+                        }
+                    }
+                }
+
+                let next_health = build_health(driver.as_ref(), &ids, last_error.clone());
+                let changed = next_health.torque_enabled != health.torque_enabled
+                    || next_health.connected != health.connected
+                    || next_health.missing_ids != health.missing_ids;
+                if changed || last_health.elapsed() >= Duration::from_secs(1) {
+                    last_health = Instant::now();
+                    health = next_health;
+                    telemetry.log_servo_bus_health(&health);
+                    let _ = bus.servo_health.send(health.clone());
+                }
 
                 if last_power.elapsed() >= Duration::from_secs(1) {
                     last_power = Instant::now();
-                    let power = build_power_state(&robot.battery);
-                    telemetry.log_power_state(&power);
-                    let _ = bus.power.send(power);
+                    if let Some(voltage) = last_voltage {
+                        let power = build_power_state(voltage, &robot.battery);
+                        telemetry.log_power_state(&power);
+                        let _ = bus.power.send(power);
+                    }
                 }
             }
-            Ok(cmd) = cmd_rx.recv() => {
-                last_cmd = cmd;
+            Ok(targets) = targets_rx.recv() => {
+                last_targets = Some((targets, Instant::now()));
             }
             _ = shutdown.changed() => {
                 break;
@@ -46,48 +112,248 @@ pub async fn run(
         }
     }
 
+    if let Some(mut servo_bus) = driver {
+        let _ = tokio::task::spawn_blocking(move || servo_bus.release()).await;
+    }
+
     Ok(())
 }
 
-fn build_servo_state(
-    cmd: &VelocityCommand,
-    drive: &DriveConfig,
-    battery: &BatteryConfig,
-) -> ServoStateArray {
-    let mut servos = Vec::with_capacity(drive.wheel_mounts.len());
-    let timestamp_ns = now_nanos();
-
-    let wheel_radius = drive.wheel_radius_m.max(1e-4);
-    let wheel_distance = drive.wheel_distance_m;
-
-    for mount in &drive.wheel_mounts {
-        let angle = mount.angle_deg.to_radians();
-        let v = -angle.sin() * cmd.vx_m_s + angle.cos() * cmd.vy_m_s + wheel_distance * cmd.omega_rad_s;
-        let omega = (v / wheel_radius) * mount.direction as f32;
-
-        servos.push(ServoState {
-            id: mount.servo_id,
-            name: mount.name.clone(),
-            position_rad: 0.0,
-            velocity_rad_s: omega,
-            load: 0.0,
-            temperature_c: 0.0,
-            voltage_v: battery.nominal_voltage_v,
-            error_flags: 0,
-        });
-    }
-
-    ServoStateArray {
-        timestamp_ns,
-        servos,
+async fn connect(config: &ServoBusConfig, ids: &[u8], protocol: Protocol) -> Result<ServoBus> {
+    let config = config.clone();
+    let ids = ids.to_vec();
+    let result = tokio::task::spawn_blocking(move || ServoBus::open(&config, ids, protocol)).await;
+
+    match result {
+        Ok(Ok(servo_bus)) => {
+            tracing::info!("Servo bus connected on {}", servo_bus.port.describe());
+            Ok(servo_bus)
+        }
+        Ok(Err(err)) => {
+            tracing::warn!("Servo bus unavailable: {err:#}");
+            Err(err)
+        }
+        Err(err) => {
+            tracing::warn!("Servo bus connect task failed: {err}");
+            Err(err.into())
+        }
+    }
+}
+
+/// Owns the open bus and the torque gate: torque is only enabled once every
+/// configured servo has answered a ping, and is dropped again as soon as any
+/// of them goes quiet.
+struct ServoBus {
+    port: FeetechBus,
+    ids: Vec<u8>,
+    missing: Vec<u8>,
+    torque_enabled: Option<bool>,
+    last_probe: Instant,
+}
+
+impl ServoBus {
+    fn open(config: &ServoBusConfig, ids: Vec<u8>, protocol: Protocol) -> Result<Self> {
+        let port = transport::open(config, protocol, &ids)?;
+        Self::start(port, ids, protocol)
+    }
+
+    fn start(port: Box<dyn ServoTransport>, ids: Vec<u8>, protocol: Protocol) -> Result<Self> {
+        let mut servo_bus = Self {
+            port: FeetechBus::new(port, protocol),
+            missing: ids.clone(),
+            ids,
+            torque_enabled: None,
+            last_probe: Instant::now(),
+        };
+        // Servos keep their last goal speed across a dropped connection, so
+        // stop them before anything else; sync writes need no reply.
+        servo_bus
+            .port
+            .write_goal_speeds(&servo_bus.zero_targets())
+            .context("unable to zero goal speeds")?;
+        servo_bus.probe()?;
+        Ok(servo_bus)
+    }
+
+    fn probe(&mut self) -> Result<()> {
+        self.last_probe = Instant::now();
+        let mut missing = Vec::new();
+        for id in self.ids.clone() {
+            let ready = self
+                .port
+                .ping(id)
+                .and_then(|_| self.port.set_wheel_mode(id))
+                .with_context(|| format!("servo {id} is not ready"));
+            if let Err(err) = ready {
+                tracing::warn!("{err:#}");
+                missing.push(id);
+            }
+        }
+
+        if missing != self.missing {
+            if missing.is_empty() {
+                tracing::info!("All {} servos answered ping", self.ids.len());
+            } else {
+                tracing::warn!("Servos {missing:?} missing; torque stays off");
+            }
+        }
+        self.missing = missing;
+        self.apply_torque_gate()
+    }
+
+    fn apply_torque_gate(&mut self) -> Result<()> {
+        let want = self.missing.is_empty();
+        if self.torque_enabled == Some(want) {
+            return Ok(());
+        }
+        if !want {
+            self.port.write_goal_speeds(&self.zero_targets())?;
+        }
+        for id in self.ids.clone() {
+            if self.missing.contains(&id) {
+                continue;
+            }
+            self.port
+                .set_torque(id, want)
+                .with_context(|| format!("unable to set torque on servo {id}"))?;
+        }
+        self.torque_enabled = Some(want);
+        tracing::info!("Servo torque {}", if want { "enabled" } else { "disabled" });
+        Ok(())
+    }
+
+    fn cycle(&mut self, targets: &[(u8, f32)], drive: &DriveConfig) -> Result<ServoStateArray> {
+        if !self.missing.is_empty() && self.last_probe.elapsed() >= RECONNECT_INTERVAL {
+            self.probe()?;
+        }
+        if self.torque_enabled == Some(true) {
+            self.port.write_goal_speeds(targets)?;
+        }
+
+        let readings = self.port.read_states(&self.ids)?;
+        let timestamp_ns = now_nanos();
+
+        let mut servos = Vec::with_capacity(readings.len());
+        let mut missing = Vec::new();
+        for (mount, (id, reading)) in drive.wheel_mounts.iter().zip(readings) {
+            match reading {
+                Ok(reading) => servos.push(build_servo_state(id, &mount.name, &reading)),
+                Err(err) => {
+                    missing.push(id);
+                    tracing::warn!("Servo {id} ({}) read failed: {err:#}", mount.name);
+                }
+            }
+        }
+
+        anyhow::ensure!(
+            missing.len() < self.ids.len() || self.ids.is_empty(),
+            "no servo answered the state read"
+        );
+        if !missing.is_empty() {
+            for id in missing {
+                if !self.missing.contains(&id) {
+                    self.missing.push(id);
+                }
+            }
+            self.apply_torque_gate()?;
+        }
+
+        Ok(ServoStateArray {
+            timestamp_ns,
+            servos,
+        })
+    }
+
+    /// Best-effort stop: zero speeds, then torque off on every servo,
+    /// carrying on past failures since the bus may be half broken.
+    fn release(&mut self) {
+        if let Err(err) = self.port.write_goal_speeds(&self.zero_targets()) {
+            tracing::warn!("Unable to zero servo speeds: {err:#}");
+        }
+        for id in self.ids.clone() {
+            if let Err(err) = self.port.set_torque(id, false) {
+                tracing::warn!("Unable to disable torque on servo {id}: {err:#}");
+            }
+        }
+    }
+
+    fn zero_targets(&self) -> Vec<(u8, f32)> {
+        self.ids.iter().map(|id| (*id, 0.0)).collect()
+    }
+}
+
+fn build_health(
+    driver: Option<&ServoBus>,
+    ids: &[u8],
+    last_error: Option<String>,
+) -> ServoBusHealth {
+    match driver {
+        Some(servo_bus) => ServoBusHealth {
+            timestamp_ns: now_nanos(),
+            connected: true,
+            torque_enabled: servo_bus.torque_enabled == Some(true),
+            responding_ids: ids
+                .iter()
+                .copied()
+                .filter(|id| !servo_bus.missing.contains(id))
+                .collect(),
+            missing_ids: servo_bus.missing.clone(),
+            last_error,
+        },
+        None => ServoBusHealth {
+            timestamp_ns: now_nanos(),
+            connected: false,
+            torque_enabled: false,
+            responding_ids: Vec::new(),
+            missing_ids: ids.to_vec(),
+            last_error,
+        },
     }
 }
 
-fn build_power_state(battery: &BatteryConfig) -> PowerState {
-    let voltage = battery.nominal_voltage_v;
+/// Goal speeds per servo from the latest kinematics output; stale or missing
+/// targets command zero so a stalled pipeline cannot leave the wheels running.
+fn servo_targets(
+    latest: Option<&(WheelTargetArray, Instant)>,
+    ids: &[u8],
+    timeout: Duration,
+) -> Vec<(u8, f32)> {
+    let fresh = latest.filter(|(_, received)| received.elapsed() <= timeout);
+    ids.iter()
+        .map(|id| {
+            let velocity = fresh
+                .and_then(|(targets, _)| targets.wheels.iter().find(|wheel| wheel.servo_id == *id))
+                .map(|wheel| wheel.velocity_rad_s)
+                .unwrap_or(0.0);
+            (*id, velocity)
+        })
+        .collect()
+}
+
+fn build_servo_state(id: u8, name: &str, reading: &ServoReading) -> ServoState {
+    ServoState {
+        id,
+        name: name.to_string(),
+        position_rad: reading.position_rad(),
+        velocity_rad_s: reading.velocity_rad_s(),
+        load: reading.load(),
+        temperature_c: reading.temperature_c,
+        voltage_v: reading.voltage_v,
+        error_flags: reading.error as u32,
+    }
+}
+
+fn mean_voltage(state: &ServoStateArray) -> Option<f32> {
+    if state.servos.is_empty() {
+        return None;
+    }
+    let sum: f32 = state.servos.iter().map(|servo| servo.voltage_v).sum();
+    Some(sum / state.servos.len() as f32)
+}
+
+fn build_power_state(voltage: f32, battery: &BatteryConfig) -> PowerState {
     let percent = if battery.nominal_voltage_v > battery.low_voltage_v {
-        ((voltage - battery.low_voltage_v)
-            / (battery.nominal_voltage_v - battery.low_voltage_v))
+        ((voltage - battery.low_voltage_v) / (battery.nominal_voltage_v - battery.low_voltage_v))
             .clamp(0.0, 1.0)
     } else {
         1.0
@@ -100,3 +366,161 @@ fn build_power_state(battery: &BatteryConfig) -> PowerState {
         low_battery: voltage <= battery.low_voltage_v,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::config::{StallConfig, WheelMount};
+    use crate::servo::sim::{LoopbackTransport, SimulatedBus};
+
+    const IDS: [u8; 3] = [7, 8, 9];
+
+    /// A cable to a simulated bus that can be pulled and plugged back in;
+    /// the servos keep their state in between.
+    #[derive(Clone)]
+    struct Link {
+        sim: Arc<Mutex<LoopbackTransport>>,
+        up: Arc<AtomicBool>,
+    }
+
+    impl Link {
+        fn new(servos: &[u8]) -> Self {
+            let sim = SimulatedBus::new(Protocol::Sts, servos);
+            Self {
+                sim: Arc::new(Mutex::new(LoopbackTransport::new(sim))),
+                up: Arc::new(AtomicBool::new(true)),
+            }
+        }
+
+        fn connect(&self) -> Result<ServoBus> {
+            ServoBus::start(Box::new(self.clone()), IDS.to_vec(), Protocol::Sts)
+        }
+    }
+
+    impl Read for Link {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if !self.up.load(Ordering::Relaxed) {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "link down"));
+            }
+            self.sim.lock().unwrap().read(buf)
+        }
+    }
+
+    impl Write for Link {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if !self.up.load(Ordering::Relaxed) {
+                return Ok(buf.len());
+            }
+            self.sim.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl ServoTransport for Link {
+        fn describe(&self) -> String {
+            "test link".to_string()
+        }
+    }
+
+    fn drive() -> DriveConfig {
+        DriveConfig {
+            wheel_radius_m: 0.05,
+            wheel_distance_m: 0.125,
+            max_wheel_speed_rad_s: 5.0,
+            wheel_mounts: IDS
+                .iter()
+                .map(|id| WheelMount {
+                    name: format!("wheel_{id}"),
+                    angle_deg: 0.0,
+                    servo_id: *id,
+                    direction: 1,
+                    stall: StallConfig::default(),
+                })
+                .collect(),
+        }
+    }
+
+    fn speeds(state: &ServoStateArray) -> Vec<(u8, f32)> {
+        state
+            .servos
+            .iter()
+            .map(|servo| (servo.id, servo.velocity_rad_s))
+            .collect()
+    }
+
+    fn stopped(servo_bus: &mut ServoBus) -> bool {
+        servo_bus
+            .port
+            .read_states(&IDS)
+            .unwrap()
+            .into_iter()
+            .all(|(_, reading)| reading.unwrap().speed_steps_s == 0)
+    }
+
+    fn assert_speeds(state: &ServoStateArray, expected: &[(u8, f32)]) {
+        let actual = speeds(state);
+        assert_eq!(actual.len(), expected.len(), "{actual:?}");
+        for ((id, speed), (expected_id, expected_speed)) in actual.iter().zip(expected) {
+            assert_eq!(id, expected_id);
+            assert!((speed - expected_speed).abs() < 0.01, "{actual:?}");
+        }
+    }
+
+    #[test]
+    fn drives_the_wheels_once_every_servo_answers() {
+        let link = Link::new(&IDS);
+        let mut servo_bus = link.connect().unwrap();
+        assert!(servo_bus.missing.is_empty());
+        assert_eq!(servo_bus.torque_enabled, Some(true));
+
+        let targets = [(7, 1.0), (8, -0.5), (9, 0.0)];
+        let state = servo_bus.cycle(&targets, &drive()).unwrap();
+        assert_speeds(&state, &targets);
+        assert!(state.servos.iter().all(|servo| servo.voltage_v > 11.0));
+
+        servo_bus.release();
+        assert!(stopped(&mut servo_bus));
+    }
+
+    #[test]
+    fn missing_servo_keeps_torque_off() {
+        let link = Link::new(&IDS[..2]);
+        let mut servo_bus = link.connect().unwrap();
+        assert_eq!(servo_bus.missing, [9]);
+        assert_eq!(servo_bus.torque_enabled, Some(false));
+
+        let targets = [(7, 1.0), (8, 1.0), (9, 1.0)];
+        let state = servo_bus.cycle(&targets, &drive()).unwrap();
+        assert_speeds(&state, &[(7, 0.0), (8, 0.0)]);
+    }
+
+    #[test]
+    fn reconnect_stops_the_wheels_before_driving_again() {
+        let link = Link::new(&IDS);
+        let mut servo_bus = link.connect().unwrap();
+        let targets = [(7, 1.0), (8, 1.0), (9, 1.0)];
+        servo_bus.cycle(&targets, &drive()).unwrap();
+
+        link.up.store(false, Ordering::Relaxed);
+        assert!(servo_bus.cycle(&targets, &drive()).is_err());
+        let silent = link.connect().unwrap();
+        assert_eq!(silent.missing, IDS);
+        assert_eq!(silent.torque_enabled, Some(false));
+
+        link.up.store(true, Ordering::Relaxed);
+        let mut servo_bus = link.connect().unwrap();
+        assert_eq!(servo_bus.torque_enabled, Some(true));
+        // Opening the bus zeroed the goal speed the servos kept.
+        assert!(stopped(&mut servo_bus));
+
+        let state = servo_bus.cycle(&targets, &drive()).unwrap();
+        assert_speeds(&state, &targets);
+    }
+}
@@ -5,27 +5,54 @@ use anyhow::Result;
 use tokio::sync::watch;
 
 use crate::bus::Bus;
-use crate::config::FramesConfig;
-use crate::messages::{Odometry, VelocityCommand};
+use crate::config::{EstimatorMode, RobotConfig};
+use crate::ekf::PoseEkf;
+use crate::messages::{Imu, Odometry, PoseReset, ServoStateArray, SetPoseCommand};
+use crate::services::kinematics::{KiwiKinematics, Twist};
 use crate::telemetry::Telemetry;
-use crate::utils::now_nanos;
+use crate::utils::{normalize_angle, now_nanos};
 
 const TICK_HZ: u64 = 50;
+const ENCODER_TIMEOUT: Duration = Duration::from_millis(500);
+/// /cmd/set_pose outside these bounds is a client bug, not a place the robot
+/// can be.
+const MAX_SET_POSE_M: f64 = 10_000.0;
+const MAX_SET_POSE_RAD: f64 = 4.0 * std::f64::consts::PI;
 
 pub async fn run(
     bus: Arc<Bus>,
     telemetry: Arc<Telemetry>,
-    frames: FramesConfig,
+    robot: RobotConfig,
     mut shutdown: watch::Receiver<bool>,
 ) -> Result<()> {
-    let mut cmd_rx = bus.cmd_out.subscribe();
+    let kinematics = KiwiKinematics::new(&robot.drive)?;
+    let mode = robot.estimator.mode;
+    let mut targets_rx = bus.wheel_targets.subscribe();
+    let mut servos_rx = bus.servos.subscribe();
+    let mut imu_rx = bus.imu.subscribe();
+    let mut set_pose_rx = bus.cmd_set_pose.subscribe();
     let mut interval = tokio::time::interval(Duration::from_millis(1000 / TICK_HZ));
+    let mut encoders = WheelEncoders::new(
+        robot
+            .drive
+            .wheel_mounts
+            .iter()
+            .map(|mount| mount.servo_id)
+            .collect(),
+    );
+    let imu_timeout = Duration::from_millis(robot.estimator.ekf.imu_timeout_ms);
+    let mut ekf = PoseEkf::new(robot.estimator.ekf.clone());
+    let mut latest_imu: Option<(Imu, Instant)> = None;
+    let mut imu_live = false;
+    let mut encoders_live = true;
+    tracing::info!("State estimator using {mode:?} odometry");
 
-    let mut last_cmd = VelocityCommand::zero("state_estimator", now_nanos());
+    let mut twist = Twist::default();
     let mut pose_x = 0.0f64;
     let mut pose_y = 0.0f64;
     let mut pose_theta = 0.0f64;
     let mut last_update = Instant::now();
+    let mut last_encoder = Instant::now();
 
     loop {
         tokio::select! {
@@ -34,34 +61,123 @@ pub async fn run(
                 let dt = (now - last_update).as_secs_f64().max(1e-3);
                 last_update = now;
 
-                let vx = last_cmd.vx_m_s as f64;
-                let vy = last_cmd.vy_m_s as f64;
-                let omega = last_cmd.omega_rad_s as f64;
-
-                let cos_t = pose_theta.cos();
-                let sin_t = pose_theta.sin();
-                let dx = (vx * cos_t - vy * sin_t) * dt;
-                let dy = (vx * sin_t + vy * cos_t) * dt;
-
-                pose_x += dx;
-                pose_y += dy;
-                pose_theta = normalize_angle(pose_theta + omega * dt);
+                match mode {
+                    EstimatorMode::Commanded => {
+                        integrate(&mut pose_x, &mut pose_y, &mut pose_theta, &twist, dt);
+                    }
+                    EstimatorMode::Encoders => {
+                        if last_encoder.elapsed() > ENCODER_TIMEOUT {
+                            twist = Twist::default();
+                        }
+                    }
+                    EstimatorMode::Ekf => {
+                        let imu = latest_imu
+                            .as_ref()
+                            .filter(|(_, received)| received.elapsed() <= imu_timeout)
+                            .map(|(imu, _)| imu);
+                        if imu_live != imu.is_some() {
+                            imu_live = imu.is_some();
+                            if imu_live {
+                                tracing::info!("EKF receiving IMU samples");
+                            } else {
+                                tracing::warn!("EKF lost IMU; fusing wheel odometry only");
+                            }
+                        }
+                        let stale = last_encoder.elapsed() > ENCODER_TIMEOUT;
+                        if encoders_live == stale {
+                            encoders_live = !stale;
+                            if stale {
+                                tracing::warn!("EKF lost wheel odometry; holding the pose still");
+                            } else {
+                                tracing::info!("EKF receiving wheel odometry");
+                            }
+                        }
+                        // Without encoders nothing bounds the velocity, so
+                        // neither the last twist nor the accelerometer may
+                        // move the pose.
+                        let accel = imu.filter(|_| !stale).map(|imu| {
+                            [
+                                imu.linear_acceleration_m_s2[0] as f64,
+                                imu.linear_acceleration_m_s2[1] as f64,
+                            ]
+                        });
+                        if stale {
+                            ekf.zero_velocity();
+                        }
+                        ekf.predict(dt, accel);
+                        (pose_x, pose_y, pose_theta) = ekf.pose();
+                        twist = ekf.twist();
+                    }
+                }
 
                 let odom = Odometry {
                     timestamp_ns: now_nanos(),
                     x_m: pose_x,
                     y_m: pose_y,
                     theta_rad: pose_theta,
-                    vx_m_s: last_cmd.vx_m_s,
-                    vy_m_s: last_cmd.vy_m_s,
-                    omega_rad_s: last_cmd.omega_rad_s,
-                    frame_id: frames.odom.clone(),
+                    vx_m_s: twist.vx_m_s as f32,
+                    vy_m_s: twist.vy_m_s as f32,
+                    omega_rad_s: twist.omega_rad_s as f32,
+                    frame_id: robot.frames.odom.clone(),
+                    pose_covariance: (mode == EstimatorMode::Ekf).then(|| ekf.pose_covariance()),
                 };
                 telemetry.log_odometry(&odom);
                 let _ = bus.odometry.send(odom);
             }
-            Ok(cmd) = cmd_rx.recv() => {
-                last_cmd = cmd;
+            Ok(targets) = targets_rx.recv(), if mode == EstimatorMode::Commanded => {
+                let speeds: Vec<f64> = targets
+                    .wheels
+                    .iter()
+                    .map(|wheel| wheel.velocity_rad_s as f64)
+                    .collect();
+                twist = kinematics.forward(&speeds);
+            }
+            Ok(servos) = servos_rx.recv(), if mode != EstimatorMode::Commanded => {
+                if let Some((deltas, dt)) = encoders.update(&servos) {
+                    let speeds: Vec<f64> = deltas.iter().map(|delta| delta / dt).collect();
+                    let measured = kinematics.forward(&speeds);
+                    if mode == EstimatorMode::Ekf {
+                        ekf.update_wheels(&measured);
+                    } else {
+                        twist = measured;
+                        integrate(&mut pose_x, &mut pose_y, &mut pose_theta, &twist, dt);
+                    }
+                    last_encoder = Instant::now();
+                }
+            }
+            Ok(imu) = imu_rx.recv(), if mode == EstimatorMode::Ekf => {
+                ekf.update_gyro(imu.angular_velocity_rad_s[2] as f64);
+                latest_imu = Some((imu, Instant::now()));
+            }
+            Ok(cmd) = set_pose_rx.recv() => {
+                if let Err(reason) = check_set_pose(&cmd) {
+                    tracing::warn!("Ignoring /cmd/set_pose from '{}': {reason}", cmd.source);
+                    continue;
+                }
+                let reset = PoseReset {
+                    timestamp_ns: now_nanos(),
+                    previous_x_m: pose_x,
+                    previous_y_m: pose_y,
+                    previous_theta_rad: pose_theta,
+                    x_m: cmd.x_m,
+                    y_m: cmd.y_m,
+                    theta_rad: normalize_angle(cmd.theta_rad),
+                    source: cmd.source,
+                    frame_id: robot.frames.odom.clone(),
+                };
+                pose_x = reset.x_m;
+                pose_y = reset.y_m;
+                pose_theta = reset.theta_rad;
+                ekf.reset_pose(pose_x, pose_y, pose_theta);
+                tracing::info!(
+                    "Pose reset by '{}' to ({:.3}, {:.3}, {:.3})",
+                    reset.source,
+                    reset.x_m,
+                    reset.y_m,
+                    reset.theta_rad
+                );
+                telemetry.log_pose_reset(&reset);
+                let _ = bus.pose_reset.send(reset);
             }
             _ = shutdown.changed() => {
                 break;
@@ -72,12 +188,78 @@ pub async fn run(
     Ok(())
 }
 
-fn normalize_angle(mut theta: f64) -> f64 {
-    while theta > std::f64::consts::PI {
-        theta -= 2.0 * std::f64::consts::PI;
+/// Tracks wheel angles between servo reports. Positions come from 12-bit
+/// encoders that wrap every revolution, so each delta is folded into
+/// (-pi, pi]; at the servo's top speed a 50 Hz bus moves well under that.
+struct WheelEncoders {
+    ids: Vec<u8>,
+    last: Option<(Vec<f64>, u64)>,
+}
+
+impl WheelEncoders {
+    fn new(ids: Vec<u8>) -> Self {
+        Self { ids, last: None }
     }
-    while theta < -std::f64::consts::PI {
-        theta += 2.0 * std::f64::consts::PI;
+
+    /// Returns per-wheel angle deltas (rad, mount order) and the elapsed time.
+    /// Samples missing any wheel are skipped so the next delta spans the gap.
+    fn update(&mut self, msg: &ServoStateArray) -> Option<(Vec<f64>, f64)> {
+        let positions: Option<Vec<f64>> = self
+            .ids
+            .iter()
+            .map(|id| {
+                msg.servos
+                    .iter()
+                    .find(|servo| servo.id == *id)
+                    .map(|servo| servo.position_rad as f64)
+            })
+            .collect();
+        let positions = positions?;
+
+        let previous = self.last.replace((positions.clone(), msg.timestamp_ns));
+        let (previous, previous_ns) = previous?;
+        if msg.timestamp_ns <= previous_ns {
+            return None;
+        }
+        let dt = (msg.timestamp_ns - previous_ns) as f64 * 1e-9;
+        if dt > ENCODER_TIMEOUT.as_secs_f64() {
+            // Too long for the wrap fold to be trusted; restart from here.
+            return None;
+        }
+
+        let deltas = positions
+            .iter()
+            .zip(&previous)
+            .map(|(now, before)| normalize_angle(now - before))
+            .collect();
+        Some((deltas, dt))
+    }
+}
+
+/// Why a /cmd/set_pose must be ignored: a non-finite or out-of-range value.
+fn check_set_pose(cmd: &SetPoseCommand) -> Result<(), String> {
+    for (name, value) in [("x_m", cmd.x_m), ("y_m", cmd.y_m)] {
+        if !value.is_finite() || value.abs() > MAX_SET_POSE_M {
+            return Err(format!(
+                "{name} must be finite and within ±{MAX_SET_POSE_M} m"
+            ));
+        }
     }
-    theta
+    if !cmd.theta_rad.is_finite() || cmd.theta_rad.abs() > MAX_SET_POSE_RAD {
+        return Err(format!(
+            "theta_rad must be finite and within ±{MAX_SET_POSE_RAD:.2} rad"
+        ));
+    }
+    Ok(())
+}
+
+/// Integrates a body-frame twist over `dt`, rotating by the midpoint heading.
+fn integrate(pose_x: &mut f64, pose_y: &mut f64, pose_theta: &mut f64, twist: &Twist, dt: f64) {
+    let heading = *pose_theta + twist.omega_rad_s * dt * 0.5;
+    let cos_t = heading.cos();
+    let sin_t = heading.sin();
+
+    *pose_x += (twist.vx_m_s * cos_t - twist.vy_m_s * sin_t) * dt;
+    *pose_y += (twist.vx_m_s * sin_t + twist.vy_m_s * cos_t) * dt;
+    *pose_theta = normalize_angle(*pose_theta + twist.omega_rad_s * dt);
 }
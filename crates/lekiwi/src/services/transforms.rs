@@ -0,0 +1,126 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use foxglove::schemas::{FrameTransform, FrameTransforms, Quaternion, Vector3};
+use tokio::sync::watch;
+
+use crate::bus::Bus;
+use crate::config::{CamerasConfig, RobotConfig};
+use crate::messages::Odometry;
+use crate::telemetry::Telemetry;
+use crate::utils::{foxglove_timestamp, now_nanos};
+
+/// Static frames are re-sent periodically so clients that connect late, and
+/// MCAP sessions started mid-run, still receive them.
+const STATIC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Publishes `/tf`: odom -> base_link from the estimator, plus fixed
+/// base_link -> wheel_<name> and camera extrinsics.
+pub async fn run(
+    bus: Arc<Bus>,
+    telemetry: Arc<Telemetry>,
+    robot: RobotConfig,
+    cameras: CamerasConfig,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let mut odom_rx = bus.odometry.subscribe();
+    let mut interval = tokio::time::interval(STATIC_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let timestamp_ns = now_nanos();
+                let transforms = FrameTransforms {
+                    transforms: static_transforms(&robot, &cameras, timestamp_ns),
+                };
+                telemetry.log_transforms(&transforms, timestamp_ns);
+            }
+            Ok(odom) = odom_rx.recv() => {
+                let transforms = FrameTransforms {
+                    transforms: vec![odom_transform(&odom, &robot.frames.base_link)],
+                };
+                telemetry.log_transforms(&transforms, odom.timestamp_ns);
+            }
+            _ = shutdown.changed() => {
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn odom_transform(odom: &Odometry, base_link: &str) -> FrameTransform {
+    transform(
+        odom.timestamp_ns,
+        &odom.frame_id,
+        base_link,
+        [odom.x_m, odom.y_m, 0.0],
+        [0.0, 0.0, odom.theta_rad],
+    )
+}
+
+fn static_transforms(
+    robot: &RobotConfig,
+    cameras: &CamerasConfig,
+    timestamp_ns: u64,
+) -> Vec<FrameTransform> {
+    let base_link = &robot.frames.base_link;
+    let base_radius = robot.drive.wheel_distance_m as f64;
+
+    // Wheel frames sit on the mount circle with x pointing out along the axle.
+    let wheels = robot.drive.wheel_mounts.iter().map(|mount| {
+        let angle = (mount.angle_deg as f64).to_radians();
+        transform(
+            timestamp_ns,
+            base_link,
+            &format!("wheel_{}", mount.name),
+            [base_radius * angle.cos(), base_radius * angle.sin(), 0.0],
+            [0.0, 0.0, angle],
+        )
+    });
+
+    let cameras = cameras.cameras.iter().map(|camera| {
+        let extrinsics = &camera.extrinsics;
+        transform(
+            timestamp_ns,
+            &extrinsics.parent_frame,
+            &camera.frame_id(),
+            extrinsics.translation_m,
+            extrinsics.rotation_rpy_deg.map(f64::to_radians),
+        )
+    });
+
+    wheels.chain(cameras).collect()
+}
+
+fn transform(
+    timestamp_ns: u64,
+    parent: &str,
+    child: &str,
+    translation: [f64; 3],
+    rpy: [f64; 3],
+) -> FrameTransform {
+    let [x, y, z] = translation;
+    FrameTransform {
+        timestamp: Some(foxglove_timestamp(timestamp_ns)),
+        parent_frame_id: parent.to_string(),
+        child_frame_id: child.to_string(),
+        translation: Some(Vector3 { x, y, z }),
+        rotation: Some(quaternion_from_rpy(rpy)),
+    }
+}
+
+/// Fixed-axis roll, pitch, yaw (R = Rz * Ry * Rx) to a unit quaternion.
+fn quaternion_from_rpy([roll, pitch, yaw]: [f64; 3]) -> Quaternion {
+    let (sr, cr) = (roll * 0.5).sin_cos();
+    let (sp, cp) = (pitch * 0.5).sin_cos();
+    let (sy, cy) = (yaw * 0.5).sin_cos();
+    Quaternion {
+        x: sr * cp * cy - cr * sp * sy,
+        y: cr * sp * cy + sr * cp * sy,
+        z: cr * cp * sy - sr * sp * cy,
+        w: cr * cp * cy + sr * sp * sy,
+    }
+}
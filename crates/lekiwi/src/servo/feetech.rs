@@ -0,0 +1,596 @@
+use std::io::Read;
+
+use anyhow::{bail, ensure, Context, Result};
+
+use crate::servo::transport::ServoTransport;
+
+pub const BROADCAST_ID: u8 = 0xFE;
+pub const STEPS_PER_REV: f32 = 4096.0;
+
+const HEADER: [u8; 2] = [0xFF, 0xFF];
+const MAX_SCAN_BYTES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Instruction {
+    Ping = 0x01,
+    Read = 0x02,
+    Write = 0x03,
+    SyncRead = 0x82,
+    SyncWrite = 0x83,
+}
+
+impl Instruction {
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0x01 => Some(Self::Ping),
+            0x02 => Some(Self::Read),
+            0x03 => Some(Self::Write),
+            0x82 => Some(Self::SyncRead),
+            0x83 => Some(Self::SyncWrite),
+            _ => None,
+        }
+    }
+}
+
+/// STS3215 control table (SMS/STS memory map).
+pub mod reg {
+    pub const ID: u8 = 5;
+    pub const MIN_ANGLE_LIMIT: u8 = 9;
+    pub const MAX_ANGLE_LIMIT: u8 = 11;
+    pub const OPERATING_MODE: u8 = 33;
+    pub const TORQUE_ENABLE: u8 = 40;
+    pub const GOAL_SPEED: u8 = 46;
+    pub const PRESENT_POSITION: u8 = 56;
+    pub const PRESENT_SPEED: u8 = 58;
+    pub const PRESENT_LOAD: u8 = 60;
+    pub const PRESENT_VOLTAGE: u8 = 62;
+    pub const PRESENT_TEMPERATURE: u8 = 63;
+
+    pub const MODE_WHEEL: u8 = 1;
+
+    /// PRESENT_POSITION through PRESENT_TEMPERATURE in one contiguous read.
+    pub const STATE_BLOCK_LEN: u8 = PRESENT_TEMPERATURE - PRESENT_POSITION + 1;
+}
+
+/// Bits of the status-packet error byte (SMS/STS).
+pub mod error_bits {
+    pub const VOLTAGE: u8 = 0x01;
+    pub const ANGLE: u8 = 0x02;
+    pub const OVERHEAT: u8 = 0x04;
+    pub const OVERCURRENT: u8 = 0x08;
+    pub const OVERLOAD: u8 = 0x20;
+
+    const NAMED: [(u8, &str); 5] = [
+        (VOLTAGE, "voltage"),
+        (ANGLE, "angle_sensor"),
+        (OVERHEAT, "overheat"),
+        (OVERCURRENT, "overcurrent"),
+        (OVERLOAD, "overload"),
+    ];
+
+    /// Fault names for the bits set in `error`; undocumented bits are named
+    /// by position, e.g. "error_bit_4".
+    pub fn names(error: u8) -> Vec<String> {
+        (0..8)
+            .map(|bit| 1u8 << bit)
+            .filter(|mask| error & mask != 0)
+            .map(
+                |mask| match NAMED.iter().find(|(named, _)| *named == mask) {
+                    Some((_, name)) => name.to_string(),
+                    None => format!("error_bit_{}", mask.trailing_zeros()),
+                },
+            )
+            .collect()
+    }
+}
+
+/// STS servos are little-endian with sign-magnitude speed (direction in bit
+/// 15); SCS servos use the same framing but big-endian registers, direction
+/// in bit 10 and angle limits for wheel mode. Load has its sign in bit 10 on
+/// both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Sts,
+    Scs,
+}
+
+impl Protocol {
+    pub fn from_config(name: &str) -> Result<Self> {
+        match name {
+            "feetech_sts" | "sts" => Ok(Self::Sts),
+            "feetech_scs" | "scs" => Ok(Self::Scs),
+            other => bail!("unsupported servo bus protocol '{other}'"),
+        }
+    }
+
+    pub fn encode_u16(self, value: u16) -> [u8; 2] {
+        match self {
+            Self::Sts => value.to_le_bytes(),
+            Self::Scs => value.to_be_bytes(),
+        }
+    }
+
+    pub fn decode_u16(self, bytes: [u8; 2]) -> u16 {
+        match self {
+            Self::Sts => u16::from_le_bytes(bytes),
+            Self::Scs => u16::from_be_bytes(bytes),
+        }
+    }
+
+    pub fn encode_speed(self, steps_per_s: i32) -> [u8; 2] {
+        self.encode_u16(encode_sign_magnitude(steps_per_s, self.speed_sign_bit()))
+    }
+
+    pub fn decode_speed(self, bytes: [u8; 2]) -> i32 {
+        decode_sign_magnitude(self.decode_u16(bytes), self.speed_sign_bit())
+    }
+
+    fn speed_sign_bit(self) -> u32 {
+        match self {
+            Self::Sts => 15,
+            Self::Scs => 10,
+        }
+    }
+
+    pub fn encode_load(self, permille: i32) -> [u8; 2] {
+        self.encode_u16(encode_sign_magnitude(permille, 10))
+    }
+
+    pub fn decode_load(self, bytes: [u8; 2]) -> i32 {
+        decode_sign_magnitude(self.decode_u16(bytes), 10)
+    }
+}
+
+fn encode_sign_magnitude(value: i32, sign_bit: u32) -> u16 {
+    let max = (1i32 << sign_bit) - 1;
+    let magnitude = value.unsigned_abs().min(max as u32) as u16;
+    if value < 0 {
+        magnitude | (1 << sign_bit)
+    } else {
+        magnitude
+    }
+}
+
+fn decode_sign_magnitude(raw: u16, sign_bit: u32) -> i32 {
+    let magnitude = (raw & ((1 << sign_bit) - 1)) as i32;
+    if raw & (1 << sign_bit) != 0 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+pub fn rad_to_steps(rad: f32) -> i32 {
+    (rad * STEPS_PER_REV / std::f32::consts::TAU).round() as i32
+}
+
+pub fn steps_to_rad(steps: i32) -> f32 {
+    steps as f32 * std::f32::consts::TAU / STEPS_PER_REV
+}
+
+pub fn checksum(body: &[u8]) -> u8 {
+    let sum = body.iter().fold(0u32, |acc, b| acc + *b as u32);
+    !(sum as u8)
+}
+
+pub fn encode_packet(id: u8, instruction: Instruction, params: &[u8]) -> Vec<u8> {
+    encode_frame(id, instruction as u8, params)
+}
+
+/// Frames `0xFF 0xFF ID LEN <code> PARAMS CHECKSUM`, where `code` is the
+/// instruction for requests and the error byte for status replies.
+pub fn encode_frame(id: u8, code: u8, params: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(params.len() + 6);
+    packet.extend_from_slice(&HEADER);
+    packet.push(id);
+    packet.push((params.len() + 2) as u8);
+    packet.push(code);
+    packet.extend_from_slice(params);
+    packet.push(checksum(&packet[2..]));
+    packet
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub id: u8,
+    pub code: u8,
+    pub params: Vec<u8>,
+}
+
+/// Decodes one frame from the front of `buf`, returning it with the number of
+/// bytes consumed. `Ok(None)` means more bytes are needed.
+pub fn decode_frame(buf: &[u8]) -> Result<Option<(Frame, usize)>> {
+    let Some(start) = buf.windows(2).position(|w| w == HEADER) else {
+        return Ok(None);
+    };
+    let buf = &buf[start..];
+    if buf.len() < 4 {
+        return Ok(None);
+    }
+    let (id, len) = (buf[2], buf[3] as usize);
+    ensure!(len >= 2, "invalid packet length {len} from servo {id}");
+    let total = 4 + len;
+    if buf.len() < total {
+        return Ok(None);
+    }
+
+    let expected = buf[total - 1];
+    let actual = checksum(&buf[2..total - 1]);
+    ensure!(
+        actual == expected,
+        "checksum mismatch from servo {id}: expected {expected:#04x}, got {actual:#04x}"
+    );
+
+    let frame = Frame {
+        id,
+        code: buf[4],
+        params: buf[5..total - 1].to_vec(),
+    };
+    Ok(Some((frame, start + total)))
+}
+
+/// Reads one frame from the stream, skipping any noise before the header.
+pub fn read_frame<R: Read + ?Sized>(reader: &mut R) -> Result<Frame> {
+    let mut previous = 0u8;
+    let mut scanned = 0usize;
+    loop {
+        let mut byte = [0u8; 1];
+        reader
+            .read_exact(&mut byte)
+            .context("servo bus read failed")?;
+        if previous == HEADER[0] && byte[0] == HEADER[1] {
+            break;
+        }
+        previous = byte[0];
+        scanned += 1;
+        ensure!(scanned < MAX_SCAN_BYTES, "no packet header on servo bus");
+    }
+
+    let mut id_len = [0u8; 2];
+    reader
+        .read_exact(&mut id_len)
+        .context("servo bus read failed")?;
+    let [id, len] = id_len;
+    ensure!(len >= 2, "invalid packet length {len} from servo {id}");
+
+    let mut rest = vec![0u8; len as usize];
+    reader
+        .read_exact(&mut rest)
+        .context("servo bus read failed")?;
+    let expected = rest[rest.len() - 1];
+    let mut body = vec![id, len];
+    body.extend_from_slice(&rest[..rest.len() - 1]);
+    let actual = checksum(&body);
+    ensure!(
+        actual == expected,
+        "checksum mismatch from servo {id}: expected {expected:#04x}, got {actual:#04x}"
+    );
+
+    Ok(Frame {
+        id,
+        code: rest[0],
+        params: rest[1..rest.len() - 1].to_vec(),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ServoReading {
+    pub position_steps: u16,
+    pub speed_steps_s: i32,
+    pub load_permille: i32,
+    pub voltage_v: f32,
+    pub temperature_c: f32,
+    pub error: u8,
+}
+
+impl ServoReading {
+    pub fn decode(protocol: Protocol, block: &[u8], error: u8) -> Result<Self> {
+        ensure!(
+            block.len() >= reg::STATE_BLOCK_LEN as usize,
+            "short state block ({} bytes)",
+            block.len()
+        );
+        Ok(Self {
+            position_steps: protocol.decode_u16([block[0], block[1]]) % STEPS_PER_REV as u16,
+            speed_steps_s: protocol.decode_speed([block[2], block[3]]),
+            load_permille: protocol.decode_load([block[4], block[5]]),
+            voltage_v: block[6] as f32 * 0.1,
+            temperature_c: block[7] as f32,
+            error,
+        })
+    }
+
+    pub fn position_rad(&self) -> f32 {
+        steps_to_rad(self.position_steps as i32)
+    }
+
+    pub fn velocity_rad_s(&self) -> f32 {
+        steps_to_rad(self.speed_steps_s)
+    }
+
+    pub fn load(&self) -> f32 {
+        self.load_permille as f32 / 1000.0
+    }
+}
+
+pub struct FeetechBus {
+    port: Box<dyn ServoTransport>,
+    protocol: Protocol,
+}
+
+impl FeetechBus {
+    pub fn new(port: Box<dyn ServoTransport>, protocol: Protocol) -> Self {
+        Self { port, protocol }
+    }
+
+    pub fn describe(&self) -> String {
+        self.port.describe()
+    }
+
+    pub fn ping(&mut self, id: u8) -> Result<u8> {
+        self.send(id, Instruction::Ping, &[])?;
+        Ok(self.read_status(id)?.code)
+    }
+
+    pub fn read(&mut self, id: u8, address: u8, len: u8) -> Result<Vec<u8>> {
+        self.send(id, Instruction::Read, &[address, len])?;
+        let status = self.read_status(id)?;
+        ensure!(
+            status.params.len() == len as usize,
+            "servo {id} returned {} bytes, expected {len}",
+            status.params.len()
+        );
+        Ok(status.params)
+    }
+
+    pub fn write(&mut self, id: u8, address: u8, data: &[u8]) -> Result<()> {
+        let mut params = Vec::with_capacity(data.len() + 1);
+        params.push(address);
+        params.extend_from_slice(data);
+        self.send(id, Instruction::Write, &params)?;
+        if id != BROADCAST_ID {
+            self.read_status(id)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_u8(&mut self, id: u8, address: u8, value: u8) -> Result<()> {
+        self.write(id, address, &[value])
+    }
+
+    pub fn write_u16(&mut self, id: u8, address: u8, value: u16) -> Result<()> {
+        let bytes = self.protocol.encode_u16(value);
+        self.write(id, address, &bytes)
+    }
+
+    pub fn sync_write(&mut self, address: u8, len: u8, entries: &[(u8, Vec<u8>)]) -> Result<()> {
+        let mut params = Vec::with_capacity(2 + entries.len() * (len as usize + 1));
+        params.push(address);
+        params.push(len);
+        for (id, data) in entries {
+            ensure!(
+                data.len() == len as usize,
+                "sync write entry for servo {id} has {} bytes, expected {len}",
+                data.len()
+            );
+            params.push(*id);
+            params.extend_from_slice(data);
+        }
+        self.send(BROADCAST_ID, Instruction::SyncWrite, &params)
+    }
+
+    /// Each servo answers in the order listed; a silent servo yields an error
+    /// for its slot without aborting the others.
+    pub fn sync_read(&mut self, address: u8, len: u8, ids: &[u8]) -> Result<Vec<Result<Frame>>> {
+        let mut params = Vec::with_capacity(2 + ids.len());
+        params.push(address);
+        params.push(len);
+        params.extend_from_slice(ids);
+        self.send(BROADCAST_ID, Instruction::SyncRead, &params)?;
+
+        Ok(ids.iter().map(|id| self.read_status(*id)).collect())
+    }
+
+    pub fn set_wheel_mode(&mut self, id: u8) -> Result<()> {
+        match self.protocol {
+            Protocol::Sts => {
+                let mode = self.read(id, reg::OPERATING_MODE, 1)?;
+                if mode[0] == reg::MODE_WHEEL {
+                    return Ok(());
+                }
+                self.write_u8(id, reg::OPERATING_MODE, reg::MODE_WHEEL)
+            }
+            Protocol::Scs => {
+                self.write_u16(id, reg::MIN_ANGLE_LIMIT, 0)?;
+                self.write_u16(id, reg::MAX_ANGLE_LIMIT, 0)
+            }
+        }
+    }
+
+    pub fn set_torque(&mut self, id: u8, enabled: bool) -> Result<()> {
+        self.write_u8(id, reg::TORQUE_ENABLE, enabled as u8)
+    }
+
+    pub fn write_goal_speeds(&mut self, targets: &[(u8, f32)]) -> Result<()> {
+        let entries: Vec<(u8, Vec<u8>)> = targets
+            .iter()
+            .map(|(id, rad_s)| {
+                (
+                    *id,
+                    self.protocol.encode_speed(rad_to_steps(*rad_s)).to_vec(),
+                )
+            })
+            .collect();
+        self.sync_write(reg::GOAL_SPEED, 2, &entries)
+    }
+
+    pub fn read_states(&mut self, ids: &[u8]) -> Result<Vec<(u8, Result<ServoReading>)>> {
+        let protocol = self.protocol;
+        let replies = self.sync_read(reg::PRESENT_POSITION, reg::STATE_BLOCK_LEN, ids)?;
+        Ok(ids
+            .iter()
+            .zip(replies)
+            .map(|(id, reply)| {
+                let reading = reply
+                    .and_then(|frame| ServoReading::decode(protocol, &frame.params, frame.code));
+                (*id, reading)
+            })
+            .collect())
+    }
+
+    fn send(&mut self, id: u8, instruction: Instruction, params: &[u8]) -> Result<()> {
+        let packet = encode_packet(id, instruction, params);
+        self.port.discard_input()?;
+        self.port
+            .write_all(&packet)
+            .and_then(|_| self.port.flush())
+            .context("servo bus write failed")
+    }
+
+    fn read_status(&mut self, id: u8) -> Result<Frame> {
+        let frame =
+            read_frame(&mut self.port).with_context(|| format!("no reply from servo {id}"))?;
+        ensure!(
+            frame.id == id,
+            "unexpected reply from servo {} while waiting for {id}",
+            frame.id
+        );
+        Ok(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ping_packet_matches_datasheet() {
+        assert_eq!(
+            encode_packet(1, Instruction::Ping, &[]),
+            vec![0xFF, 0xFF, 0x01, 0x02, 0x01, 0xFB]
+        );
+        assert_eq!(
+            encode_packet(1, Instruction::Read, &[reg::PRESENT_POSITION, 2]),
+            vec![0xFF, 0xFF, 0x01, 0x04, 0x02, 0x38, 0x02, 0xBE]
+        );
+    }
+
+    #[test]
+    fn checksum_wraps_past_a_byte() {
+        assert_eq!(checksum(&[0xFE, 0x04, 0x03, 0x28, 0x01]), !0x2Eu8);
+        assert_eq!(checksum(&[]), 0xFF);
+    }
+
+    #[test]
+    fn frame_round_trip_after_noise() {
+        let mut buf = vec![0x00, 0xFF, 0x12];
+        let packet = encode_frame(7, 0x20, &[1, 2, 3]);
+        buf.extend(&packet);
+
+        let (frame, consumed) = decode_frame(&buf).unwrap().unwrap();
+        assert_eq!(consumed, buf.len());
+        assert_eq!(
+            frame,
+            Frame {
+                id: 7,
+                code: 0x20,
+                params: vec![1, 2, 3],
+            }
+        );
+        assert_eq!(read_frame(&mut buf.as_slice()).unwrap(), frame);
+    }
+
+    #[test]
+    fn partial_frame_waits_for_more_bytes() {
+        let packet = encode_frame(3, 0, &[9, 9]);
+        for len in 0..packet.len() {
+            assert!(decode_frame(&packet[..len]).unwrap().is_none());
+        }
+        assert!(read_frame(&mut &packet[..packet.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn corrupt_frame_is_rejected() {
+        let mut packet = encode_frame(3, 0, &[9, 9]);
+        packet[5] ^= 0x01;
+        assert!(decode_frame(&packet).is_err());
+        assert!(read_frame(&mut packet.as_slice()).is_err());
+        assert!(decode_frame(&[0xFF, 0xFF, 0x01, 0x01, 0x00]).is_err());
+    }
+
+    #[test]
+    fn read_frame_gives_up_on_noise() {
+        let noise = vec![0x55; MAX_SCAN_BYTES + 8];
+        assert!(read_frame(&mut noise.as_slice()).is_err());
+    }
+
+    #[test]
+    fn sts_speed_is_little_endian_with_bit_15_sign() {
+        let sts = Protocol::Sts;
+        assert_eq!(sts.encode_speed(100), [0x64, 0x00]);
+        assert_eq!(sts.encode_speed(-100), [0x64, 0x80]);
+        assert_eq!(sts.encode_speed(-40_000), [0xFF, 0xFF]);
+        for steps in [0, 1, -1, 3400, -3400] {
+            assert_eq!(sts.decode_speed(sts.encode_speed(steps)), steps);
+        }
+    }
+
+    #[test]
+    fn scs_speed_is_big_endian_with_bit_10_sign() {
+        let scs = Protocol::Scs;
+        assert_eq!(scs.encode_speed(100), [0x00, 0x64]);
+        assert_eq!(scs.encode_speed(-100), [0x04, 0x64]);
+        assert_eq!(scs.encode_speed(5000), [0x03, 0xFF]);
+        for steps in [0, 1, -1, 1023, -1023] {
+            assert_eq!(scs.decode_speed(scs.encode_speed(steps)), steps);
+        }
+    }
+
+    #[test]
+    fn load_sign_is_bit_10() {
+        for protocol in [Protocol::Sts, Protocol::Scs] {
+            assert_eq!(
+                protocol.decode_u16(protocol.encode_load(-300)),
+                0x0400 | 300
+            );
+            assert_eq!(protocol.decode_load(protocol.encode_load(-300)), -300);
+            assert_eq!(protocol.decode_load(protocol.encode_load(2000)), 1023);
+        }
+    }
+
+    #[test]
+    fn decodes_state_block() {
+        let protocol = Protocol::Sts;
+        let mut block = Vec::new();
+        block.extend(protocol.encode_u16(4096 + 1024));
+        block.extend(protocol.encode_speed(-512));
+        block.extend(protocol.encode_load(250));
+        block.extend([121, 35]);
+
+        let reading = ServoReading::decode(protocol, &block, error_bits::OVERHEAT).unwrap();
+        assert_eq!(reading.position_steps, 1024);
+        assert_eq!(reading.speed_steps_s, -512);
+        assert_eq!(reading.load(), 0.25);
+        assert!((reading.voltage_v - 12.1).abs() < 1e-6);
+        assert_eq!(reading.temperature_c, 35.0);
+        assert!((reading.velocity_rad_s() + std::f32::consts::FRAC_PI_4).abs() < 1e-6);
+        assert!(ServoReading::decode(protocol, &block[..7], 0).is_err());
+    }
+
+    #[test]
+    fn names_error_bits() {
+        assert!(error_bits::names(0).is_empty());
+        assert_eq!(
+            error_bits::names(error_bits::OVERHEAT | error_bits::OVERLOAD | 0x10),
+            vec!["overheat", "error_bit_4", "overload"]
+        );
+    }
+
+    #[test]
+    fn radians_and_steps_agree() {
+        assert_eq!(rad_to_steps(std::f32::consts::TAU), 4096);
+        assert_eq!(rad_to_steps(-std::f32::consts::PI), -2048);
+        assert_eq!(steps_to_rad(2048), std::f32::consts::PI);
+    }
+}
@@ -0,0 +1,3 @@
+pub mod feetech;
+pub mod sim;
+pub mod transport;
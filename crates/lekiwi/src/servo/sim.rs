@@ -0,0 +1,467 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use serialport::{SerialPort, TTYPort};
+use tokio::sync::watch;
+
+use crate::config::RobotConfig;
+use crate::servo::feetech::{
+    decode_frame, encode_frame, reg, Frame, Instruction, Protocol, BROADCAST_ID, STEPS_PER_REV,
+};
+
+const MEMORY_SIZE: usize = 128;
+const MAX_SPEED_STEPS_S: f64 = 3400.0;
+const NOMINAL_VOLTAGE_DV: u8 = 120;
+const AMBIENT_TEMP_C: f64 = 28.0;
+const PTY_POLL: Duration = Duration::from_millis(50);
+
+/// A fake STS3215 bus that answers instruction packets with plausible state.
+/// Wheel-mode servos with torque enabled integrate their goal speed into
+/// position, and report load and temperature that grow with speed.
+pub struct SimulatedBus {
+    protocol: Protocol,
+    servos: BTreeMap<u8, SimServo>,
+    rx: Vec<u8>,
+}
+
+impl SimulatedBus {
+    pub fn new(protocol: Protocol, ids: &[u8]) -> Self {
+        let servos = ids
+            .iter()
+            .map(|id| (*id, SimServo::new(*id, protocol)))
+            .collect();
+        Self {
+            protocol,
+            servos,
+            rx: Vec::new(),
+        }
+    }
+
+    /// Feeds raw bytes from the host and returns every reply they produced.
+    pub fn handle_bytes(&mut self, input: &[u8]) -> Vec<u8> {
+        self.rx.extend_from_slice(input);
+        let mut replies = Vec::new();
+
+        loop {
+            match decode_frame(&self.rx) {
+                Ok(Some((frame, consumed))) => {
+                    self.rx.drain(..consumed);
+                    self.handle_frame(&frame, &mut replies);
+                }
+                Ok(None) => {
+                    // Keep only what can still become a packet: from the
+                    // first header on, or a trailing half header.
+                    let start = self
+                        .rx
+                        .windows(2)
+                        .position(|w| w == [0xFF, 0xFF])
+                        .unwrap_or(self.rx.len() - usize::from(self.rx.last() == Some(&0xFF)));
+                    self.rx.drain(..start);
+                    break;
+                }
+                Err(err) => {
+                    tracing::debug!("Simulated servo bus dropped packet: {err:#}");
+                    let skip = self
+                        .rx
+                        .windows(2)
+                        .position(|w| w == [0xFF, 0xFF])
+                        .unwrap_or(0);
+                    self.rx.drain(..skip + 2);
+                }
+            }
+        }
+
+        replies
+    }
+
+    fn handle_frame(&mut self, frame: &Frame, replies: &mut Vec<u8>) {
+        let Some(instruction) = Instruction::from_byte(frame.code) else {
+            return;
+        };
+        let protocol = self.protocol;
+
+        match instruction {
+            Instruction::Ping => {
+                if self.servos.contains_key(&frame.id) {
+                    replies.extend(encode_frame(frame.id, 0, &[]));
+                }
+            }
+            Instruction::Read => {
+                if let (Some(servo), [address, len]) =
+                    (self.servos.get_mut(&frame.id), frame.params.as_slice())
+                {
+                    servo.step(protocol);
+                    replies.extend(encode_frame(frame.id, 0, servo.slice(*address, *len)));
+                }
+            }
+            Instruction::Write => {
+                let Some((address, data)) = frame.params.split_first() else {
+                    return;
+                };
+                if frame.id == BROADCAST_ID {
+                    for servo in self.servos.values_mut() {
+                        servo.write(protocol, *address, data);
+                    }
+                } else if let Some(servo) = self.servos.get_mut(&frame.id) {
+                    servo.write(protocol, *address, data);
+                    replies.extend(encode_frame(frame.id, 0, &[]));
+                }
+            }
+            Instruction::SyncWrite => {
+                let [address, len, entries @ ..] = frame.params.as_slice() else {
+                    return;
+                };
+                let stride = *len as usize + 1;
+                for entry in entries.chunks_exact(stride) {
+                    if let Some(servo) = self.servos.get_mut(&entry[0]) {
+                        servo.write(protocol, *address, &entry[1..]);
+                    }
+                }
+            }
+            Instruction::SyncRead => {
+                let [address, len, ids @ ..] = frame.params.as_slice() else {
+                    return;
+                };
+                for id in ids {
+                    if let Some(servo) = self.servos.get_mut(id) {
+                        servo.step(protocol);
+                        replies.extend(encode_frame(*id, 0, servo.slice(*address, *len)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct SimServo {
+    memory: [u8; MEMORY_SIZE],
+    position_steps: f64,
+    temperature_c: f64,
+    last_step: Instant,
+}
+
+impl SimServo {
+    fn new(id: u8, protocol: Protocol) -> Self {
+        let mut servo = Self {
+            memory: [0; MEMORY_SIZE],
+            position_steps: 2048.0,
+            temperature_c: AMBIENT_TEMP_C,
+            last_step: Instant::now(),
+        };
+        servo.memory[reg::ID as usize] = id;
+        servo.memory[reg::PRESENT_VOLTAGE as usize] = NOMINAL_VOLTAGE_DV;
+        servo.step(protocol);
+        servo
+    }
+
+    fn slice(&self, address: u8, len: u8) -> &[u8] {
+        let start = (address as usize).min(MEMORY_SIZE);
+        let end = (start + len as usize).min(MEMORY_SIZE);
+        &self.memory[start..end]
+    }
+
+    fn write(&mut self, protocol: Protocol, address: u8, data: &[u8]) {
+        self.step(protocol);
+        let start = address as usize;
+        for (offset, byte) in data.iter().enumerate() {
+            if let Some(slot) = self.memory.get_mut(start + offset) {
+                *slot = *byte;
+            }
+        }
+    }
+
+    fn get(&self, address: u8) -> [u8; 2] {
+        let address = address as usize;
+        [self.memory[address], self.memory[address + 1]]
+    }
+
+    fn put(&mut self, address: u8, bytes: [u8; 2]) {
+        let address = address as usize;
+        self.memory[address..address + 2].copy_from_slice(&bytes);
+    }
+
+    fn wheel_mode(&self, protocol: Protocol) -> bool {
+        match protocol {
+            Protocol::Sts => self.memory[reg::OPERATING_MODE as usize] == reg::MODE_WHEEL,
+            Protocol::Scs => {
+                self.get(reg::MIN_ANGLE_LIMIT) == [0, 0] && self.get(reg::MAX_ANGLE_LIMIT) == [0, 0]
+            }
+        }
+    }
+
+    fn step(&mut self, protocol: Protocol) {
+        let now = Instant::now();
+        let dt = now.duration_since(self.last_step).as_secs_f64();
+        self.last_step = now;
+
+        let torque = self.memory[reg::TORQUE_ENABLE as usize] != 0;
+        let speed = if torque && self.wheel_mode(protocol) {
+            let goal = protocol.decode_speed(self.get(reg::GOAL_SPEED)) as f64;
+            goal.clamp(-MAX_SPEED_STEPS_S, MAX_SPEED_STEPS_S)
+        } else {
+            0.0
+        };
+
+        self.position_steps = (self.position_steps + speed * dt).rem_euclid(STEPS_PER_REV as f64);
+        let effort = speed.abs() / MAX_SPEED_STEPS_S;
+        let load = if torque {
+            (speed.signum() * (40.0 + 260.0 * effort)) as i32
+        } else {
+            0
+        };
+        let target_temp = AMBIENT_TEMP_C + 15.0 * effort;
+        self.temperature_c += (target_temp - self.temperature_c) * (dt / 60.0).min(1.0);
+
+        self.put(
+            reg::PRESENT_POSITION,
+            protocol.encode_u16(self.position_steps as u16),
+        );
+        self.put(reg::PRESENT_SPEED, protocol.encode_speed(speed as i32));
+        self.put(reg::PRESENT_LOAD, protocol.encode_load(load));
+        self.memory[reg::PRESENT_TEMPERATURE as usize] = self.temperature_c.round() as u8;
+    }
+}
+
+/// In-memory transport that hands every write straight to a simulated bus.
+/// Reads with nothing pending time out like an idle serial port.
+pub struct LoopbackTransport {
+    sim: SimulatedBus,
+    pending: Vec<u8>,
+}
+
+impl LoopbackTransport {
+    pub fn new(sim: SimulatedBus) -> Self {
+        Self {
+            sim,
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl Read for LoopbackTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "loopback idle"));
+        }
+        let len = buf.len().min(self.pending.len());
+        buf[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        Ok(len)
+    }
+}
+
+impl Write for LoopbackTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let replies = self.sim.handle_bytes(buf);
+        self.pending.extend(replies);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Opens a pseudo-terminal pair and serves a simulated bus on the master side
+/// from a background thread. The returned slave behaves like a USB serial
+/// adapter; the thread exits when `stop` is set or the slave is closed.
+pub fn spawn_pty(protocol: Protocol, ids: &[u8], stop: Arc<AtomicBool>) -> Result<TTYPort> {
+    let (mut master, mut slave) = TTYPort::pair().context("unable to open pseudo-terminal")?;
+    slave
+        .set_exclusive(false)
+        .context("unable to share pseudo-terminal")?;
+    master.set_timeout(PTY_POLL)?;
+
+    let mut sim = SimulatedBus::new(protocol, ids);
+    std::thread::Builder::new()
+        .name("servo-sim".to_string())
+        .spawn(move || serve_pty(&mut master, &mut sim, &stop))
+        .context("unable to start servo simulator thread")?;
+
+    Ok(slave)
+}
+
+fn serve_pty(master: &mut TTYPort, sim: &mut SimulatedBus, stop: &AtomicBool) {
+    let mut buf = [0u8; 256];
+    while !stop.load(Ordering::Relaxed) {
+        match master.read(&mut buf) {
+            Ok(0) => continue,
+            Ok(len) => {
+                let replies = sim.handle_bytes(&buf[..len]);
+                if !replies.is_empty() && master.write_all(&replies).is_err() {
+                    break;
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => continue,
+            Err(err) => {
+                tracing::debug!("Simulated servo bus closed: {err}");
+                break;
+            }
+        }
+    }
+}
+
+/// Serves a simulated bus for the configured wheel servos on a PTY until
+/// shutdown, so a separate `motor-bus` process can open it as its serial port.
+pub async fn run_pty_server(robot: RobotConfig, mut shutdown: watch::Receiver<bool>) -> Result<()> {
+    let protocol = Protocol::from_config(&robot.servo_bus.protocol)?;
+    let ids: Vec<u8> = robot
+        .drive
+        .wheel_mounts
+        .iter()
+        .map(|mount| mount.servo_id)
+        .collect();
+    let stop = Arc::new(AtomicBool::new(false));
+    let slave = spawn_pty(protocol, &ids, stop.clone())?;
+    let path = slave.name().unwrap_or_default();
+    tracing::info!("Simulated servo bus for ids {ids:?} on {path}");
+
+    loop {
+        if *shutdown.borrow() {
+            break;
+        }
+        if shutdown.changed().await.is_err() {
+            break;
+        }
+    }
+
+    stop.store(true, Ordering::Relaxed);
+    drop(slave);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::servo::feetech::{encode_packet, rad_to_steps, FeetechBus};
+
+    const IDS: [u8; 3] = [1, 2, 3];
+
+    fn bus(protocol: Protocol) -> FeetechBus {
+        let sim = SimulatedBus::new(protocol, &IDS);
+        FeetechBus::new(Box::new(LoopbackTransport::new(sim)), protocol)
+    }
+
+    #[test]
+    fn ping_answers_only_known_ids() {
+        let mut bus = bus(Protocol::Sts);
+        for id in IDS {
+            assert_eq!(bus.ping(id).unwrap(), 0);
+        }
+        assert!(bus.ping(9).is_err());
+    }
+
+    #[test]
+    fn read_returns_written_registers() {
+        for protocol in [Protocol::Sts, Protocol::Scs] {
+            let mut bus = bus(protocol);
+            bus.write_u16(2, reg::MIN_ANGLE_LIMIT, 0x0123).unwrap();
+            let bytes = bus.read(2, reg::MIN_ANGLE_LIMIT, 2).unwrap();
+            assert_eq!(protocol.decode_u16([bytes[0], bytes[1]]), 0x0123);
+            assert_eq!(bus.read(2, reg::ID, 1).unwrap(), vec![2]);
+        }
+    }
+
+    #[test]
+    fn wheel_speeds_round_trip() {
+        for protocol in [Protocol::Sts, Protocol::Scs] {
+            let mut bus = bus(protocol);
+            for id in IDS {
+                bus.set_wheel_mode(id).unwrap();
+                bus.set_torque(id, true).unwrap();
+            }
+            let targets = [(1, 1.0), (2, -1.5), (3, 0.0)];
+            bus.write_goal_speeds(&targets).unwrap();
+
+            let states = bus.read_states(&IDS).unwrap();
+            for ((id, state), (target_id, rad_s)) in states.into_iter().zip(targets) {
+                assert_eq!(id, target_id);
+                let state = state.unwrap();
+                assert_eq!(state.speed_steps_s, rad_to_steps(rad_s), "{protocol:?}");
+                assert_eq!(state.load_permille.signum(), rad_s.signum() as i32);
+                assert_eq!(state.voltage_v, NOMINAL_VOLTAGE_DV as f32 * 0.1);
+                assert_eq!(state.error, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn torque_off_stops_the_wheel() {
+        let mut bus = bus(Protocol::Sts);
+        bus.set_wheel_mode(1).unwrap();
+        bus.set_torque(1, true).unwrap();
+        bus.write_goal_speeds(&[(1, 2.0)]).unwrap();
+        bus.set_torque(1, false).unwrap();
+
+        let (_, state) = bus.read_states(&[1]).unwrap().remove(0);
+        let state = state.unwrap();
+        assert_eq!(state.speed_steps_s, 0);
+        assert_eq!(state.load_permille, 0);
+    }
+
+    #[test]
+    fn sync_read_skips_missing_servo() {
+        let mut bus = bus(Protocol::Sts);
+        let states = bus.read_states(&[1, 9]).unwrap();
+        assert!(states[0].1.is_ok());
+        assert!(states[1].1.is_err());
+    }
+
+    #[test]
+    fn corrupt_packet_is_dropped_and_next_answered() {
+        let mut sim = SimulatedBus::new(Protocol::Sts, &IDS);
+        let mut corrupt = encode_packet(1, Instruction::Ping, &[]);
+        *corrupt.last_mut().unwrap() ^= 0xFF;
+        let mut input = corrupt;
+        input.extend(encode_packet(2, Instruction::Ping, &[]));
+
+        let replies = sim.handle_bytes(&input);
+        let (frame, consumed) = decode_frame(&replies).unwrap().unwrap();
+        assert_eq!(consumed, replies.len());
+        assert_eq!((frame.id, frame.code), (2, 0));
+    }
+
+    #[test]
+    fn noise_without_a_header_is_not_buffered() {
+        let mut sim = SimulatedBus::new(Protocol::Sts, &IDS);
+        for _ in 0..100 {
+            assert!(sim.handle_bytes(&[0x12, 0x34, 0x56, 0x78]).is_empty());
+        }
+        assert!(sim.rx.is_empty());
+
+        // A header split across reads survives the trim.
+        let packet = encode_packet(2, Instruction::Ping, &[]);
+        assert!(sim.handle_bytes(&[0x12, packet[0]]).is_empty());
+        assert_eq!(sim.rx, [0xFF]);
+        let replies = sim.handle_bytes(&packet[1..]);
+        let (frame, _) = decode_frame(&replies).unwrap().unwrap();
+        assert_eq!(frame.id, 2);
+        assert!(sim.rx.is_empty());
+    }
+
+    #[test]
+    fn partial_packet_waits_for_the_rest() {
+        let mut sim = SimulatedBus::new(Protocol::Sts, &IDS);
+        let packet = encode_packet(3, Instruction::Ping, &[]);
+        let mut input = vec![0x00, 0x01];
+        input.extend_from_slice(&packet[..4]);
+        assert!(sim.handle_bytes(&input).is_empty());
+        assert_eq!(sim.rx, packet[..4]);
+        assert!(!sim.handle_bytes(&packet[4..]).is_empty());
+    }
+
+    #[test]
+    fn broadcast_write_gets_no_reply() {
+        let mut sim = SimulatedBus::new(Protocol::Sts, &IDS);
+        let packet = encode_packet(BROADCAST_ID, Instruction::Write, &[reg::TORQUE_ENABLE, 1]);
+        assert!(sim.handle_bytes(&packet).is_empty());
+        assert!(sim
+            .servos
+            .values()
+            .all(|servo| servo.memory[reg::TORQUE_ENABLE as usize] == 1));
+    }
+}
@@ -0,0 +1,108 @@
+use std::io::{Read, Write};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serialport::{ClearBuffer, SerialPort};
+
+use crate::config::{ServoBusConfig, ServoTransportKind};
+use crate::servo::feetech::Protocol;
+use crate::servo::sim::{self, LoopbackTransport, SimulatedBus};
+
+pub const SERIAL_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// Byte stream the Feetech driver talks over. Reads must time out rather than
+/// block forever so a silent servo surfaces as an error.
+pub trait ServoTransport: Read + Write + Send {
+    fn describe(&self) -> String;
+
+    /// Drops stale bytes (late replies, line noise) before a new transaction.
+    fn discard_input(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl ServoTransport for Box<dyn SerialPort> {
+    fn describe(&self) -> String {
+        self.name().unwrap_or_else(|| "serial".to_string())
+    }
+
+    fn discard_input(&mut self) -> Result<()> {
+        self.clear(ClearBuffer::Input)?;
+        Ok(())
+    }
+}
+
+impl ServoTransport for LoopbackTransport {
+    fn describe(&self) -> String {
+        "loopback".to_string()
+    }
+}
+
+/// Keeps the simulator thread behind a PTY alive for as long as the driver
+/// holds the slave end.
+pub struct PtySimTransport {
+    port: Box<dyn SerialPort>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Read for PtySimTransport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.port.read(buf)
+    }
+}
+
+impl Write for PtySimTransport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.port.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl ServoTransport for PtySimTransport {
+    fn describe(&self) -> String {
+        format!("simulated {}", self.port.describe())
+    }
+
+    fn discard_input(&mut self) -> Result<()> {
+        self.port.discard_input()
+    }
+}
+
+impl Drop for PtySimTransport {
+    fn drop(&mut self) {
+        self.stop.store(true, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+pub fn open(
+    config: &ServoBusConfig,
+    protocol: Protocol,
+    ids: &[u8],
+) -> Result<Box<dyn ServoTransport>> {
+    match config.transport {
+        ServoTransportKind::Serial => {
+            let port = serialport::new(&config.port, config.baud_rate)
+                .timeout(SERIAL_TIMEOUT)
+                .open()
+                .with_context(|| format!("unable to open {}", config.port))?;
+            Ok(Box::new(port))
+        }
+        ServoTransportKind::Loopback => Ok(Box::new(LoopbackTransport::new(SimulatedBus::new(
+            protocol, ids,
+        )))),
+        ServoTransportKind::PtySim => {
+            let stop = Arc::new(AtomicBool::new(false));
+            let mut slave = sim::spawn_pty(protocol, ids, stop.clone())?;
+            slave.set_timeout(SERIAL_TIMEOUT)?;
+            Ok(Box::new(PtySimTransport {
+                port: Box::new(slave),
+                stop,
+            }))
+        }
+    }
+}
@@ -1,99 +1,235 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use anyhow::Result;
-use foxglove::{ChannelBuilder, Context, PartialMetadata, RawChannel};
+use foxglove::schemas::{CameraCalibration, CompressedImage, FrameTransforms, SceneUpdate};
+use foxglove::{Channel, ChannelBuilder, Context, Encode, PartialMetadata, RawChannel};
 use serde::Serialize;
 
+use crate::config::{CameraConfig, CamerasConfig};
 use crate::messages::{
-    CameraFrame, Diagnostics, EstopCommand, LogControl, LogStatus, Odometry, PowerState,
-    ServoStateArray, SkillCommand, VelocityCommand,
+    CameraFrame, CameraStats, Diagnostics, EstopCommand, EstopReset, EstopState, Imu, LogControl,
+    LogStatus, Odometry, PoseReset, PowerState, SafetyEvent, ServoBusHealth, ServoStateArray,
+    SetPoseCommand, SkillCancel, SkillCommand, SkillStatus, SpeedScaleCommand, VelocityCommand,
+    WheelTargetArray,
 };
+use crate::utils::foxglove_timestamp;
 
 pub const TOPIC_CMD_VELOCITY: &str = "/cmd/velocity";
+pub const TOPIC_CMD_WHEELS: &str = "/cmd/wheels";
 pub const TOPIC_CMD_SKILL: &str = "/cmd/skill";
+pub const TOPIC_CMD_SKILL_CANCEL: &str = "/cmd/skill_cancel";
+pub const TOPIC_SKILL_STATUS: &str = "/skill/status";
 pub const TOPIC_CMD_ESTOP: &str = "/cmd/estop";
+pub const TOPIC_CMD_ESTOP_RESET: &str = "/cmd/estop_reset";
+pub const TOPIC_CMD_SPEED_SCALE: &str = "/cmd/speed_scale";
+pub const TOPIC_CMD_SET_POSE: &str = "/cmd/set_pose";
 pub const TOPIC_STATE_ODOM: &str = "/state/odometry";
+pub const TOPIC_STATE_POSE_RESET: &str = "/state/pose_reset";
 pub const TOPIC_STATE_SERVOS: &str = "/state/servos";
+pub const TOPIC_STATE_SERVO_BUS: &str = "/state/servo_bus";
 pub const TOPIC_STATE_POWER: &str = "/state/power";
+pub const TOPIC_STATE_ESTOP: &str = "/state/estop";
 pub const TOPIC_SYSTEM_DIAG: &str = "/system/diagnostics";
-pub const TOPIC_CAMERA_BASE: &str = "/sensors/camera/base";
-pub const TOPIC_CAMERA_WRIST: &str = "/sensors/camera/wrist";
+pub const TOPIC_SAFETY_EVENT: &str = "/safety/event";
+pub const TOPIC_TF: &str = "/tf";
+pub const TOPIC_SCENE_GEOFENCE: &str = "/scene/geofence";
+pub const TOPIC_SENSORS_IMU: &str = "/sensors/imu";
 pub const TOPIC_LOG_CONTROL: &str = "/log/control";
 pub const TOPIC_LOG_STATUS: &str = "/log/status";
 
 #[derive(Clone)]
 pub struct Telemetry {
-    ctx: Arc<Context>,
     cmd_velocity: Arc<RawChannel>,
+    cmd_wheels: Arc<RawChannel>,
     cmd_skill: Arc<RawChannel>,
+    cmd_skill_cancel: Arc<RawChannel>,
+    skill_status: Arc<RawChannel>,
     cmd_estop: Arc<RawChannel>,
+    cmd_estop_reset: Arc<RawChannel>,
+    cmd_speed_scale: Arc<RawChannel>,
+    cmd_set_pose: Arc<RawChannel>,
     odometry: Arc<RawChannel>,
+    pose_reset: Arc<RawChannel>,
     servos: Arc<RawChannel>,
+    servo_bus: Arc<RawChannel>,
     power: Arc<RawChannel>,
+    estop_state: Arc<RawChannel>,
     diagnostics: Arc<RawChannel>,
-    camera_base: Arc<RawChannel>,
-    camera_wrist: Arc<RawChannel>,
+    safety_event: Arc<RawChannel>,
+    tf: Arc<Channel<FrameTransforms>>,
+    geofence: Arc<Channel<SceneUpdate>>,
+    imu: Arc<RawChannel>,
+    /// Keyed by camera name, one entry per camera in cameras.yaml.
+    cameras: HashMap<String, CameraChannels>,
     log_control: Arc<RawChannel>,
     log_status: Arc<RawChannel>,
 }
 
 impl Telemetry {
-    pub fn new(ctx: &Arc<Context>) -> Result<Self> {
+    pub fn new(ctx: &Arc<Context>, cameras: &CamerasConfig) -> Result<Self> {
+        let mut camera_channels = HashMap::new();
+        for camera in &cameras.cameras {
+            let topic = camera_topic(&camera.name);
+            let json = cameras
+                .json_frames
+                .then(|| build_json_channel(ctx, &format!("{topic}/json")))
+                .transpose()?;
+            let channels = CameraChannels {
+                image: build_schema_channel(ctx, &topic),
+                json,
+                stats: build_json_channel(ctx, &camera_stats_topic(&camera.name))?,
+                calibration: camera_calibration(camera).map(|calibration| {
+                    let topic = camera_calibration_topic(&camera.name);
+                    (build_schema_channel(ctx, &topic), calibration)
+                }),
+            };
+            camera_channels.insert(camera.name.clone(), channels);
+        }
         Ok(Self {
-            ctx: ctx.clone(),
             cmd_velocity: build_json_channel(ctx, TOPIC_CMD_VELOCITY)?,
+            cmd_wheels: build_json_channel(ctx, TOPIC_CMD_WHEELS)?,
             cmd_skill: build_json_channel(ctx, TOPIC_CMD_SKILL)?,
+            cmd_skill_cancel: build_json_channel(ctx, TOPIC_CMD_SKILL_CANCEL)?,
+            skill_status: build_json_channel(ctx, TOPIC_SKILL_STATUS)?,
             cmd_estop: build_json_channel(ctx, TOPIC_CMD_ESTOP)?,
+            cmd_estop_reset: build_json_channel(ctx, TOPIC_CMD_ESTOP_RESET)?,
+            cmd_speed_scale: build_json_channel(ctx, TOPIC_CMD_SPEED_SCALE)?,
+            cmd_set_pose: build_json_channel(ctx, TOPIC_CMD_SET_POSE)?,
             odometry: build_json_channel(ctx, TOPIC_STATE_ODOM)?,
+            pose_reset: build_json_channel(ctx, TOPIC_STATE_POSE_RESET)?,
             servos: build_json_channel(ctx, TOPIC_STATE_SERVOS)?,
+            servo_bus: build_json_channel(ctx, TOPIC_STATE_SERVO_BUS)?,
             power: build_json_channel(ctx, TOPIC_STATE_POWER)?,
+            estop_state: build_json_channel(ctx, TOPIC_STATE_ESTOP)?,
             diagnostics: build_json_channel(ctx, TOPIC_SYSTEM_DIAG)?,
-            camera_base: build_json_channel(ctx, TOPIC_CAMERA_BASE)?,
-            camera_wrist: build_json_channel(ctx, TOPIC_CAMERA_WRIST)?,
+            safety_event: build_json_channel(ctx, TOPIC_SAFETY_EVENT)?,
+            tf: build_schema_channel(ctx, TOPIC_TF),
+            geofence: build_schema_channel(ctx, TOPIC_SCENE_GEOFENCE),
+            imu: build_json_channel(ctx, TOPIC_SENSORS_IMU)?,
+            cameras: camera_channels,
             log_control: build_json_channel(ctx, TOPIC_LOG_CONTROL)?,
             log_status: build_json_channel(ctx, TOPIC_LOG_STATUS)?,
         })
     }
 
-    pub fn context(&self) -> Arc<Context> {
-        self.ctx.clone()
-    }
-
     pub fn log_cmd_velocity(&self, msg: &VelocityCommand) {
         log_json(&self.cmd_velocity, msg, msg.timestamp_ns);
     }
 
+    pub fn log_wheel_targets(&self, msg: &WheelTargetArray) {
+        log_json(&self.cmd_wheels, msg, msg.timestamp_ns);
+    }
+
     pub fn log_cmd_skill(&self, msg: &SkillCommand) {
         log_json(&self.cmd_skill, msg, msg.timestamp_ns);
     }
 
+    pub fn log_cmd_skill_cancel(&self, msg: &SkillCancel) {
+        log_json(&self.cmd_skill_cancel, msg, msg.timestamp_ns);
+    }
+
+    pub fn log_skill_status(&self, msg: &SkillStatus) {
+        log_json(&self.skill_status, msg, msg.timestamp_ns);
+    }
+
     pub fn log_cmd_estop(&self, msg: &EstopCommand) {
         log_json(&self.cmd_estop, msg, msg.timestamp_ns);
     }
 
+    pub fn log_cmd_estop_reset(&self, msg: &EstopReset) {
+        log_json(&self.cmd_estop_reset, msg, msg.timestamp_ns);
+    }
+
+    pub fn log_cmd_speed_scale(&self, msg: &SpeedScaleCommand) {
+        log_json(&self.cmd_speed_scale, msg, msg.timestamp_ns);
+    }
+
+    pub fn log_cmd_set_pose(&self, msg: &SetPoseCommand) {
+        log_json(&self.cmd_set_pose, msg, msg.timestamp_ns);
+    }
+
     pub fn log_odometry(&self, msg: &Odometry) {
         log_json(&self.odometry, msg, msg.timestamp_ns);
     }
 
+    pub fn log_pose_reset(&self, msg: &PoseReset) {
+        log_json(&self.pose_reset, msg, msg.timestamp_ns);
+    }
+
     pub fn log_servo_state(&self, msg: &ServoStateArray) {
         log_json(&self.servos, msg, msg.timestamp_ns);
     }
 
+    pub fn log_servo_bus_health(&self, msg: &ServoBusHealth) {
+        log_json(&self.servo_bus, msg, msg.timestamp_ns);
+    }
+
     pub fn log_power_state(&self, msg: &PowerState) {
         log_json(&self.power, msg, msg.timestamp_ns);
     }
 
+    pub fn log_estop_state(&self, msg: &EstopState) {
+        log_json(&self.estop_state, msg, msg.timestamp_ns);
+    }
+
     pub fn log_diagnostics(&self, msg: &Diagnostics) {
         log_json(&self.diagnostics, msg, msg.timestamp_ns);
     }
 
+    pub fn log_safety_event(&self, msg: &SafetyEvent) {
+        log_json(&self.safety_event, msg, msg.timestamp_ns);
+    }
+
+    pub fn log_transforms(&self, msg: &FrameTransforms, timestamp_ns: u64) {
+        self.tf
+            .log_with_meta(msg, PartialMetadata::with_log_time(timestamp_ns));
+    }
+
+    pub fn log_geofence(&self, msg: &SceneUpdate, timestamp_ns: u64) {
+        self.geofence
+            .log_with_meta(msg, PartialMetadata::with_log_time(timestamp_ns));
+    }
+
+    pub fn log_imu(&self, msg: &Imu) {
+        log_json(&self.imu, msg, msg.timestamp_ns);
+    }
+
     pub fn log_camera_frame(&self, msg: &CameraFrame) {
-        let channel = if msg.camera_name == "wrist" {
-            &self.camera_wrist
-        } else {
-            &self.camera_base
+        let Some(channels) = self.cameras.get(&msg.camera_name) else {
+            tracing::warn!("No channel for camera {}", msg.camera_name);
+            return;
+        };
+        let image = CompressedImage {
+            timestamp: Some(foxglove_timestamp(msg.timestamp_ns)),
+            frame_id: msg.frame_id.clone(),
+            data: msg.data.clone(),
+            format: msg.encoding.clone(),
         };
-        log_json(channel, msg, msg.timestamp_ns);
+        channels
+            .image
+            .log_with_meta(&image, PartialMetadata::with_log_time(msg.timestamp_ns));
+        // Sent with every frame under the same timestamp, so panels and
+        // recordings always have intrinsics next to the image.
+        if let Some((channel, calibration)) = &channels.calibration {
+            let calibration = CameraCalibration {
+                timestamp: image.timestamp,
+                ..calibration.clone()
+            };
+            channel.log_with_meta(
+                &calibration,
+                PartialMetadata::with_log_time(msg.timestamp_ns),
+            );
+        }
+        if let Some(json) = &channels.json {
+            log_json(json, msg, msg.timestamp_ns);
+        }
+    }
+
+    pub fn log_camera_stats(&self, msg: &CameraStats) {
+        if let Some(channels) = self.cameras.get(&msg.camera_name) {
+            log_json(&channels.stats, msg, msg.timestamp_ns);
+        }
     }
 
     pub fn log_log_control(&self, msg: &LogControl) {
@@ -105,6 +241,46 @@ impl Telemetry {
     }
 }
 
+/// Image topic for a camera in cameras.yaml, e.g. `/sensors/camera/wrist`.
+pub fn camera_topic(name: &str) -> String {
+    format!("/sensors/camera/{name}")
+}
+
+pub fn camera_stats_topic(name: &str) -> String {
+    format!("{}/stats", camera_topic(name))
+}
+
+pub fn camera_calibration_topic(name: &str) -> String {
+    format!("{}/calibration", camera_topic(name))
+}
+
+/// CameraCalibration for a camera with intrinsics; the timestamp is filled
+/// in per frame.
+fn camera_calibration(camera: &CameraConfig) -> Option<CameraCalibration> {
+    let intrinsics = camera.calibration.as_ref()?;
+    Some(CameraCalibration {
+        timestamp: None,
+        frame_id: camera.frame_id(),
+        width: intrinsics.width,
+        height: intrinsics.height,
+        distortion_model: intrinsics.distortion_model.as_str().to_string(),
+        d: intrinsics.d.clone(),
+        k: intrinsics.k.to_vec(),
+        r: intrinsics.rectification().to_vec(),
+        p: intrinsics.projection().to_vec(),
+    })
+}
+
+#[derive(Clone)]
+struct CameraChannels {
+    image: Arc<Channel<CompressedImage>>,
+    /// Legacy JSON frames, only with `json_frames` set in cameras.yaml.
+    json: Option<Arc<RawChannel>>,
+    stats: Arc<RawChannel>,
+    /// Only for cameras with intrinsics in cameras.yaml.
+    calibration: Option<(Arc<Channel<CameraCalibration>>, CameraCalibration)>,
+}
+
 fn build_json_channel(ctx: &Arc<Context>, topic: &str) -> Result<Arc<RawChannel>> {
     let channel = ChannelBuilder::new(topic)
         .context(ctx)
@@ -113,6 +289,12 @@ fn build_json_channel(ctx: &Arc<Context>, topic: &str) -> Result<Arc<RawChannel>
     Ok(channel)
 }
 
+/// Channel for a Foxglove well-known schema, so panels such as 3D pick the
+/// topic up without any JSON schema.
+fn build_schema_channel<T: Encode>(ctx: &Arc<Context>, topic: &str) -> Arc<Channel<T>> {
+    Arc::new(ChannelBuilder::new(topic).context(ctx).build::<T>())
+}
+
 fn log_json<T: Serialize>(channel: &RawChannel, msg: &T, timestamp_ns: u64) {
     match serde_json::to_vec(msg) {
         Ok(encoded) => {
@@ -123,4 +305,3 @@ fn log_json<T: Serialize>(channel: &RawChannel, msg: &T, timestamp_ns: u64) {
         }
     }
 }
-
@@ -1,8 +1,25 @@
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use foxglove::schemas::Timestamp;
+
 pub fn now_nanos() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap_or_default()
         .as_nanos() as u64
 }
+
+/// Nanoseconds since the epoch as a Foxglove schema timestamp.
+pub fn foxglove_timestamp(timestamp_ns: u64) -> Timestamp {
+    Timestamp::new(
+        (timestamp_ns / 1_000_000_000) as u32,
+        (timestamp_ns % 1_000_000_000) as u32,
+    )
+}
+
+/// Wraps an angle into [-pi, pi). Constant time, so a huge input cannot
+/// stall the caller.
+pub fn normalize_angle(theta: f64) -> f64 {
+    use std::f64::consts::PI;
+    (theta + PI).rem_euclid(2.0 * PI) - PI
+}